@@ -0,0 +1,579 @@
+//! The data the bootloader gives to the kernel. It is a header followed by typed tags, so the
+//! bootloader and the kernel can be built separately: the kernel checks the magic and the version
+//! of the header, then skips the tags it doesn't know.
+//!
+//! Layout: a [`HandoffHeader`], then the tags, each one starting on an 8-byte boundary with a
+//! [`TagHeader`] followed by its payload. The last tag is always [`tag_types::END`]. All the
+//! integers are little-endian.
+
+use crate::framebuffer::FramebufferData;
+
+/// "DOGBOOT" followed by a 0, read as a little-endian u64.
+pub const HANDOFF_MAGIC: u64 = u64::from_le_bytes(*b"DOGBOOT\0");
+/// Changed when the layout of the header or of an existing tag changes in a way that older kernels
+/// can't read. Such handoffs are rejected.
+pub const HANDOFF_MAJOR_VERSION: u16 = 1;
+/// Changed when tags are added, or when fields are added at the end of a tag. Older kernels can
+/// still read such handoffs.
+pub const HANDOFF_MINOR_VERSION: u16 = 4;
+/// The handoff can't be larger than a page.
+pub const HANDOFF_MAX_SIZE: usize = 0x1000;
+
+/// The types of the tags. Types that are not known by the kernel are skipped.
+pub mod tag_types {
+    /// The last tag, without a payload.
+    pub const END: u32 = 0;
+    /// [`crate::framebuffer::FramebufferData`]. Required.
+    pub const FRAMEBUFFER: u32 = 1;
+    /// [`super::MemoryMapTag`]. Required.
+    pub const MEMORY_MAP: u32 = 2;
+    /// [`super::CmdlineTag`].
+    pub const CMDLINE: u32 = 3;
+    /// [`super::InitrdTag`].
+    pub const INITRD: u32 = 4;
+    /// [`super::FirmwareTablesTag`].
+    pub const FIRMWARE_TABLES: u32 = 5;
+    /// [`super::KernelImageTag`].
+    pub const KERNEL_IMAGE: u32 = 6;
+    /// [`super::KernelStackTag`].
+    pub const KERNEL_STACK: u32 = 7;
+    /// [`super::PageTablesTag`].
+    pub const PAGE_TABLES: u32 = 8;
+    /// [`super::DirectMapTag`] (since 1.1).
+    pub const DIRECT_MAP: u32 = 9;
+    /// [`super::UefiRuntimeTag`] (since 1.2).
+    pub const UEFI_RUNTIME: u32 = 10;
+    /// [`super::ModulesTag`] (since 1.3).
+    pub const MODULES: u32 = 11;
+    /// [`super::BootTimestampsTag`] (since 1.4).
+    pub const BOOT_TIMESTAMPS: u32 = 12;
+}
+
+/// The stages of the bootloader that are timestamped, the indices in [`BootTimestampsTag::tsc`].
+/// Each stage is timestamped when it ends.
+pub mod boot_stages {
+    /// The bootloader was started.
+    pub const ENTRY: usize = 0;
+    /// `dog.cfg` was read.
+    pub const CONFIG_READ: usize = 1;
+    /// An entry was chosen, either by the user or after the timeout of the boot menu.
+    pub const ENTRY_CHOSEN: usize = 2;
+    /// The kernel was read and loaded (after the memory test, if there is one).
+    pub const KERNEL_READ: usize = 3;
+    /// The initrd and the modules were read.
+    pub const FILES_READ: usize = 4;
+    /// The graphics mode was set.
+    pub const GOP_SET: usize = 5;
+    /// The memory for the physical memory manager was allocated.
+    pub const PMM_ALLOCATED: usize = 6;
+    /// The page tables of the kernel were built.
+    pub const PAGING_SET: usize = 7;
+    /// ExitBootServices was called.
+    pub const BOOT_SERVICES_EXITED: usize = 8;
+    /// The number of stages.
+    pub const COUNT: usize = 9;
+
+    /// The names of the stages, for printing them.
+    pub const NAMES: [&str; COUNT] = [
+        "entry",
+        "config read",
+        "entry chosen",
+        "kernel read",
+        "files read",
+        "graphics mode set",
+        "PMM allocated",
+        "paging set",
+        "boot services exited",
+    ];
+}
+
+/// Size = 24 bytes.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct HandoffHeader {
+    /// Always [`HANDOFF_MAGIC`].
+    pub magic: u64,
+    pub major_version: u16,
+    pub minor_version: u16,
+    /// The size in bytes of the whole handoff, including this header and the end tag.
+    pub total_size: u32,
+    /// The number of tags, including the end tag.
+    pub num_tags: u32,
+    pub reserved: u32,
+}
+
+/// Size = 8 bytes.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct TagHeader {
+    /// One of the [`tag_types`] constants.
+    pub tag_type: u32,
+    /// The size in bytes of the tag, including this header, but not the padding after it.
+    pub size: u32,
+}
+
+/// A payload that can be read from a tag or written in one.
+///
+/// # Safety
+/// The type must be `#[repr(C)]` and any bit pattern must be a valid value for it (only integers).
+pub unsafe trait Tag: Sized {
+    /// One of the [`tag_types`] constants.
+    const TAG_TYPE: u32;
+}
+
+unsafe impl Tag for FramebufferData {
+    const TAG_TYPE: u32 = tag_types::FRAMEBUFFER;
+}
+
+/// The memory map, an array of [`crate::memory_map::MemoryMapEntry`]. The entries are sorted, don't
+/// overlap and the adjacent ones with the same type are merged.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryMapTag {
+    /// The virtual address of the map ([`crate::MEM_MAP_VIRTUAL_ADDRESS`]).
+    pub virt_addr: u64,
+    /// The size of the map in bytes.
+    pub size: u32,
+    /// The size of an entry in bytes.
+    pub entry_size: u32,
+}
+
+unsafe impl Tag for MemoryMapTag {
+    const TAG_TYPE: u32 = tag_types::MEMORY_MAP;
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CmdlineTag {
+    /// The virtual address of the command line ([`crate::CMDLINE_VIRTUAL_ADDRESS`]).
+    pub virt_addr: u64,
+    /// The size in bytes of the command line (it is not NUL-terminated).
+    pub size: u32,
+    pub reserved: u32,
+}
+
+unsafe impl Tag for CmdlineTag {
+    const TAG_TYPE: u32 = tag_types::CMDLINE;
+}
+
+/// Only present if there is an initial ramdisk.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct InitrdTag {
+    /// The virtual address of the initrd ([`crate::INITRD_VIRTUAL_ADDRESS`]).
+    pub virt_addr: u64,
+    /// The size in bytes of the initrd.
+    pub size: u64,
+}
+
+unsafe impl Tag for InitrdTag {
+    const TAG_TYPE: u32 = tag_types::INITRD;
+}
+
+/// The physical addresses of the firmware tables, 0 for the ones that were not found.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FirmwareTablesTag {
+    pub acpi_rsdp_phys_addr: u64,
+    pub smbios_phys_addr: u64,
+    /// The EFI Runtime Services table.
+    pub uefi_rs_phys_addr: u64,
+}
+
+unsafe impl Tag for FirmwareTablesTag {
+    const TAG_TYPE: u32 = tag_types::FIRMWARE_TABLES;
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct KernelImageTag {
+    /// The virtual address where the first page of the kernel is mapped.
+    pub virt_base: u64,
+    /// The difference between the address where the kernel is mapped and its link address.
+    pub slide: u64,
+}
+
+unsafe impl Tag for KernelImageTag {
+    const TAG_TYPE: u32 = tag_types::KERNEL_IMAGE;
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct KernelStackTag {
+    /// The lowest address of the kernel stack.
+    pub bottom: u64,
+    /// The address right above the kernel stack.
+    pub top: u64,
+}
+
+unsafe impl Tag for KernelStackTag {
+    const TAG_TYPE: u32 = tag_types::KERNEL_STACK;
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct PageTablesTag {
+    /// The number of entries in the page table.
+    pub num_entries: u64,
+}
+
+unsafe impl Tag for PageTablesTag {
+    const TAG_TYPE: u32 = tag_types::PAGE_TABLES;
+}
+
+/// The direct map of the physical memory.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct DirectMapTag {
+    /// The virtual address where physical address 0 is mapped ([`crate::PHYS_DIRECT_MAP_ADDRESS`]).
+    pub offset: u64,
+    /// The physical address right above the highest RAM page in the map.
+    pub size: u64,
+}
+
+unsafe impl Tag for DirectMapTag {
+    const TAG_TYPE: u32 = tag_types::DIRECT_MAP;
+}
+
+/// Only present if the runtime services were remapped with SetVirtualAddressMap.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UefiRuntimeTag {
+    /// The virtual address of the EFI Runtime Services table.
+    pub runtime_services_virt_addr: u64,
+    /// The virtual address of the EFI System Table.
+    pub system_table_virt_addr: u64,
+}
+
+unsafe impl Tag for UefiRuntimeTag {
+    const TAG_TYPE: u32 = tag_types::UEFI_RUNTIME;
+}
+
+/// Only present if modules were loaded.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ModulesTag {
+    /// The virtual address of the table of modules, an array of
+    /// [`crate::modules::ModuleEntry`] ([`crate::MODULES_VIRTUAL_ADDRESS`]).
+    pub table_virt_addr: u64,
+    /// The number of entries in the table.
+    pub count: u32,
+    /// The size of an entry in bytes.
+    pub entry_size: u32,
+}
+
+unsafe impl Tag for ModulesTag {
+    const TAG_TYPE: u32 = tag_types::MODULES;
+}
+
+/// The value of the time stamp counter at the end of each of the [`boot_stages`], 0 for the stages
+/// that were not timestamped. The TSC is not calibrated by the bootloader, so only the kernel can
+/// convert these to time.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct BootTimestampsTag {
+    pub tsc: [u64; boot_stages::COUNT],
+}
+
+unsafe impl Tag for BootTimestampsTag {
+    const TAG_TYPE: u32 = tag_types::BOOT_TIMESTAMPS;
+}
+
+/// Why a handoff was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandoffError {
+    NullPointer,
+    /// The magic is wrong, so the kernel was probably started by another bootloader.
+    BadMagic,
+    /// The handoff was made for another major version of the format.
+    IncompatibleVersion {
+        major: u16,
+        minor: u16,
+    },
+    /// The total size is smaller than the header or larger than [`HANDOFF_MAX_SIZE`].
+    BadSize,
+    /// A tag goes past the end of the handoff, or there is no end tag.
+    MalformedTag,
+    /// A required tag (one of the [`tag_types`] constants) is missing.
+    MissingTag(u32),
+}
+
+/// A handoff that was validated, so its tags can be read safely.
+pub struct Handoff<'a> {
+    data: &'a [u8],
+    minor_version: u16,
+}
+
+impl<'a> Handoff<'a> {
+    /// Validates the handoff at the given address: the header and the bounds of all its tags.
+    ///
+    /// # Safety
+    /// `addr` must be null or point to memory that is readable for [`HANDOFF_MAX_SIZE`] bytes, or
+    /// for the size in the header if that is smaller, and stays valid for `'a`.
+    pub unsafe fn from_ptr(addr: *const u8) -> Result<Self, HandoffError> {
+        if addr.is_null() {
+            return Err(HandoffError::NullPointer);
+        }
+
+        let header: HandoffHeader = unsafe { (addr as *const HandoffHeader).read_unaligned() };
+        if header.magic != HANDOFF_MAGIC {
+            return Err(HandoffError::BadMagic);
+        }
+
+        let total_size: usize = header.total_size as usize;
+        if total_size < size_of::<HandoffHeader>() || total_size > HANDOFF_MAX_SIZE {
+            return Err(HandoffError::BadSize);
+        }
+
+        let data: &'a [u8] = unsafe { core::slice::from_raw_parts(addr, total_size) };
+        return Self::from_bytes(data);
+    }
+
+    /// Validates a handoff that was already read in memory.
+    pub fn from_bytes(data: &'a [u8]) -> Result<Self, HandoffError> {
+        if data.len() < size_of::<HandoffHeader>() {
+            return Err(HandoffError::BadSize);
+        }
+
+        let header: HandoffHeader =
+            unsafe { (data.as_ptr() as *const HandoffHeader).read_unaligned() };
+        if header.magic != HANDOFF_MAGIC {
+            return Err(HandoffError::BadMagic);
+        }
+
+        if header.major_version != HANDOFF_MAJOR_VERSION {
+            return Err(HandoffError::IncompatibleVersion {
+                major: header.major_version,
+                minor: header.minor_version,
+            });
+        }
+
+        let total_size: usize = header.total_size as usize;
+        if total_size < size_of::<HandoffHeader>()
+            || total_size > HANDOFF_MAX_SIZE
+            || total_size > data.len()
+        {
+            return Err(HandoffError::BadSize);
+        }
+
+        let data: &'a [u8] = &data[..total_size];
+
+        //walk through all the tags, so the iterator doesn't have to check anything
+        let mut offset: usize = size_of::<HandoffHeader>();
+        loop {
+            let tag: Option<(TagHeader, usize)> = read_tag_header(data, offset);
+            if tag.is_none() {
+                return Err(HandoffError::MalformedTag);
+            }
+
+            let (tag, next_offset): (TagHeader, usize) = tag.unwrap();
+            if tag.tag_type == tag_types::END {
+                break;
+            }
+
+            offset = next_offset;
+        }
+
+        return Ok(Handoff {
+            data,
+            minor_version: header.minor_version,
+        });
+    }
+
+    /// The minor version of the format the bootloader used. It can be newer than
+    /// [`HANDOFF_MINOR_VERSION`].
+    pub fn minor_version(&self) -> u16 {
+        self.minor_version
+    }
+
+    /// Returns all the tags, in order, without the end tag.
+    pub fn tags(&self) -> TagIter<'a> {
+        TagIter {
+            data: self.data,
+            offset: size_of::<HandoffHeader>(),
+        }
+    }
+
+    /// Returns the payload of the first tag of the given type. Tags that are too small for the
+    /// payload (from an older format) are ignored.
+    pub fn find<T: Tag>(&self) -> Option<T> {
+        self.tags()
+            .filter(|tag| tag.tag_type() == T::TAG_TYPE)
+            .find_map(|tag| tag.read::<T>())
+    }
+}
+
+/// A tag of a validated handoff.
+pub struct RawTag<'a> {
+    tag_type: u32,
+    payload: &'a [u8],
+}
+
+impl<'a> RawTag<'a> {
+    /// One of the [`tag_types`] constants, or an unknown type from a newer bootloader.
+    pub fn tag_type(&self) -> u32 {
+        self.tag_type
+    }
+
+    /// The bytes after the tag header.
+    pub fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+
+    /// Reads the payload as `T`. Returns None if the tag has another type or if it's too small. If
+    /// it is larger (a newer format), the extra bytes are ignored.
+    pub fn read<T: Tag>(&self) -> Option<T> {
+        if self.tag_type != T::TAG_TYPE || self.payload.len() < size_of::<T>() {
+            return None;
+        }
+
+        return Some(unsafe { (self.payload.as_ptr() as *const T).read_unaligned() });
+    }
+}
+
+pub struct TagIter<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Iterator for TagIter<'a> {
+    type Item = RawTag<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (tag, next_offset): (TagHeader, usize) = read_tag_header(self.data, self.offset)?;
+        if tag.tag_type == tag_types::END {
+            return None;
+        }
+
+        let payload_start: usize = self.offset + size_of::<TagHeader>();
+        let payload: &'a [u8] = &self.data[payload_start..self.offset + tag.size as usize];
+        self.offset = next_offset;
+
+        return Some(RawTag {
+            tag_type: tag.tag_type,
+            payload,
+        });
+    }
+}
+
+/// Reads the tag header at the given offset and returns it with the offset of the next tag. Returns
+/// None if the tag doesn't fit in the data.
+fn read_tag_header(data: &[u8], offset: usize) -> Option<(TagHeader, usize)> {
+    if !offset.is_multiple_of(8) || offset + size_of::<TagHeader>() > data.len() {
+        return None;
+    }
+
+    let tag: TagHeader =
+        unsafe { (data.as_ptr().add(offset) as *const TagHeader).read_unaligned() };
+    let size: usize = tag.size as usize;
+    if size < size_of::<TagHeader>() || offset + size > data.len() {
+        return None;
+    }
+
+    return Some((tag, (offset + size).next_multiple_of(8)));
+}
+
+/// Writes a handoff in a buffer: the header is written by [`HandoffWriter::finish`], after all the
+/// tags were added.
+pub struct HandoffWriter<'a> {
+    buffer: &'a mut [u8],
+    offset: usize,
+    num_tags: u32,
+}
+
+impl<'a> HandoffWriter<'a> {
+    /// Returns None if the buffer is not 8-byte aligned or can't even hold the header and the end
+    /// tag.
+    pub fn new(buffer: &'a mut [u8]) -> Option<Self> {
+        if !(buffer.as_ptr() as usize).is_multiple_of(8)
+            || buffer.len() < size_of::<HandoffHeader>() + size_of::<TagHeader>()
+        {
+            return None;
+        }
+
+        let buffer_len: usize = buffer.len().min(HANDOFF_MAX_SIZE);
+        return Some(HandoffWriter {
+            buffer: &mut buffer[..buffer_len],
+            offset: size_of::<HandoffHeader>(),
+            num_tags: 0,
+        });
+    }
+
+    /// Adds a tag. Returns false if there is no room left for it (and the end tag).
+    pub fn push<T: Tag>(&mut self, payload: &T) -> bool {
+        let payload_start: Option<usize> = self.add_tag(T::TAG_TYPE, size_of::<T>());
+        if payload_start.is_none() {
+            return false;
+        }
+
+        //copy the bytes as they are, the padding of T must not be read as u8
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                payload as *const T as *const u8,
+                self.buffer.as_mut_ptr().add(payload_start.unwrap()),
+                size_of::<T>(),
+            );
+        }
+
+        return true;
+    }
+
+    /// Adds a tag with the given type and payload. Returns false if there is no room left for it
+    /// (and the end tag).
+    pub fn push_raw(&mut self, tag_type: u32, payload: &[u8]) -> bool {
+        let payload_start: Option<usize> = self.add_tag(tag_type, payload.len());
+        if payload_start.is_none() {
+            return false;
+        }
+
+        let payload_start: usize = payload_start.unwrap();
+        self.buffer[payload_start..payload_start + payload.len()].copy_from_slice(payload);
+        return true;
+    }
+
+    /// Writes the header of a new tag and zeroes its payload and padding. Returns the offset of
+    /// the payload, or None if there is no room left for the tag (and the end tag).
+    fn add_tag(&mut self, tag_type: u32, payload_size: usize) -> Option<usize> {
+        let size: usize = size_of::<TagHeader>() + payload_size;
+        let next_offset: usize = (self.offset + size).next_multiple_of(8);
+        if next_offset + size_of::<TagHeader>() > self.buffer.len() {
+            return None;
+        }
+
+        self.write_tag_header(tag_type, size as u32);
+        let payload_start: usize = self.offset + size_of::<TagHeader>();
+        self.buffer[payload_start..next_offset].fill(0);
+
+        self.offset = next_offset;
+        self.num_tags += 1;
+        return Some(payload_start);
+    }
+
+    /// Writes the end tag and the header. Returns the total size of the handoff.
+    pub fn finish(mut self) -> usize {
+        //there is always room for the end tag, push doesn't take it
+        self.write_tag_header(tag_types::END, size_of::<TagHeader>() as u32);
+        let total_size: usize = self.offset + size_of::<TagHeader>();
+
+        let header: HandoffHeader = HandoffHeader {
+            magic: HANDOFF_MAGIC,
+            major_version: HANDOFF_MAJOR_VERSION,
+            minor_version: HANDOFF_MINOR_VERSION,
+            total_size: total_size as u32,
+            num_tags: self.num_tags + 1,
+            reserved: 0,
+        };
+        unsafe {
+            (self.buffer.as_mut_ptr() as *mut HandoffHeader).write_unaligned(header);
+        }
+
+        return total_size;
+    }
+
+    fn write_tag_header(&mut self, tag_type: u32, size: u32) {
+        let tag: TagHeader = TagHeader { tag_type, size };
+        unsafe {
+            (self.buffer.as_mut_ptr().add(self.offset) as *mut TagHeader).write_unaligned(tag);
+        }
+    }
+}
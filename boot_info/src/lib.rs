@@ -1,15 +1,73 @@
 #![no_std]
 
 pub mod framebuffer;
+pub mod handoff;
 pub mod memory_map;
+pub mod modules;
 
-pub const KERNEL_VIRTUAL_ADDRESS: u64 = 0xffff_eeee_8000_0000;
+use handoff::{
+    BootTimestampsTag, CmdlineTag, DirectMapTag, FirmwareTablesTag, Handoff, HandoffError,
+    InitrdTag, KernelImageTag, KernelStackTag, MemoryMapTag, ModulesTag, PageTablesTag,
+    UefiRuntimeTag,
+};
+
+/// The lowest address of the kernel image (the start of the last 2 GiB, the link address in
+/// kernel/src/boot/linker.ld). With KASLR, the kernel is moved at a random address between this
+/// and [`KERNEL_VIRTUAL_END`], see [`KParams::kernel_slide`].
+pub const KERNEL_VIRTUAL_ADDRESS: u64 = 0xffff_ffff_8000_0000;
+/// The end of the range where a relocatable kernel can be placed.
+pub const KERNEL_VIRTUAL_END: u64 = 0xffff_ffff_c000_0000;
 pub const GOP_VIRTUAL_ADDRESS: u64 = 0xffff_eeed_f000_0000;
 pub const MEM_MAP_VIRTUAL_ADDRESS: u64 = 0xffff_eeed_e000_0000;
 /// Can theoretically use up to 0xffff_fffe_e000_0000, but should cap at 0xffff_fffe_8000_0000
 pub const PHYS_BITMAP_MANAGER_ADDRESS: u64 = 0xffff_eeed_0000_0000;
 /// The virtual address for the page tables themselves.
 pub const PAGE_TABLES_ADDRESS: u64 = 0xffff_eeec_f000_0000;
+/// The virtual address of the kernel command line (a single page, not NUL-terminated, see
+/// [`KParams::cmdline_size`]).
+pub const CMDLINE_VIRTUAL_ADDRESS: u64 = 0xffff_eeed_d000_0000;
+/// The maximum size of the kernel command line in bytes.
+pub const CMDLINE_MAX_SIZE: u32 = 0x1000;
+/// The virtual address of the initial ramdisk (read-only), see [`KParams::initrd_addr`].
+pub const INITRD_VIRTUAL_ADDRESS: u64 = 0xffff_eeed_c000_0000;
+/// The maximum size of the initial ramdisk in bytes (256 MiB, up to [`CMDLINE_VIRTUAL_ADDRESS`]).
+pub const INITRD_MAX_SIZE: u64 = 0x1000_0000;
+/// The virtual address of the guard page of the kernel stack. It is never mapped, so a stack
+/// overflow causes a page fault instead of silently overwriting other memory. The stack starts right
+/// above it, see [`KParams::kernel_stack_bottom`].
+pub const KERNEL_STACK_GUARD_ADDRESS: u64 = 0xffff_eeed_b000_0000;
+/// The maximum size of the kernel stack in bytes (16 MiB, up to [`INITRD_VIRTUAL_ADDRESS`]).
+pub const KERNEL_STACK_MAX_SIZE: u64 = 0x100_0000;
+/// The virtual address of the table of modules (a single page, see [`KParams::modules_table_addr`]).
+/// The modules themselves are mapped right after it, one after another, each one starting on a new
+/// page.
+pub const MODULES_VIRTUAL_ADDRESS: u64 = 0xffff_eeed_8000_0000;
+/// The maximum size of the table and all the modules in bytes (768 MiB, up to
+/// [`KERNEL_STACK_GUARD_ADDRESS`]).
+pub const MODULES_MAX_SIZE: u64 = 0x3000_0000;
+
+/// The memory used by the UEFI runtime services is mapped in this window (the regions one after
+/// another), and the firmware was told so with SetVirtualAddressMap, see
+/// [`KParams::uefi_rs_virt_addr`].
+pub const UEFI_RUNTIME_VIRTUAL_ADDRESS: u64 = 0xffff_eeeb_0000_0000;
+/// The maximum size of the runtime services window (4 GiB, up to [`PAGE_TABLES_ADDRESS`]).
+pub const UEFI_RUNTIME_MAX_SIZE: u64 = 0x1_0000_0000;
+/// All the physical RAM is mapped at this offset (physical address 0 is mapped here), so the kernel
+/// can reach any frame, see [`KParams::phys_direct_map_size`]. MMIO and reserved regions are not
+/// part of it.
+pub const PHYS_DIRECT_MAP_ADDRESS: u64 = 0xffff_8000_0000_0000;
+/// The largest physical address that can be in the direct map (64 TiB, up to [`K_HEAP_START`]).
+pub const PHYS_DIRECT_MAP_MAX_SIZE: u64 = 0x4000_0000_0000;
+
+/// The vendor GUID of the UEFI variables of the bootloader (in the byte order of EFI_GUID):
+/// a4c1e7b2-3f58-4d96-9b0e-5c27d81f6a43.
+pub const BOOTLOADER_VENDOR_GUID: [u8; 16] = [
+    0xb2, 0xe7, 0xc1, 0xa4, 0x58, 0x3f, 0x96, 0x4d, 0x9b, 0x0e, 0x5c, 0x27, 0xd8, 0x1f, 0x6a, 0x43,
+];
+/// The UEFI variable (a little-endian u32) that counts the boots of the default entry since the
+/// kernel last booted successfully. The bootloader increments it and switches to the fallback entry
+/// after too many attempts, the kernel deletes it once it booted.
+pub const BOOT_ATTEMPTS_VARIABLE_NAME: &str = "DogBootAttempts";
 
 /// This is the **theoretical** heap limit of the kernel (the max virtual address). In reality,
 /// the kernel uses way less memory for its heap.
@@ -17,13 +75,113 @@ pub const K_HEAP_END: u64 = 0xffff_ee00_0000_0000;
 /// This is the start of the kernel heap. It grows upwards towards [`K_HEAP_END`].
 pub const K_HEAP_START: u64 = 0xffff_e000_0000_0000;
 
-#[repr(C)]
+/// The boot parameters of the kernel, read from the tags of the [`handoff`] given by the bootloader
+/// with [`KParams::from_handoff`]. The values of the optional tags that are missing are 0.
 pub struct KParams {
     pub fb_data: framebuffer::FramebufferData,
     pub memory_map_size: u32,
     /// The number of entries in the page table.
     pub page_table_num_entries: u64,
-    /// The physical address of the EFI Runtime Services table. Interpret this to interact with the
-    /// system using UEFI.
+    /// The physical address of the EFI Runtime Services table. Only usable if
+    /// [`KParams::uefi_rs_virt_addr`] is 0, otherwise the firmware expects virtual addresses.
     pub uefi_rs_phys_addr: u64,
+    /// The virtual address of the EFI Runtime Services table, in the window at
+    /// [`UEFI_RUNTIME_VIRTUAL_ADDRESS`]. Interpret this to interact with the system using UEFI. It
+    /// is 0 if the bootloader couldn't call SetVirtualAddressMap.
+    pub uefi_rs_virt_addr: u64,
+    /// The virtual address of the EFI System Table, 0 if the runtime services were not remapped.
+    pub uefi_system_table_virt_addr: u64,
+    /// The size in bytes of the kernel command line found at [`CMDLINE_VIRTUAL_ADDRESS`]. It is 0
+    /// if there is no command line.
+    pub cmdline_size: u32,
+    /// The virtual address where the first page of the kernel is mapped.
+    pub kernel_virt_base: u64,
+    /// The difference between the address where the kernel is mapped and its link address (it is
+    /// 0 if the kernel was not moved). It wraps around, so the kernel can also be moved downwards.
+    pub kernel_slide: u64,
+    /// The virtual address of the initial ramdisk ([`INITRD_VIRTUAL_ADDRESS`]), or 0 if there is
+    /// no initrd.
+    pub initrd_addr: u64,
+    /// The size in bytes of the initial ramdisk, 0 if there is none.
+    pub initrd_size: u64,
+    /// The virtual address of the table of modules ([`MODULES_VIRTUAL_ADDRESS`]), or 0 if no
+    /// modules were loaded.
+    pub modules_table_addr: u64,
+    /// The number of entries in the table of modules.
+    pub modules_count: u32,
+    /// The physical address of the ACPI RSDP (revision 2 or later if the firmware has it), 0 if
+    /// there is none. The memory of the ACPI tables is in the memory map as ACPI reclaimable (or
+    /// ACPI NVS) and identity-mapped.
+    pub acpi_rsdp_phys_addr: u64,
+    /// The physical address of the SMBIOS entry point (`_SM3_` if the firmware has it, `_SM_`
+    /// otherwise), 0 if there is none. It is identity-mapped and not reused by the memory map.
+    pub smbios_phys_addr: u64,
+    /// The lowest address of the kernel stack (the page below it is the unmapped guard page at
+    /// [`KERNEL_STACK_GUARD_ADDRESS`]).
+    pub kernel_stack_bottom: u64,
+    /// The address right above the kernel stack. The kernel is entered with RSP at this address
+    /// minus 8 (a 0 return address is pushed, as if the entry point was called).
+    pub kernel_stack_top: u64,
+    /// The virtual address where the physical memory is mapped ([`PHYS_DIRECT_MAP_ADDRESS`]), 0 if
+    /// it is not mapped.
+    pub phys_direct_map_offset: u64,
+    /// The physical address right above the highest RAM page in the direct map.
+    pub phys_direct_map_size: u64,
+    /// The value of the time stamp counter at the end of each of the [`handoff::boot_stages`] of
+    /// the bootloader, all 0 if the bootloader didn't give them.
+    pub boot_tsc_timestamps: [u64; handoff::boot_stages::COUNT],
+}
+
+impl KParams {
+    /// Reads the boot parameters from a validated handoff. The framebuffer and the memory map are
+    /// required, all the other tags are optional.
+    pub fn from_handoff(handoff: &Handoff) -> Result<Self, HandoffError> {
+        let fb_data: Option<framebuffer::FramebufferData> = handoff.find();
+        if fb_data.is_none() {
+            return Err(HandoffError::MissingTag(handoff::tag_types::FRAMEBUFFER));
+        }
+
+        let memory_map: Option<MemoryMapTag> = handoff.find();
+        if memory_map.is_none() {
+            return Err(HandoffError::MissingTag(handoff::tag_types::MEMORY_MAP));
+        }
+
+        let page_tables: Option<PageTablesTag> = handoff.find();
+        let cmdline: Option<CmdlineTag> = handoff.find();
+        let kernel_image: Option<KernelImageTag> = handoff.find();
+        let initrd: Option<InitrdTag> = handoff.find();
+        let firmware_tables: Option<FirmwareTablesTag> = handoff.find();
+        let kernel_stack: Option<KernelStackTag> = handoff.find();
+        let direct_map: Option<DirectMapTag> = handoff.find();
+        let uefi_runtime: Option<UefiRuntimeTag> = handoff.find();
+        let boot_timestamps: Option<BootTimestampsTag> = handoff.find();
+        //an entry of another size would be read wrong, so such a table is ignored
+        let modules: Option<ModulesTag> = handoff
+            .find::<ModulesTag>()
+            .filter(|x| x.entry_size as usize == size_of::<modules::ModuleEntry>());
+
+        return Ok(KParams {
+            fb_data: fb_data.unwrap(),
+            memory_map_size: memory_map.unwrap().size,
+            page_table_num_entries: page_tables.map_or(0, |x| x.num_entries),
+            uefi_rs_phys_addr: firmware_tables.map_or(0, |x| x.uefi_rs_phys_addr),
+            uefi_rs_virt_addr: uefi_runtime.map_or(0, |x| x.runtime_services_virt_addr),
+            uefi_system_table_virt_addr: uefi_runtime.map_or(0, |x| x.system_table_virt_addr),
+            cmdline_size: cmdline.map_or(0, |x| x.size),
+            kernel_virt_base: kernel_image.map_or(0, |x| x.virt_base),
+            kernel_slide: kernel_image.map_or(0, |x| x.slide),
+            initrd_addr: initrd.map_or(0, |x| x.virt_addr),
+            initrd_size: initrd.map_or(0, |x| x.size),
+            modules_table_addr: modules.map_or(0, |x| x.table_virt_addr),
+            modules_count: modules.map_or(0, |x| x.count),
+            acpi_rsdp_phys_addr: firmware_tables.map_or(0, |x| x.acpi_rsdp_phys_addr),
+            smbios_phys_addr: firmware_tables.map_or(0, |x| x.smbios_phys_addr),
+            kernel_stack_bottom: kernel_stack.map_or(0, |x| x.bottom),
+            kernel_stack_top: kernel_stack.map_or(0, |x| x.top),
+            phys_direct_map_offset: direct_map.map_or(0, |x| x.offset),
+            phys_direct_map_size: direct_map.map_or(0, |x| x.size),
+            boot_tsc_timestamps: boot_timestamps
+                .map_or([0; handoff::boot_stages::COUNT], |x| x.tsc),
+        });
+    }
 }
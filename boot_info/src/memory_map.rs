@@ -88,6 +88,35 @@ pub enum MemoryType {
     PersistentMemory = 14,
     /// Memory that must be accepted by the boot target before it can be used.
     Unaccepted = 15,
+    /// Memory the bootloader used for itself (and the boot handoff). Free once the kernel runs.
+    BootloaderReclaimable = 16,
+    /// The loaded segments of the kernel.
+    KernelImage = 17,
+    /// The stack the kernel was started on.
+    KernelStack = 18,
+    /// The page tables built by the bootloader, still in use by the kernel.
+    PageTables = 19,
+    /// The bitmaps of the physical memory manager.
+    PmmBitmap = 20,
+    /// The data the kernel reads from its fixed addresses: this memory map, the command line and
+    /// the initrd.
+    BootData = 21,
+    /// The framebuffer of the display.
+    Framebuffer = 22,
+}
+
+impl MemoryType {
+    /// Returns true if the memory can be given out by the physical memory manager as soon as the
+    /// kernel starts.
+    pub fn is_free_after_boot(&self) -> bool {
+        return matches!(
+            self,
+            MemoryType::Conventional
+                | MemoryType::BootServicesCode
+                | MemoryType::BootServicesData
+                | MemoryType::BootloaderReclaimable
+        );
+    }
 }
 
 impl From<u32> for MemoryType {
@@ -109,6 +138,13 @@ impl From<u32> for MemoryType {
             13 => {MemoryType::PalCode}
             14 => {MemoryType::PersistentMemory}
             15 => {MemoryType::Unaccepted}
+            16 => {MemoryType::BootloaderReclaimable}
+            17 => {MemoryType::KernelImage}
+            18 => {MemoryType::KernelStack}
+            19 => {MemoryType::PageTables}
+            20 => {MemoryType::PmmBitmap}
+            21 => {MemoryType::BootData}
+            22 => {MemoryType::Framebuffer}
             _ => {MemoryType::Unusable} 
         }
     }
@@ -0,0 +1,62 @@
+/// The maximum number of modules (the table fills exactly one page).
+pub const MAX_MODULES: usize = 16;
+/// The maximum size in bytes of the name of a module.
+pub const MODULE_NAME_MAX_SIZE: usize = 32;
+/// The maximum size in bytes of the command line of a module.
+pub const MODULE_CMDLINE_MAX_SIZE: usize = 200;
+
+///Size = 256 bytes.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ModuleEntry {
+    /// The virtual address of the contents of the module (page-aligned, read-only).
+    virt_addr: u64,
+    /// The size of the module in bytes.
+    size: u64,
+    name_size: u32,
+    cmdline_size: u32,
+    name: [u8; MODULE_NAME_MAX_SIZE],
+    cmdline: [u8; MODULE_CMDLINE_MAX_SIZE],
+}
+
+impl ModuleEntry {
+    pub fn virt_addr(&self) -> u64 {
+        self.virt_addr
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The name the module is looked up by. Empty if the entry is invalid.
+    pub fn name(&self) -> &str {
+        let size: usize = (self.name_size as usize).min(MODULE_NAME_MAX_SIZE);
+        core::str::from_utf8(&self.name[..size]).unwrap_or("")
+    }
+
+    /// The command line of the module, can be empty.
+    pub fn cmdline(&self) -> &str {
+        let size: usize = (self.cmdline_size as usize).min(MODULE_CMDLINE_MAX_SIZE);
+        core::str::from_utf8(&self.cmdline[..size]).unwrap_or("")
+    }
+
+    /// Returns None if the name or the command line is too long.
+    pub fn new(virt_addr: u64, size: u64, name: &str, cmdline: &str) -> Option<Self> {
+        if name.len() > MODULE_NAME_MAX_SIZE || cmdline.len() > MODULE_CMDLINE_MAX_SIZE {
+            return None;
+        }
+
+        let mut entry: ModuleEntry = ModuleEntry {
+            virt_addr,
+            size,
+            name_size: name.len() as u32,
+            cmdline_size: cmdline.len() as u32,
+            name: [0; MODULE_NAME_MAX_SIZE],
+            cmdline: [0; MODULE_CMDLINE_MAX_SIZE],
+        };
+        entry.name[..name.len()].copy_from_slice(name.as_bytes());
+        entry.cmdline[..cmdline.len()].copy_from_slice(cmdline.as_bytes());
+
+        return Some(entry);
+    }
+}
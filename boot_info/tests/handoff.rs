@@ -0,0 +1,235 @@
+use boot_info::handoff::{
+    CmdlineTag, HANDOFF_MAJOR_VERSION, HANDOFF_MINOR_VERSION, Handoff, HandoffError, HandoffHeader,
+    HandoffWriter, MemoryMapTag, RawTag, tag_types,
+};
+
+/// The writer needs an 8-byte aligned buffer.
+#[repr(C, align(8))]
+struct Buffer([u8; 0x1000]);
+
+const MEMORY_MAP: MemoryMapTag = MemoryMapTag {
+    virt_addr: boot_info::MEM_MAP_VIRTUAL_ADDRESS,
+    size: 0x300,
+    entry_size: 0x30,
+};
+
+const CMDLINE: CmdlineTag = CmdlineTag {
+    virt_addr: boot_info::CMDLINE_VIRTUAL_ADDRESS,
+    size: 12,
+    reserved: 0,
+};
+
+/// A tag type no kernel knows about.
+const UNKNOWN_TAG: u32 = 0x1234;
+
+/// Writes a memory map tag, a tag of an unknown type and a command line tag. Returns the total size.
+fn write_handoff(buffer: &mut Buffer) -> usize {
+    let mut writer: HandoffWriter = HandoffWriter::new(&mut buffer.0).unwrap();
+    assert!(writer.push(&MEMORY_MAP));
+    assert!(writer.push_raw(UNKNOWN_TAG, &[1, 2, 3]));
+    assert!(writer.push(&CMDLINE));
+    return writer.finish();
+}
+
+fn write_u16(data: &mut [u8], offset: usize, value: u16) {
+    data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+#[test]
+fn written_handoff_reads_back() {
+    let mut buffer: Buffer = Buffer([0; 0x1000]);
+    let size: usize = write_handoff(&mut buffer);
+    let handoff: Handoff = Handoff::from_bytes(&buffer.0[..size]).unwrap();
+
+    assert_eq!(handoff.minor_version(), HANDOFF_MINOR_VERSION);
+
+    let tag_types: Vec<u32> = handoff.tags().map(|tag: RawTag| tag.tag_type()).collect();
+    assert_eq!(
+        tag_types,
+        [tag_types::MEMORY_MAP, UNKNOWN_TAG, tag_types::CMDLINE]
+    );
+
+    let memory_map: MemoryMapTag = handoff.find::<MemoryMapTag>().unwrap();
+    assert_eq!(memory_map.virt_addr, MEMORY_MAP.virt_addr);
+    assert_eq!(memory_map.size, MEMORY_MAP.size);
+    assert_eq!(memory_map.entry_size, MEMORY_MAP.entry_size);
+
+    let cmdline: CmdlineTag = handoff.find::<CmdlineTag>().unwrap();
+    assert_eq!(cmdline.virt_addr, CMDLINE.virt_addr);
+    assert_eq!(cmdline.size, CMDLINE.size);
+
+    let unknown: RawTag = handoff.tags().nth(1).unwrap();
+    assert_eq!(unknown.payload(), [1, 2, 3]);
+    assert!(unknown.read::<CmdlineTag>().is_none());
+}
+
+#[test]
+fn header_counts_the_tags_and_the_size() {
+    let mut buffer: Buffer = Buffer([0; 0x1000]);
+    let size: usize = write_handoff(&mut buffer);
+    let header: HandoffHeader =
+        unsafe { (buffer.0.as_ptr() as *const HandoffHeader).read_unaligned() };
+
+    //3 tags and the end tag
+    assert_eq!(header.num_tags, 4);
+    assert_eq!(header.total_size as usize, size);
+    assert_eq!(header.major_version, HANDOFF_MAJOR_VERSION);
+}
+
+#[test]
+fn rejects_bad_magic() {
+    let mut buffer: Buffer = Buffer([0; 0x1000]);
+    let size: usize = write_handoff(&mut buffer);
+    buffer.0[0] ^= 0xff;
+
+    assert_eq!(
+        Handoff::from_bytes(&buffer.0[..size]).err(),
+        Some(HandoffError::BadMagic)
+    );
+}
+
+#[test]
+fn rejects_another_major_version() {
+    let mut buffer: Buffer = Buffer([0; 0x1000]);
+    let size: usize = write_handoff(&mut buffer);
+    write_u16(&mut buffer.0, 8, HANDOFF_MAJOR_VERSION + 1);
+
+    assert_eq!(
+        Handoff::from_bytes(&buffer.0[..size]).err(),
+        Some(HandoffError::IncompatibleVersion {
+            major: HANDOFF_MAJOR_VERSION + 1,
+            minor: HANDOFF_MINOR_VERSION
+        })
+    );
+}
+
+#[test]
+fn accepts_a_newer_minor_version() {
+    let mut buffer: Buffer = Buffer([0; 0x1000]);
+    let size: usize = write_handoff(&mut buffer);
+    write_u16(&mut buffer.0, 10, HANDOFF_MINOR_VERSION + 1);
+
+    let handoff: Handoff = Handoff::from_bytes(&buffer.0[..size]).unwrap();
+    assert_eq!(handoff.minor_version(), HANDOFF_MINOR_VERSION + 1);
+}
+
+#[test]
+fn rejects_data_shorter_than_the_header_size() {
+    let mut buffer: Buffer = Buffer([0; 0x1000]);
+    let size: usize = write_handoff(&mut buffer);
+
+    assert_eq!(
+        Handoff::from_bytes(&buffer.0[..size - 8]).err(),
+        Some(HandoffError::BadSize)
+    );
+    assert_eq!(
+        Handoff::from_bytes(&buffer.0[..16]).err(),
+        Some(HandoffError::BadSize)
+    );
+}
+
+#[test]
+fn rejects_a_tag_past_the_end() {
+    let mut buffer: Buffer = Buffer([0; 0x1000]);
+    let size: usize = write_handoff(&mut buffer);
+    //the size of the first tag, right after the header
+    write_u32(&mut buffer.0, size_of::<HandoffHeader>() + 4, size as u32);
+
+    assert_eq!(
+        Handoff::from_bytes(&buffer.0[..size]).err(),
+        Some(HandoffError::MalformedTag)
+    );
+}
+
+#[test]
+fn rejects_a_tag_smaller_than_its_header() {
+    let mut buffer: Buffer = Buffer([0; 0x1000]);
+    let size: usize = write_handoff(&mut buffer);
+    write_u32(&mut buffer.0, size_of::<HandoffHeader>() + 4, 4);
+
+    assert_eq!(
+        Handoff::from_bytes(&buffer.0[..size]).err(),
+        Some(HandoffError::MalformedTag)
+    );
+}
+
+#[test]
+fn rejects_a_missing_end_tag() {
+    let mut buffer: Buffer = Buffer([0; 0x1000]);
+    let size: usize = write_handoff(&mut buffer);
+    //the end tag is the last 8 bytes
+    write_u32(&mut buffer.0, 12, size as u32 - 8);
+
+    assert_eq!(
+        Handoff::from_bytes(&buffer.0[..size]).err(),
+        Some(HandoffError::MalformedTag)
+    );
+}
+
+#[test]
+fn tags_too_small_for_the_payload_are_skipped() {
+    let mut buffer: Buffer = Buffer([0; 0x1000]);
+    let mut writer: HandoffWriter = HandoffWriter::new(&mut buffer.0).unwrap();
+    //an older format of the tag, with only the address
+    assert!(writer.push_raw(tag_types::MEMORY_MAP, &[0xff; 8]));
+    assert!(writer.push(&MEMORY_MAP));
+    let size: usize = writer.finish();
+
+    let handoff: Handoff = Handoff::from_bytes(&buffer.0[..size]).unwrap();
+    assert!(
+        handoff
+            .tags()
+            .next()
+            .unwrap()
+            .read::<MemoryMapTag>()
+            .is_none()
+    );
+    assert_eq!(
+        handoff.find::<MemoryMapTag>().unwrap().virt_addr,
+        MEMORY_MAP.virt_addr
+    );
+}
+
+#[test]
+fn larger_payloads_are_read_from_the_start() {
+    let mut buffer: Buffer = Buffer([0; 0x1000]);
+    let mut writer: HandoffWriter = HandoffWriter::new(&mut buffer.0).unwrap();
+    //a newer format of the tag, with a field added at the end
+    let mut payload: [u8; 24] = [0xee; 24];
+    payload[..8].copy_from_slice(&CMDLINE.virt_addr.to_le_bytes());
+    payload[8..12].copy_from_slice(&CMDLINE.size.to_le_bytes());
+    assert!(writer.push_raw(tag_types::CMDLINE, &payload));
+    let size: usize = writer.finish();
+
+    let handoff: Handoff = Handoff::from_bytes(&buffer.0[..size]).unwrap();
+    let cmdline: CmdlineTag = handoff.find::<CmdlineTag>().unwrap();
+    assert_eq!(cmdline.virt_addr, CMDLINE.virt_addr);
+    assert_eq!(cmdline.size, CMDLINE.size);
+}
+
+#[test]
+fn writer_keeps_room_for_the_end_tag() {
+    //the header, one memory map tag (8 + 16 bytes) and the end tag
+    let mut buffer: Buffer = Buffer([0; 0x1000]);
+    let mut writer: HandoffWriter = HandoffWriter::new(&mut buffer.0[..56]).unwrap();
+    assert!(writer.push(&MEMORY_MAP));
+    assert!(!writer.push(&CMDLINE));
+    assert!(!writer.push_raw(UNKNOWN_TAG, &[]));
+    let size: usize = writer.finish();
+    assert_eq!(size, 56);
+
+    let handoff: Handoff = Handoff::from_bytes(&buffer.0[..size]).unwrap();
+    assert_eq!(handoff.tags().count(), 1);
+}
+
+#[test]
+fn writer_rejects_small_or_unaligned_buffers() {
+    let mut buffer: Buffer = Buffer([0; 0x1000]);
+    assert!(HandoffWriter::new(&mut buffer.0[..31]).is_none());
+    assert!(HandoffWriter::new(&mut buffer.0[4..]).is_none());
+    assert!(HandoffWriter::new(&mut buffer.0[..32]).is_some());
+}
@@ -0,0 +1,86 @@
+use crate::sys_config_reader::SystemConfig;
+use log::{info, warn};
+use uefi::runtime::{self, VariableAttributes, VariableVendor};
+use uefi::{CStr16, Guid, Status};
+
+/// Returns the index of the entry booted when the user doesn't choose one: the fallback entry if
+/// the default one was booted too many times without the kernel reporting success, the default one
+/// otherwise. When the default entry is chosen, this boot is counted as an attempt.
+pub fn choose_default_entry(config: &SystemConfig) -> usize {
+    if config.fallback_entry().is_none() {
+        return config.default_entry();
+    }
+
+    let fallback_entry: usize = config.fallback_entry().unwrap();
+    let attempts: u32 = read_boot_attempts();
+    if attempts >= config.max_boot_attempts() {
+        warn!(
+            "{} failed to boot {attempts} times, using {} instead.",
+            config.entries()[config.default_entry()].title(),
+            config.entries()[fallback_entry].title()
+        );
+        return fallback_entry;
+    }
+
+    if !write_boot_attempts(attempts + 1) {
+        warn!("Error saving the boot attempt counter, the fallback entry won't be used.");
+    } else if attempts > 0 {
+        info!(
+            "Boot attempt {} of {} for the default entry.",
+            attempts + 1,
+            config.max_boot_attempts()
+        );
+    }
+
+    return config.default_entry();
+}
+
+/// Reads [`boot_info::BOOT_ATTEMPTS_VARIABLE_NAME`]. A missing or invalid variable counts as 0.
+fn read_boot_attempts() -> u32 {
+    let mut name_buffer: [u16; 32] = [0; 32];
+    let name: &CStr16 =
+        CStr16::from_str_with_buf(boot_info::BOOT_ATTEMPTS_VARIABLE_NAME, &mut name_buffer)
+            .unwrap();
+
+    let mut data: [u8; 4] = [0; 4];
+    let result: uefi::Result<(&mut [u8], VariableAttributes), Option<usize>> =
+        runtime::get_variable(name, &vendor(), &mut data);
+    if result.is_err() {
+        let err_msg: uefi::Error<Option<usize>> = result.err().unwrap();
+        if err_msg.status() != Status::NOT_FOUND {
+            warn!(
+                "Error reading the boot attempt counter: {}",
+                err_msg.status()
+            );
+        }
+
+        return 0;
+    }
+
+    let (value, _): (&mut [u8], VariableAttributes) = result.unwrap();
+    if value.len() != 4 {
+        return 0;
+    }
+
+    return u32::from_le_bytes(data);
+}
+
+fn write_boot_attempts(attempts: u32) -> bool {
+    let mut name_buffer: [u16; 32] = [0; 32];
+    let name: &CStr16 =
+        CStr16::from_str_with_buf(boot_info::BOOT_ATTEMPTS_VARIABLE_NAME, &mut name_buffer)
+            .unwrap();
+
+    //the kernel deletes it through the runtime services, so it needs runtime access
+    let attributes: VariableAttributes = VariableAttributes::NON_VOLATILE
+        | VariableAttributes::BOOTSERVICE_ACCESS
+        | VariableAttributes::RUNTIME_ACCESS;
+    let result: uefi::Result =
+        runtime::set_variable(name, &vendor(), attributes, &attempts.to_le_bytes());
+
+    return result.is_ok();
+}
+
+fn vendor() -> VariableVendor {
+    VariableVendor(Guid::from_bytes(boot_info::BOOTLOADER_VENDOR_GUID))
+}
@@ -0,0 +1,217 @@
+use crate::file_loader::{self, LoadedFile};
+use crate::serial::SerialPort;
+use core::cell::UnsafeCell;
+use core::fmt::{self, Write};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use uefi::boot::MemoryType;
+use uefi::proto::media::file::{File, RegularFile};
+use uefi::Status;
+
+/// Where the log of the current boot is saved.
+const LOG_PATH: &str = "boot\\bootlog.txt";
+/// The log of the previous boot is moved here before the new one is saved.
+const OLD_LOG_PATH: &str = "boot\\bootlog.old.txt";
+/// The messages are kept in memory until they are saved, the ones that don't fit are only shown
+/// (64 KiB).
+const LOG_BUFFER_SIZE: usize = 0x1_0000;
+/// Added at the end of the saved log if some messages didn't fit in the buffer.
+const TRUNCATED_NOTE: &str = "[...] the log was too large, the rest was only sent to COM1\n";
+
+struct LogState {
+    buffer: [u8; LOG_BUFFER_SIZE],
+    len: usize,
+    /// Whether some messages didn't fit in the buffer.
+    is_truncated: bool,
+    /// The messages above this level are still saved and sent to COM1, just not shown.
+    console_level: LevelFilter,
+    /// The console and the ESP can't be used after exiting the boot services.
+    are_boot_services_active: bool,
+    /// Whether the previous log was already moved to [`OLD_LOG_PATH`] during this boot.
+    is_rotated: bool,
+    /// Where the last error message is in the buffer (start and length, without the newline).
+    last_error: Option<(usize, usize)>,
+    serial: SerialPort,
+}
+
+impl LogState {
+    /// Sends the bytes to COM1 and keeps as many of them as fit in the buffer.
+    fn append(&mut self, bytes: &[u8]) {
+        self.serial.write_bytes(bytes);
+
+        let free: usize = LOG_BUFFER_SIZE - self.len;
+        if bytes.len() > free {
+            self.is_truncated = true;
+        }
+
+        let count: usize = bytes.len().min(free);
+        self.buffer[self.len..self.len + count].copy_from_slice(&bytes[..count]);
+        self.len += count;
+    }
+}
+
+impl Write for LogState {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.append(s.as_bytes());
+        return Ok(());
+    }
+}
+
+/// Shows the messages on the UEFI console (like the logger of the uefi crate), sends them to COM1
+/// and keeps them in memory, so they can be saved on the ESP.
+struct BootLogger {
+    state: UnsafeCell<LogState>,
+}
+
+//the bootloader is single-threaded and never logs from an interrupt handler
+unsafe impl Sync for BootLogger {}
+
+impl BootLogger {
+    #[allow(clippy::mut_from_ref)]
+    fn state(&self) -> &mut LogState {
+        unsafe { &mut *self.state.get() }
+    }
+}
+
+impl Log for BootLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let state: &mut LogState = self.state();
+        let file: &str = record.file().unwrap_or("");
+        let line: u32 = record.line().unwrap_or(0);
+
+        if state.are_boot_services_active && record.level() <= state.console_level {
+            uefi::system::with_stdout(|stdout| {
+                let _ = writeln!(
+                    stdout,
+                    "[{:>5}]: {file:>12}@{line:03}: {}",
+                    record.level(),
+                    record.args()
+                );
+            });
+        }
+
+        let start: usize = state.len;
+        let _ = writeln!(
+            state,
+            "[{:>5}]: {file:>12}@{line:03}: {}",
+            record.level(),
+            record.args()
+        );
+
+        //only keep it if it fits whole, a cut message would be misleading
+        if record.level() == Level::Error && !state.is_truncated {
+            state.last_error = Some((start, state.len - start - 1));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: BootLogger = BootLogger {
+    state: UnsafeCell::new(LogState {
+        buffer: [0; LOG_BUFFER_SIZE],
+        len: 0,
+        is_truncated: false,
+        console_level: LevelFilter::Info,
+        are_boot_services_active: true,
+        is_rotated: false,
+        last_error: None,
+        serial: SerialPort::new(),
+    }),
+};
+
+/// Installs the boot logger. Must be called once, before anything is logged.
+pub fn init() {
+    LOGGER.state().serial.init();
+
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(LevelFilter::Info);
+}
+
+/// Sets the level of the messages shown on the console. The log file and COM1 always get at least
+/// the info messages, so a quiet boot can still be diagnosed.
+pub fn set_level(level: LevelFilter) {
+    LOGGER.state().console_level = level;
+    log::set_max_level(level.max(LevelFilter::Info));
+}
+
+/// Only changes what is shown on the console (e.g. so the boot messages don't scroll over the
+/// splash screen).
+pub fn set_console_level(level: LevelFilter) {
+    LOGGER.state().console_level = level;
+}
+
+/// Stops using the console and the ESP, must be called right before exiting the boot services. The
+/// messages are still sent to COM1.
+pub fn exit_boot_services() {
+    LOGGER.state().are_boot_services_active = false;
+}
+
+/// Whether the console and the ESP can still be used, i.e. the boot services weren't exited.
+pub fn are_boot_services_active() -> bool {
+    LOGGER.state().are_boot_services_active
+}
+
+/// The last error message that was logged, as it appears in the log.
+pub fn last_error() -> Option<&'static str> {
+    let state: &LogState = LOGGER.state();
+    let (start, len): (usize, usize) = state.last_error?;
+
+    return core::str::from_utf8(&state.buffer[start..start + len]).ok();
+}
+
+/// Writes everything logged so far to [`LOG_PATH`]. The first time, the existing log is moved to
+/// [`OLD_LOG_PATH`]. Returns false if the log couldn't be written (e.g. the ESP is read-only) or if
+/// the boot services were exited.
+pub fn save() -> bool {
+    let state: &mut LogState = LOGGER.state();
+    if !state.are_boot_services_active {
+        return false;
+    }
+
+    if !state.is_rotated {
+        //the previous log is less important than the current one, so go on even if this fails
+        rotate();
+        state.is_rotated = true;
+    }
+
+    let file: Result<RegularFile, uefi::Error> = file_loader::create_file(LOG_PATH);
+    if file.is_err() {
+        return false;
+    }
+
+    let mut file: RegularFile = file.unwrap();
+    if file.write(&state.buffer[..state.len]).is_err() {
+        return false;
+    }
+
+    if state.is_truncated && file.write(TRUNCATED_NOTE.as_bytes()).is_err() {
+        return false;
+    }
+
+    return file.flush().is_ok();
+}
+
+/// Moves the log of the previous boot to [`OLD_LOG_PATH`].
+fn rotate() -> bool {
+    let previous: Result<LoadedFile, uefi::Error> = file_loader::load_file(
+        LOG_PATH,
+        MemoryType::LOADER_DATA,
+        (LOG_BUFFER_SIZE + TRUNCATED_NOTE.len()) as u64,
+    );
+    if previous.is_err() {
+        //NOT_FOUND just means there is no previous log
+        return previous.err().unwrap().status() == Status::NOT_FOUND;
+    }
+
+    let previous: LoadedFile = previous.unwrap();
+    let old_file: Result<RegularFile, uefi::Error> = file_loader::create_file(OLD_LOG_PATH);
+    let success: bool =
+        old_file.is_ok_and(|mut file| file.write(previous.data()).is_ok() && file.flush().is_ok());
+    previous.free();
+
+    return success;
+}
@@ -0,0 +1,192 @@
+use crate::failure_screen::{self, Failure};
+use crate::fixed_string::FixedString;
+use crate::sys_config_reader::{BootEntry, SystemConfig, MAX_CMDLINE_LEN};
+use core::fmt::Write;
+use uefi::proto::console::text::{Color, Key, ScanCode};
+use uefi::{boot, system};
+
+/// How often the keyboard is checked, in microseconds (100 ms).
+const POLL_INTERVAL: usize = 100_000;
+const TICKS_PER_SECOND: u32 = (1_000_000 / POLL_INTERVAL) as u32;
+
+/// Shows the boot menu and returns the entry chosen by the user (with the command line edited, if
+/// the user did so). When the timeout expires, the selected entry (initially `default_entry`) is
+/// booted; any key press stops the countdown. A timeout of 0 skips the menu entirely. If there are
+/// no entries, the failure screen is shown instead.
+pub fn choose_entry(config: &SystemConfig, default_entry: usize) -> BootEntry {
+    let entries: &[BootEntry] = config.entries();
+    if entries.is_empty() {
+        failure_screen::show(&Failure {
+            code: "NO_BOOT_ENTRIES",
+            subject: None,
+            status: None,
+        });
+    }
+
+    let mut selected: usize = default_entry.min(entries.len() - 1);
+    if config.boot_timeout() == 0 {
+        return entries[selected];
+    }
+
+    let mut remaining_ticks: Option<u32> = Some(config.boot_timeout() * TICKS_PER_SECOND);
+    let mut needs_redraw: bool = true;
+    let _ = system::with_stdin(|stdin| stdin.reset(false));
+
+    loop {
+        if needs_redraw {
+            draw_menu(
+                entries,
+                selected,
+                remaining_ticks.map(|x| x.div_ceil(TICKS_PER_SECOND)),
+            );
+            needs_redraw = false;
+        }
+
+        let key: Option<Key> = read_key();
+        if key.is_none() {
+            if let Some(ticks) = remaining_ticks {
+                if ticks == 0 {
+                    break;
+                }
+
+                //only redraw when the number of seconds shown changes
+                remaining_ticks = Some(ticks - 1);
+                needs_redraw = (ticks - 1) % TICKS_PER_SECOND == 0;
+            }
+
+            boot::stall(POLL_INTERVAL);
+            continue;
+        }
+
+        //the user is here, don't boot anything by ourselves anymore
+        remaining_ticks = None;
+        needs_redraw = true;
+
+        match key.unwrap() {
+            Key::Special(ScanCode::UP) => {
+                selected = if selected == 0 {
+                    entries.len() - 1
+                } else {
+                    selected - 1
+                };
+            }
+            Key::Special(ScanCode::DOWN) => {
+                selected = (selected + 1) % entries.len();
+            }
+            Key::Printable(chr) => match char::from(chr) {
+                '\r' | '\n' => break,
+                'e' | 'E' => {
+                    let mut entry: BootEntry = entries[selected];
+                    if edit_cmdline(&mut entry) {
+                        clear_screen();
+                        return entry;
+                    }
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    clear_screen();
+    return entries[selected];
+}
+
+/// Lets the user edit the command line of the entry. Returns true if the user wants to boot the entry
+/// with the new command line (Enter), false if the editing was cancelled (Esc).
+fn edit_cmdline(entry: &mut BootEntry) -> bool {
+    let mut cmdline: FixedString<MAX_CMDLINE_LEN> =
+        FixedString::from_str(entry.cmdline()).unwrap_or(FixedString::new());
+
+    loop {
+        draw_editor(entry.title(), cmdline.as_str());
+
+        match wait_for_key() {
+            Key::Special(ScanCode::ESCAPE) => return false,
+            Key::Printable(chr) => match char::from(chr) {
+                '\r' | '\n' => {
+                    entry.set_cmdline(cmdline);
+                    return true;
+                }
+                //backspace
+                '\u{8}' => {
+                    cmdline.pop();
+                }
+                chr if !chr.is_control() => {
+                    //if the command line is full, the character is simply not added
+                    cmdline.push_str(chr.encode_utf8(&mut [0; 4]));
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+fn draw_menu(entries: &[BootEntry], selected: usize, seconds_left: Option<u32>) {
+    system::with_stdout(|stdout| {
+        let _ = stdout.set_color(Color::White, Color::Black);
+        let _ = stdout.clear();
+        let _ = stdout.enable_cursor(false);
+        let _ = writeln!(stdout, "ChihuahuaOS boot menu\n");
+
+        for (idx, entry) in entries.iter().enumerate() {
+            if idx == selected {
+                let _ = stdout.set_color(Color::Black, Color::LightGray);
+            } else {
+                let _ = stdout.set_color(Color::LightGray, Color::Black);
+            }
+
+            let _ = write!(stdout, "  {}  ", entry.title());
+            let _ = stdout.set_color(Color::LightGray, Color::Black);
+            let _ = writeln!(stdout);
+        }
+
+        let _ = writeln!(
+            stdout,
+            "\nUse the arrow keys to select an entry, Enter to boot it or E to edit its command line."
+        );
+        if let Some(seconds) = seconds_left {
+            let _ = writeln!(stdout, "The selected entry will boot in {seconds} s.");
+        }
+    });
+}
+
+fn draw_editor(title: &str, cmdline: &str) {
+    system::with_stdout(|stdout| {
+        let _ = stdout.set_color(Color::LightGray, Color::Black);
+        let _ = stdout.clear();
+        let _ = writeln!(stdout, "Command line for {title}:\n");
+        let _ = stdout.set_color(Color::White, Color::Black);
+        let _ = write!(stdout, "{cmdline}_");
+        let _ = stdout.set_color(Color::LightGray, Color::Black);
+        let _ = writeln!(
+            stdout,
+            "\n\nPress Enter to boot or Esc to go back to the menu."
+        );
+    });
+}
+
+fn clear_screen() {
+    system::with_stdout(|stdout| {
+        let _ = stdout.set_color(Color::LightGray, Color::Black);
+        let _ = stdout.clear();
+        let _ = stdout.enable_cursor(true);
+    });
+}
+
+/// Returns the key that was pressed, if any, without waiting.
+fn read_key() -> Option<Key> {
+    system::with_stdin(|stdin| stdin.read_key()).unwrap_or(None)
+}
+
+fn wait_for_key() -> Key {
+    loop {
+        let key: Option<Key> = read_key();
+        if key.is_some() {
+            return key.unwrap();
+        }
+
+        boot::stall(POLL_INTERVAL);
+    }
+}
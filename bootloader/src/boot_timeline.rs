@@ -0,0 +1,36 @@
+use boot_info::handoff::{boot_stages, BootTimestampsTag};
+
+/// The time stamp counter at the end of each stage of the boot, given to the kernel, which can
+/// convert them to time once it has calibrated the TSC.
+pub struct BootTimeline {
+    tsc: [u64; boot_stages::COUNT],
+}
+
+impl BootTimeline {
+    /// Starts the timeline, timestamping [`boot_stages::ENTRY`].
+    pub fn start() -> Self {
+        let mut timeline: BootTimeline = BootTimeline {
+            tsc: [0; boot_stages::COUNT],
+        };
+        timeline.record(boot_stages::ENTRY);
+
+        return timeline;
+    }
+
+    /// Timestamps the end of the given stage (one of the [`boot_stages`] constants).
+    pub fn record(&mut self, stage: usize) {
+        if stage < boot_stages::COUNT {
+            self.tsc[stage] = read_tsc();
+        }
+    }
+
+    pub fn tag(&self) -> BootTimestampsTag {
+        BootTimestampsTag { tsc: self.tsc }
+    }
+}
+
+/// Returns the value of the time stamp counter.
+pub fn read_tsc() -> u64 {
+    //RDTSC is available on every x86_64 CPU
+    return unsafe { core::arch::x86_64::_rdtsc() };
+}
@@ -0,0 +1,31 @@
+use crate::raw_mem_map::BOOT_DATA_MEMORY;
+use core::ptr::NonNull;
+use log::error;
+use uefi::boot;
+use uefi::boot::AllocateType;
+
+/// Copies the kernel command line in a page of its own, so it can be mapped at
+/// [`boot_info::CMDLINE_VIRTUAL_ADDRESS`]. Returns the physical address of that page. The page is
+/// allocated even if the command line is empty, so the kernel can always read from it.
+pub fn alloc_cmdline(cmdline: &str) -> Option<u64> {
+    if cmdline.len() > boot_info::CMDLINE_MAX_SIZE as usize {
+        error!("Error preparing the kernel command line: it is too long.");
+        return None;
+    }
+
+    let addr: uefi::Result<NonNull<u8>> =
+        boot::allocate_pages(AllocateType::AnyPages, BOOT_DATA_MEMORY, 1);
+    if addr.is_err() {
+        let err_msg: uefi::Error = addr.err().unwrap();
+        error!("Error allocating memory for the kernel command line: {err_msg}");
+        return None;
+    }
+
+    let addr: NonNull<u8> = addr.unwrap();
+    unsafe {
+        core::ptr::write_bytes(addr.as_ptr(), 0, 0x1000);
+        core::ptr::copy_nonoverlapping(cmdline.as_ptr(), addr.as_ptr(), cmdline.len());
+    }
+
+    return Some(addr.as_ptr() as u64);
+}
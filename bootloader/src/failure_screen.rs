@@ -0,0 +1,362 @@
+use crate::boot_log;
+use core::fmt::{self, Write};
+use log::error;
+use uefi::boot::{self, LoadImageSource, OpenProtocolAttributes, OpenProtocolParams};
+use uefi::mem::memory_map::{MemoryMap, MemoryMapOwned};
+use uefi::proto::console::text::{Color, Key, ScanCode};
+use uefi::proto::device_path::{DevicePath, FfiDevicePath};
+use uefi::proto::loaded_image::LoadedImage;
+use uefi::proto::BootPolicy;
+use uefi::runtime::{self, ResetType, VariableAttributes, VariableVendor};
+use uefi::{cstr16, system, Handle, Status};
+
+/// How often the keyboard is checked, in microseconds (100 ms).
+const POLL_INTERVAL: usize = 100_000;
+/// The bit of `OsIndications` that asks the firmware to show its setup on the next boot.
+const BOOT_TO_FW_UI: u64 = 0x1;
+/// The full path of the bootloader image is built here when it is started again.
+const MAX_IMAGE_PATH_SIZE: usize = 512;
+
+/// What is known about a failure that stops the boot.
+pub struct Failure<'a> {
+    /// A short code for the failure (e.g. `KERNEL_NOT_LOADED`), also written to the log.
+    pub code: &'a str,
+    /// What the failure is about (e.g. the path of a file), if anything in particular.
+    pub subject: Option<&'a dyn fmt::Display>,
+    /// The status returned by the firmware, if it caused the failure.
+    pub status: Option<Status>,
+}
+
+/// The failures that can be explained, with whether the memory map is relevant to them.
+const EXPLANATIONS: [(&str, &str, bool); 16] = [
+    ("NO_BOOT_ENTRIES", "There is no entry to boot.", false),
+    (
+        "KERNEL_VOLUME_NOT_FOUND",
+        "The volume of the kernel (set in dog.cfg) was not found.",
+        false,
+    ),
+    (
+        "KERNEL_NOT_LOADED",
+        "The kernel could not be read or is not a valid x86_64 ELF file.",
+        false,
+    ),
+    (
+        "KERNEL_INTEGRITY_CHECK_FAILED",
+        "The kernel doesn't match its SHA-256 digest, it is corrupted or was not fully copied.",
+        false,
+    ),
+    (
+        "INITRD_INTEGRITY_CHECK_FAILED",
+        "The initrd doesn't match its SHA-256 digest, it is corrupted or was not fully copied.",
+        false,
+    ),
+    ("GFX_ERROR", "No usable graphics mode was found.", false),
+    (
+        "RAW_MEM_MAP_ERROR",
+        "There is not enough memory for the memory map given to the kernel.",
+        true,
+    ),
+    (
+        "CMDLINE_ERROR",
+        "The kernel command line could not be prepared.",
+        true,
+    ),
+    (
+        "KERNEL_STACK_ERROR",
+        "There is not enough memory for the kernel stack.",
+        true,
+    ),
+    (
+        "HANDOFF_ERROR",
+        "There is not enough memory for the boot parameters of the kernel.",
+        true,
+    ),
+    (
+        "MEMORY_PAGING_NOT_MAPPED",
+        "The page tables of the kernel could not be built.",
+        true,
+    ),
+    (
+        "EFI_SYS_TABLE_NOT_FOUND",
+        "The UEFI system table was not found.",
+        false,
+    ),
+    (
+        "EFI_MEM_MAP_UNEXPECTEDLY_LARGE",
+        "The memory map grew too much right before the kernel was started.",
+        true,
+    ),
+    (
+        "HANDOFF_TOO_LARGE",
+        "The boot parameters of the kernel don't fit in their page.",
+        false,
+    ),
+    (
+        "MMAP_ERROR",
+        "The memory map could not be read from the firmware.",
+        true,
+    ),
+    (
+        "UEFI_ERROR",
+        "The firmware returned an unexpected error.",
+        false,
+    ),
+];
+
+/// Logs the failure, saves the log and shows what went wrong, letting the user retry, reboot, enter
+/// the firmware setup or go back to the UEFI boot manager. After the boot services were exited,
+/// the failure is only sent to COM1 and the CPU is halted.
+pub fn show(failure: &Failure) -> ! {
+    //read it before the failure itself is logged
+    let last_error: Option<&str> = boot_log::last_error();
+    error!("Fatal error: {}.", failure.code);
+
+    if !boot_log::are_boot_services_active() {
+        error!("The boot services were exited, the system is halted.");
+        loop {
+            x86_64::instructions::interrupts::disable();
+            x86_64::instructions::hlt();
+        }
+    }
+
+    boot_log::save();
+    let can_enter_setup: bool = is_firmware_setup_supported();
+    let _ = system::with_stdin(|stdin| stdin.reset(false));
+
+    loop {
+        draw_screen(failure, last_error, can_enter_setup);
+
+        let key: Key = wait_for_key();
+        match key {
+            Key::Special(ScanCode::ESCAPE) => unsafe {
+                boot::exit(
+                    boot::image_handle(),
+                    Status::ABORTED,
+                    0,
+                    core::ptr::null_mut(),
+                );
+            },
+            Key::Printable(chr) => match char::from(chr) {
+                'r' | 'R' => {
+                    if !retry() {
+                        error!("Error starting the bootloader again.");
+                    }
+                }
+                'b' | 'B' => runtime::reset(ResetType::COLD, Status::SUCCESS, None),
+                'f' | 'F' if can_enter_setup => {
+                    if request_firmware_setup() {
+                        runtime::reset(ResetType::COLD, Status::SUCCESS, None);
+                    }
+
+                    error!("Error asking the firmware to show its setup.");
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+fn draw_screen(failure: &Failure, last_error: Option<&str>, can_enter_setup: bool) {
+    let explanation: Option<&(&str, &str, bool)> = EXPLANATIONS
+        .iter()
+        .find(|(code, _, _)| *code == failure.code);
+
+    system::with_stdout(|stdout| {
+        let _ = stdout.set_color(Color::LightRed, Color::Black);
+        let _ = stdout.clear();
+        let _ = stdout.enable_cursor(false);
+        let _ = writeln!(stdout, "ChihuahuaOS could not be booted\n");
+
+        let _ = stdout.set_color(Color::White, Color::Black);
+        match explanation {
+            Some((_, text, _)) => {
+                let _ = writeln!(stdout, "{text}\n");
+            }
+            None => {
+                let _ = writeln!(stdout, "The bootloader can't continue.\n");
+            }
+        }
+
+        let _ = stdout.set_color(Color::LightGray, Color::Black);
+        let _ = writeln!(stdout, "Error code:  {}", failure.code);
+        if let Some(subject) = failure.subject {
+            let _ = writeln!(stdout, "Concerning:  {subject}");
+        }
+
+        if let Some(status) = failure.status {
+            let _ = writeln!(stdout, "UEFI status: {status}");
+        }
+
+        if let Some(last_error) = last_error {
+            let _ = writeln!(stdout, "Last error:  {last_error}");
+        }
+
+        if explanation.is_some_and(|(_, _, is_memory_related)| *is_memory_related) {
+            write_memory_stats(stdout);
+        }
+
+        let _ = writeln!(
+            stdout,
+            "\nThe full log is in boot\\bootlog.txt on the boot volume.\n"
+        );
+        let _ = stdout.set_color(Color::White, Color::Black);
+        let _ = write!(stdout, "R: retry   B: reboot   ");
+        if can_enter_setup {
+            let _ = write!(stdout, "F: firmware setup   ");
+        }
+
+        let _ = writeln!(stdout, "Esc: back to the UEFI boot menu");
+    });
+}
+
+/// Shows how much memory there is and how much is still free.
+fn write_memory_stats(stdout: &mut uefi::proto::console::text::Output) {
+    let mem_map: uefi::Result<MemoryMapOwned> = boot::memory_map(boot::MemoryType::LOADER_DATA);
+    if mem_map.is_err() {
+        let _ = writeln!(stdout, "Memory:      the memory map can't be read");
+        return;
+    }
+
+    let mem_map: MemoryMapOwned = mem_map.unwrap();
+    let mut free_pages: u64 = 0;
+    let mut largest_free_pages: u64 = 0;
+    let mut total_pages: u64 = 0;
+    for entry in mem_map.entries() {
+        if entry.ty == boot::MemoryType::CONVENTIONAL {
+            free_pages += entry.page_count;
+            largest_free_pages = largest_free_pages.max(entry.page_count);
+        }
+
+        if entry.ty != boot::MemoryType::MMIO
+            && entry.ty != boot::MemoryType::MMIO_PORT_SPACE
+            && entry.ty != boot::MemoryType::RESERVED
+        {
+            total_pages += entry.page_count;
+        }
+    }
+
+    let _ = writeln!(
+        stdout,
+        "Memory:      {} MiB free of {} MiB, largest free range {} MiB, {} map entries",
+        free_pages / 256,
+        total_pages / 256,
+        largest_free_pages / 256,
+        mem_map.entries().len()
+    );
+}
+
+/// Loads the bootloader image again and starts it. Only returns if that failed or if the new
+/// instance exited.
+fn retry() -> bool {
+    let mut path_buffer: [u8; MAX_IMAGE_PATH_SIZE] = [0; MAX_IMAGE_PATH_SIZE];
+    let image: Option<Handle> = load_own_image(&mut path_buffer);
+    if image.is_none() {
+        return false;
+    }
+
+    return boot::start_image(image.unwrap()).is_ok();
+}
+
+/// The loaded image only knows the path of the file on its volume, so the full path is the path of
+/// the volume followed by that one.
+fn load_own_image(path_buffer: &mut [u8; MAX_IMAGE_PATH_SIZE]) -> Option<Handle> {
+    let loaded_image: boot::ScopedProtocol<LoadedImage> =
+        boot::open_protocol_exclusive::<LoadedImage>(boot::image_handle()).ok()?;
+    let device: Handle = loaded_image.device()?;
+    let file_path: &DevicePath = loaded_image.file_path()?;
+
+    //only get the protocol, so the drivers that use the device aren't disconnected
+    let device_path: boot::ScopedProtocol<DevicePath> = unsafe {
+        boot::open_protocol::<DevicePath>(
+            OpenProtocolParams {
+                handle: device,
+                agent: boot::image_handle(),
+                controller: None,
+            },
+            OpenProtocolAttributes::GetProtocol,
+        )
+    }
+    .ok()?;
+
+    //the end node of the volume path is left out, the one of the file path is kept
+    let device_size: usize = device_path
+        .node_iter()
+        .map(|node| node.length() as usize)
+        .sum();
+    let file_size: usize = file_path
+        .node_iter()
+        .map(|node| node.length() as usize)
+        .sum::<usize>()
+        + 4;
+    if device_size + file_size > MAX_IMAGE_PATH_SIZE {
+        return None;
+    }
+
+    unsafe {
+        let device_bytes: &[u8] =
+            core::slice::from_raw_parts(device_path.as_ffi_ptr() as *const u8, device_size);
+        let file_bytes: &[u8] =
+            core::slice::from_raw_parts(file_path.as_ffi_ptr() as *const u8, file_size);
+        path_buffer[..device_size].copy_from_slice(device_bytes);
+        path_buffer[device_size..device_size + file_size].copy_from_slice(file_bytes);
+    }
+
+    let full_path: &DevicePath =
+        unsafe { DevicePath::from_ffi_ptr(path_buffer.as_ptr() as *const FfiDevicePath) };
+    return boot::load_image(
+        boot::image_handle(),
+        LoadImageSource::FromDevicePath {
+            device_path: full_path,
+            boot_policy: BootPolicy::ExactMatch,
+        },
+    )
+    .ok();
+}
+
+fn is_firmware_setup_supported() -> bool {
+    let mut data: [u8; 8] = [0; 8];
+    let result: uefi::Result<(&mut [u8], VariableAttributes), Option<usize>> =
+        runtime::get_variable(
+            cstr16!("OsIndicationsSupported"),
+            &VariableVendor::GLOBAL_VARIABLE,
+            &mut data,
+        );
+
+    return result.is_ok() && u64::from_le_bytes(data) & BOOT_TO_FW_UI != 0;
+}
+
+/// Asks the firmware to show its setup on the next boot.
+fn request_firmware_setup() -> bool {
+    //keep the other indications, a missing variable means there are none
+    let mut data: [u8; 8] = [0; 8];
+    let _ = runtime::get_variable(
+        cstr16!("OsIndications"),
+        &VariableVendor::GLOBAL_VARIABLE,
+        &mut data,
+    );
+
+    let indications: u64 = u64::from_le_bytes(data) | BOOT_TO_FW_UI;
+    let attributes: VariableAttributes = VariableAttributes::NON_VOLATILE
+        | VariableAttributes::BOOTSERVICE_ACCESS
+        | VariableAttributes::RUNTIME_ACCESS;
+    let result: uefi::Result = runtime::set_variable(
+        cstr16!("OsIndications"),
+        &VariableVendor::GLOBAL_VARIABLE,
+        attributes,
+        &indications.to_le_bytes(),
+    );
+
+    return result.is_ok();
+}
+
+fn wait_for_key() -> Key {
+    loop {
+        let key: Option<Key> = system::with_stdin(|stdin| stdin.read_key()).unwrap_or(None);
+        if key.is_some() {
+            return key.unwrap();
+        }
+
+        boot::stall(POLL_INTERVAL);
+    }
+}
@@ -0,0 +1,222 @@
+use crate::sys_config_reader::{Volume, MAX_PATH_LEN};
+use core::ptr::NonNull;
+use uefi::boot::{
+    self, AllocateType, HandleBuffer, MemoryType, OpenProtocolAttributes, OpenProtocolParams,
+    SearchType,
+};
+use uefi::proto::loaded_image::LoadedImage;
+use uefi::proto::media::file::{self, File, FileAttribute, FileInfo, FileSystemVolumeLabel};
+use uefi::proto::media::fs;
+use uefi::proto::media::partition::PartitionInfo;
+use uefi::{CStr16, Guid, Handle, Status};
+
+/// A file that was read in memory, in pages allocated only for it.
+pub struct LoadedFile {
+    phys_addr: u64,
+    size: u64,
+}
+
+impl LoadedFile {
+    /// The physical address of the first page of the file.
+    pub fn phys_addr(&self) -> u64 {
+        self.phys_addr
+    }
+
+    /// The size of the file in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The number of pages allocated for the file.
+    pub fn page_count(&self) -> u64 {
+        self.size.div_ceil(0x1000).max(1)
+    }
+
+    pub fn data(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.phys_addr as *const u8, self.size as usize) }
+    }
+
+    /// Frees the memory of the file.
+    pub fn free(self) {
+        unsafe {
+            let _ = boot::free_pages(
+                NonNull::new_unchecked(self.phys_addr as *mut u8),
+                self.page_count() as usize,
+            );
+        }
+    }
+}
+
+/// Returns the handle of the file system the bootloader was loaded from.
+pub fn boot_volume() -> Result<Handle, uefi::Error> {
+    let loaded_image: boot::ScopedProtocol<LoadedImage> =
+        boot::open_protocol_exclusive::<LoadedImage>(boot::image_handle())?;
+    let device: Option<Handle> = loaded_image.device();
+    if device.is_none() {
+        return Err(uefi::Error::from(Status::UNSUPPORTED));
+    }
+
+    return Ok(device.unwrap());
+}
+
+/// Returns the handle of the file system that matches the given volume, looking at every file
+/// system the firmware knows of. Gives NOT_FOUND if there is none.
+pub fn find_volume(volume: &Volume) -> Result<Handle, uefi::Error> {
+    if let Volume::Boot = volume {
+        return boot_volume();
+    }
+
+    let handles: HandleBuffer =
+        boot::locate_handle_buffer(SearchType::from_proto::<fs::SimpleFileSystem>())?;
+    for handle in handles.iter() {
+        let is_match: bool = match volume {
+            Volume::Boot => false,
+            Volume::PartitionGuid(guid) => partition_guid(*handle) == Some(Guid::from_bytes(*guid)),
+            Volume::Label(label) => has_label(*handle, label.as_str()),
+        };
+        if is_match {
+            return Ok(*handle);
+        }
+    }
+
+    return Err(uefi::Error::from(Status::NOT_FOUND));
+}
+
+/// Creates a file on the volume the bootloader was loaded from, replacing the existing one. The
+/// directory must already exist.
+pub fn create_file(path: &str) -> Result<file::RegularFile, uefi::Error> {
+    let volume: Handle = boot_volume()?;
+    let existing: Result<file::RegularFile, uefi::Error> =
+        open_with_mode(volume, path, file::FileMode::ReadWrite);
+    if existing.is_ok() {
+        existing.unwrap().delete()?;
+    }
+
+    return open_with_mode(volume, path, file::FileMode::CreateReadWrite);
+}
+
+fn open_with_mode(
+    volume: Handle,
+    path: &str,
+    mode: file::FileMode,
+) -> Result<file::RegularFile, uefi::Error> {
+    let mut fs: boot::ScopedProtocol<fs::SimpleFileSystem> =
+        boot::open_protocol_exclusive::<fs::SimpleFileSystem>(volume)?;
+    let mut root_dir: file::Directory = fs.open_volume()?;
+
+    let mut path_buffer: [u16; MAX_PATH_LEN + 1] = [0; MAX_PATH_LEN + 1];
+    let path: Option<&CStr16> = CStr16::from_str_with_buf(path, &mut path_buffer).ok();
+    if path.is_none() {
+        return Err(uefi::Error::from(Status::INVALID_PARAMETER));
+    }
+
+    let fs: file::FileHandle = root_dir.open(path.unwrap(), mode, FileAttribute::empty())?;
+    let fs: Option<file::RegularFile> = fs.into_regular_file();
+    if fs.is_none() {
+        return Err(uefi::Error::from(Status::INVALID_PARAMETER));
+    }
+
+    return Ok(fs.unwrap());
+}
+
+/// Reads a whole file from the volume the bootloader was loaded from in newly allocated pages of the
+/// given memory type. Files larger than `max_size` bytes are rejected with BUFFER_TOO_SMALL. A
+/// missing file gives NOT_FOUND.
+pub fn load_file(
+    path: &str,
+    memory_type: MemoryType,
+    max_size: u64,
+) -> Result<LoadedFile, uefi::Error> {
+    return load_file_from(boot_volume()?, path, memory_type, max_size);
+}
+
+/// Same as [`load_file`], but from the given volume (see [`find_volume`]).
+pub fn load_file_from(
+    volume: Handle,
+    path: &str,
+    memory_type: MemoryType,
+    max_size: u64,
+) -> Result<LoadedFile, uefi::Error> {
+    let mut fs: file::RegularFile = open_with_mode(volume, path, file::FileMode::Read)?;
+
+    let mut info_buffer: [u8; 512] = [0; 512];
+    let info: &mut FileInfo = fs
+        .get_info::<FileInfo>(&mut info_buffer)
+        .map_err(|err| uefi::Error::from(err.status()))?;
+    let size: u64 = info.file_size();
+    if size > max_size {
+        return Err(uefi::Error::from(Status::BUFFER_TOO_SMALL));
+    }
+
+    let page_count: usize = size.div_ceil(0x1000).max(1) as usize;
+    let addr: NonNull<u8> = boot::allocate_pages(AllocateType::AnyPages, memory_type, page_count)?;
+
+    let loaded: LoadedFile = LoadedFile {
+        phys_addr: addr.as_ptr() as u64,
+        size,
+    };
+
+    let buffer: &mut [u8] =
+        unsafe { core::slice::from_raw_parts_mut(addr.as_ptr(), page_count * 0x1000) };
+    //zero-out the end of the last page, the file might be mapped as a whole
+    buffer.fill(0);
+
+    let read: Result<usize, uefi::Error> = fs.read(&mut buffer[..size as usize]);
+    if read.is_err() || read.as_ref().is_ok_and(|x| *x as u64 != size) {
+        loaded.free();
+        return Err(read.err().unwrap_or(uefi::Error::from(Status::END_OF_FILE)));
+    }
+
+    return Ok(loaded);
+}
+
+/// The unique GUID of the GPT partition of the file system, None if it isn't on a GPT partition.
+fn partition_guid(handle: Handle) -> Option<Guid> {
+    //only get the protocol, so the drivers that use it aren't disconnected
+    let info: uefi::Result<boot::ScopedProtocol<PartitionInfo>> = unsafe {
+        boot::open_protocol::<PartitionInfo>(
+            OpenProtocolParams {
+                handle,
+                agent: boot::image_handle(),
+                controller: None,
+            },
+            OpenProtocolAttributes::GetProtocol,
+        )
+    };
+    if info.is_err() {
+        return None;
+    }
+
+    return info
+        .unwrap()
+        .gpt_partition_entry()
+        .map(|entry| entry.unique_partition_guid);
+}
+
+fn has_label(handle: Handle, label: &str) -> bool {
+    let fs: uefi::Result<boot::ScopedProtocol<fs::SimpleFileSystem>> =
+        boot::open_protocol_exclusive::<fs::SimpleFileSystem>(handle);
+    if fs.is_err() {
+        return false;
+    }
+
+    let root_dir: Result<file::Directory, uefi::Error> = fs.unwrap().open_volume();
+    if root_dir.is_err() {
+        return false;
+    }
+
+    let mut info_buffer: [u8; 256] = [0; 256];
+    let mut root_dir: file::Directory = root_dir.unwrap();
+    let info: uefi::Result<&mut FileSystemVolumeLabel, Option<usize>> =
+        root_dir.get_info::<FileSystemVolumeLabel>(&mut info_buffer);
+    if info.is_err() {
+        return false;
+    }
+
+    //FAT stores the labels in uppercase, so the case doesn't matter
+    let volume_label: &CStr16 = info.unwrap().volume_label();
+    return volume_label
+        .iter()
+        .map(|x| char::from(*x).to_ascii_lowercase())
+        .eq(label.chars().map(|x| x.to_ascii_lowercase()));
+}
@@ -0,0 +1,106 @@
+use log::{info, warn};
+use uefi::system;
+use uefi::table::cfg::ConfigTableEntry;
+
+/// The size of an ACPI 2.0+ RSDP (ACPI 1.0 ones only have the first 20 bytes).
+const RSDP_SIZE: u64 = 36;
+/// The size of the largest SMBIOS entry point (the 2.1 one, the 3.0 one has 24 bytes).
+const SMBIOS_ENTRY_SIZE: u64 = 31;
+
+/// The entry points of the firmware tables the kernel needs for hardware discovery. All addresses are
+/// physical, 0 means the table was not found.
+pub struct FirmwareTables {
+    acpi_rsdp: u64,
+    smbios_entry: u64,
+    /// The SMBIOS structure table, which the entry point refers to.
+    smbios_table: u64,
+    smbios_table_size: u64,
+}
+
+impl FirmwareTables {
+    /// The ACPI RSDP (2.0 if the firmware has it, 1.0 otherwise).
+    pub fn acpi_rsdp(&self) -> u64 {
+        self.acpi_rsdp
+    }
+
+    /// The SMBIOS entry point (3.0 if the firmware has it, 2.x otherwise).
+    pub fn smbios_entry(&self) -> u64 {
+        self.smbios_entry
+    }
+
+    /// Returns the physical ranges (start, size in bytes) that must stay intact until the kernel
+    /// has parsed the tables. Only the tables that were found are returned.
+    pub fn preserved_ranges(&self) -> impl Iterator<Item = (u64, u64)> {
+        [
+            (self.acpi_rsdp, RSDP_SIZE),
+            (self.smbios_entry, SMBIOS_ENTRY_SIZE),
+            (self.smbios_table, self.smbios_table_size),
+        ]
+        .into_iter()
+        .filter(|(start, size)| *start != 0 && *size != 0)
+    }
+}
+
+/// Looks for the ACPI and SMBIOS entry points in the EFI configuration table. Must be called before
+/// exiting the boot services.
+pub fn find_firmware_tables() -> FirmwareTables {
+    let mut tables: FirmwareTables = FirmwareTables {
+        acpi_rsdp: 0,
+        smbios_entry: 0,
+        smbios_table: 0,
+        smbios_table_size: 0,
+    };
+
+    let mut smbios3: bool = false;
+    system::with_config_table(|entries: &[ConfigTableEntry]| {
+        for entry in entries {
+            let addr: u64 = entry.address as u64;
+
+            //prefer the newer versions, but take the old ones if there's nothing else
+            match entry.guid {
+                ConfigTableEntry::ACPI2_GUID => tables.acpi_rsdp = addr,
+                ConfigTableEntry::ACPI_GUID if tables.acpi_rsdp == 0 => tables.acpi_rsdp = addr,
+                ConfigTableEntry::SMBIOS3_GUID => {
+                    tables.smbios_entry = addr;
+                    smbios3 = true;
+                }
+                ConfigTableEntry::SMBIOS_GUID if !smbios3 => tables.smbios_entry = addr,
+                _ => {}
+            }
+        }
+    });
+
+    if tables.acpi_rsdp == 0 {
+        warn!("No ACPI RSDP was found, the kernel won't be able to discover the hardware.");
+    }
+
+    if tables.smbios_entry != 0 {
+        (tables.smbios_table, tables.smbios_table_size) =
+            read_smbios_table_location(tables.smbios_entry, smbios3);
+    }
+
+    info!(
+        "ACPI RSDP at {:#x}, SMBIOS entry point at {:#x}.",
+        tables.acpi_rsdp, tables.smbios_entry
+    );
+    return tables;
+}
+
+/// Returns the address and size of the SMBIOS structure table from its entry point.
+fn read_smbios_table_location(entry_point: u64, smbios3: bool) -> (u64, u64) {
+    let entry: *const u8 = entry_point as *const u8;
+
+    unsafe {
+        if smbios3 {
+            //"_SM3_": the maximum size of the table at 0x0c, its 64-bit address at 0x10
+            let size: u32 = (entry.add(0x0c) as *const u32).read_unaligned();
+            let addr: u64 = (entry.add(0x10) as *const u64).read_unaligned();
+            return (addr, size as u64);
+        }
+
+        //"_SM_": the size of the table at 0x16, its 32-bit address at 0x18
+        let size: u16 = (entry.add(0x16) as *const u16).read_unaligned();
+        let addr: u32 = (entry.add(0x18) as *const u32).read_unaligned();
+        return (addr as u64, size as u64);
+    }
+}
@@ -0,0 +1,56 @@
+/// A string with a fixed maximum capacity (in bytes). We don't have an allocator in the bootloader,
+/// so every string that comes from the config file lives in one of these. Values that don't fit are
+/// rejected instead of being silently truncated.
+#[derive(Clone, Copy)]
+pub struct FixedString<const N: usize> {
+    data: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> FixedString<N> {
+    pub const fn new() -> Self {
+        FixedString {
+            data: [0; N],
+            len: 0,
+        }
+    }
+
+    /// Returns None if the value is longer than the capacity of the string.
+    pub fn from_str(value: &str) -> Option<Self> {
+        let mut result: FixedString<N> = FixedString::new();
+        if !result.push_str(value) {
+            return None;
+        }
+
+        return Some(result);
+    }
+
+    pub fn as_str(&self) -> &str {
+        //we only ever copy whole &str values inside, so this is always valid UTF-8
+        core::str::from_utf8(&self.data[..self.len]).unwrap_or("")
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Appends the value at the end of the string. Returns false (and leaves the string unchanged)
+    /// if there isn't enough space for it.
+    pub fn push_str(&mut self, value: &str) -> bool {
+        if self.len + value.len() > N {
+            return false;
+        }
+
+        self.data[self.len..self.len + value.len()].copy_from_slice(value.as_bytes());
+        self.len += value.len();
+        return true;
+    }
+
+    /// Removes the last character of the string and returns it.
+    pub fn pop(&mut self) -> Option<char> {
+        let last: char = self.as_str().chars().last()?;
+        self.len -= last.len_utf8();
+
+        return Some(last);
+    }
+}
@@ -1,17 +1,54 @@
 use boot_info::framebuffer::FramebufferData;
+use log::info;
+use uefi::proto::{unsafe_protocol, ProtocolPointer};
 use uefi::{
-    boot::{self},
+    boot::{self, OpenProtocolAttributes, OpenProtocolParams},
     proto::console::gop::{self, GraphicsOutput, PixelFormat},
     Handle,
 };
 
-pub fn set_appropriate_framebuffer(pref_width: u32, pref_height: u32) -> Option<FramebufferData> {
+/// EFI_EDID_ACTIVE_PROTOCOL: the EDID the GPU uses for the display, which the platform may override.
+#[repr(C)]
+#[unsafe_protocol("bd8c1056-9f36-44ec-92a8-a6337f817986")]
+struct EdidActive {
+    size_of_edid: u32,
+    edid: *const u8,
+}
+
+/// EFI_EDID_DISCOVERED_PROTOCOL: the EDID as read from the display.
+#[repr(C)]
+#[unsafe_protocol("1c0c34f6-d380-41fa-a049-8ad06c1a66aa")]
+struct EdidDiscovered {
+    size_of_edid: u32,
+    edid: *const u8,
+}
+
+/// Switches to the most appropriate graphics mode and returns its framebuffer data. The modes are
+/// ranked like this:
+/// 1. the exact preferred resolution;
+/// 2. the native resolution of the display (the preferred timing from its EDID);
+/// 3. the largest mode that is not larger than the preferred resolution;
+/// 4. the smallest of the remaining modes.
+///
+/// Among modes with the same resolution, RGB is chosen over BGR and bitmask formats if `prefer_rgb`
+/// is true. If `log_modes` is true, all the available modes are logged.
+pub fn set_appropriate_framebuffer(
+    pref_width: u32,
+    pref_height: u32,
+    log_modes: bool,
+    prefer_rgb: bool,
+) -> Option<FramebufferData> {
     let gop_handle: Result<Handle, uefi::Error> = boot::get_handle_for_protocol::<GraphicsOutput>();
     if gop_handle.is_err() {
         return None;
     }
 
     let gop_handle: Handle = gop_handle.unwrap();
+    let native_resolution: Option<(usize, usize)> = read_native_resolution(gop_handle);
+    if let Some((width, height)) = native_resolution {
+        info!("The display reports a native resolution of {width}x{height}.");
+    }
+
     let gop: Result<boot::ScopedProtocol<GraphicsOutput>, uefi::Error> =
         boot::open_protocol_exclusive::<GraphicsOutput>(gop_handle);
     if gop.is_err() {
@@ -19,45 +56,154 @@ pub fn set_appropriate_framebuffer(pref_width: u32, pref_height: u32) -> Option<
     }
 
     let mut gop: boot::ScopedProtocol<GraphicsOutput> = gop.unwrap();
+    let preferred: (usize, usize) = (pref_width as usize, pref_height as usize);
 
     let mut best_mode: Option<gop::Mode> = None;
-    let mut best_mode_info: gop::ModeInfo = gop.current_mode_info();
-    let mut deviation: (i32, i32) = (
-        (pref_width as i32) - (best_mode_info.resolution().0 as i32),
-        (pref_height as i32) - (best_mode_info.resolution().1 as i32),
-    );
+    let mut best_score: ModeScore = ModeScore::default();
 
     //query all modes and pick the most appropriate one
-    gop.modes().for_each(|x| {
-        let res: (i32, i32) = (
-            x.info().resolution().0 as i32,
-            x.info().resolution().1 as i32,
-        );
-
-        let this_deviation: (i32, i32) =
-            ((pref_width as i32) - res.0, (pref_height as i32) - res.1);
-
-        if abs(this_deviation.0 + this_deviation.1) < abs(deviation.0 + deviation.1) {
-            best_mode = Some(x);
-            best_mode_info = *x.info();
-            deviation = this_deviation;
+    for (idx, mode) in gop.modes().enumerate() {
+        let info: &gop::ModeInfo = mode.info();
+        if log_modes {
+            let (width, height): (usize, usize) = info.resolution();
+            info!(
+                "Graphics mode {idx}: {width}x{height}, {:?}, stride {}",
+                info.pixel_format(),
+                info.stride()
+            );
         }
-    });
+
+        //we need a framebuffer, the kernel can't use the BLT functions
+        if info.pixel_format() == PixelFormat::BltOnly {
+            continue;
+        }
+
+        let score: ModeScore = score_mode(info, preferred, native_resolution, prefer_rgb);
+        if best_mode.is_none() || score > best_score {
+            best_mode = Some(mode);
+            best_score = score;
+        }
+    }
 
     if best_mode.is_none() {
         return None;
     }
 
-    let result: Result<(), uefi::Error> = gop.set_mode(&best_mode.unwrap());
+    let best_mode: gop::Mode = best_mode.unwrap();
+    let result: Result<(), uefi::Error> = gop.set_mode(&best_mode);
     if result.is_err() {
         return None;
     }
 
-    return fb_data_from_mode_info(best_mode_info);
+    return fb_data_from_mode_info(*best_mode.info());
 }
 
-fn abs(val: i32) -> i32 {
-    if val < 0 { -val } else { val }
+/// How good a mode is, a higher score is better. The fields are compared in order.
+#[derive(Default, PartialEq, Eq, PartialOrd, Ord)]
+struct ModeScore {
+    /// 3 for the exact preferred resolution, 2 for the native resolution, 1 for a mode that fits in
+    /// the preferred resolution and 0 for the rest.
+    tier: u8,
+    /// Inside a tier: larger modes are better if they fit in the preferred resolution, smaller ones
+    /// otherwise.
+    size: u64,
+    /// Inside the same resolution: RGB, then BGR, then bitmask (if RGB is preferred).
+    format: u8,
+}
+
+fn score_mode(
+    info: &gop::ModeInfo,
+    preferred: (usize, usize),
+    native: Option<(usize, usize)>,
+    prefer_rgb: bool,
+) -> ModeScore {
+    let (width, height): (usize, usize) = info.resolution();
+    let area: u64 = width as u64 * height as u64;
+
+    let (tier, size): (u8, u64) = if (width, height) == preferred {
+        (3, 0)
+    } else if Some((width, height)) == native {
+        (2, 0)
+    } else if width <= preferred.0 && height <= preferred.1 {
+        (1, area)
+    } else {
+        (0, u64::MAX - area)
+    };
+
+    let format: u8 = match info.pixel_format() {
+        PixelFormat::Rgb if prefer_rgb => 2,
+        PixelFormat::Bgr if prefer_rgb => 1,
+        _ => 0,
+    };
+
+    return ModeScore { tier, size, format };
+}
+
+/// Returns the native resolution of the display connected to the GPU, from the preferred timing of
+/// its EDID. The active EDID is used (it can be overridden by the platform), then the discovered
+/// one.
+fn read_native_resolution(gop_handle: Handle) -> Option<(usize, usize)> {
+    let active: Option<boot::ScopedProtocol<EdidActive>> = get_protocol::<EdidActive>(gop_handle);
+    if let Some(active) = active {
+        let resolution: Option<(usize, usize)> =
+            parse_edid_resolution(active.edid, active.size_of_edid);
+        if resolution.is_some() {
+            return resolution;
+        }
+    }
+
+    let discovered: Option<boot::ScopedProtocol<EdidDiscovered>> =
+        get_protocol::<EdidDiscovered>(gop_handle);
+    if let Some(discovered) = discovered {
+        return parse_edid_resolution(discovered.edid, discovered.size_of_edid);
+    }
+
+    return None;
+}
+
+/// Opens a protocol of the GPU without taking it, so the GOP driver that manages the handle isn't
+/// disconnected.
+fn get_protocol<P: ProtocolPointer + ?Sized>(handle: Handle) -> Option<boot::ScopedProtocol<P>> {
+    let protocol: uefi::Result<boot::ScopedProtocol<P>> = unsafe {
+        boot::open_protocol::<P>(
+            OpenProtocolParams {
+                handle,
+                agent: boot::image_handle(),
+                controller: None,
+            },
+            OpenProtocolAttributes::GetProtocol,
+        )
+    };
+
+    return protocol.ok();
+}
+
+/// Reads the resolution of the first detailed timing descriptor, which is the preferred timing.
+fn parse_edid_resolution(edid: *const u8, size: u32) -> Option<(usize, usize)> {
+    const EDID_HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+
+    if edid.is_null() || size < 128 {
+        return None;
+    }
+
+    let edid: &[u8] = unsafe { core::slice::from_raw_parts(edid, 128) };
+    if edid[..8] != EDID_HEADER {
+        return None;
+    }
+
+    //the first descriptor starts at 54, a pixel clock of 0 means it's not a timing descriptor
+    let timing: &[u8] = &edid[54..72];
+    if timing[0] == 0 && timing[1] == 0 {
+        return None;
+    }
+
+    let width: usize = timing[2] as usize | ((timing[4] as usize & 0xf0) << 4);
+    let height: usize = timing[5] as usize | ((timing[7] as usize & 0xf0) << 4);
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    return Some((width, height));
 }
 
 pub fn fb_data_from_mode_info(mode_info: gop::ModeInfo) -> Option<FramebufferData> {
@@ -66,17 +212,20 @@ pub fn fb_data_from_mode_info(mode_info: gop::ModeInfo) -> Option<FramebufferDat
     let mut blue_mask: u32 = 0;
     let mut bits_per_pixel: u8 = 0;
 
+    //the masks are for a pixel read as a little-endian u32, so the first byte is the lowest one
     match mode_info.pixel_format() {
+        //blue in the first byte
         PixelFormat::Bgr => {
-            red_mask = 0xff;
+            red_mask = 0xff_00_00;
             green_mask = 0xff_00;
-            blue_mask = 0xff_00_00;
+            blue_mask = 0xff;
             bits_per_pixel = 32;
         }
+        //red in the first byte
         PixelFormat::Rgb => {
-            red_mask = 0xff_00_00;
+            red_mask = 0xff;
             green_mask = 0xff_00;
-            blue_mask = 0xff;
+            blue_mask = 0xff_00_00;
             bits_per_pixel = 32;
         }
         PixelFormat::Bitmask => {
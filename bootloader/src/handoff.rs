@@ -0,0 +1,25 @@
+use core::ptr::NonNull;
+use log::error;
+use uefi::boot;
+use uefi::boot::{AllocateType, MemoryType};
+
+/// Allocates the page where the handoff for the kernel is written (see [`boot_info::handoff`]).
+/// It must be allocated before the page tables are set up, so it is identity-mapped. Returns the
+/// physical address of the page.
+pub fn alloc_handoff() -> Option<u64> {
+    let page_count: usize = boot_info::handoff::HANDOFF_MAX_SIZE.div_ceil(0x1000);
+    let addr: uefi::Result<NonNull<u8>> =
+        boot::allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, page_count);
+    if addr.is_err() {
+        let err_msg: uefi::Error = addr.err().unwrap();
+        error!("Error allocating memory for the kernel handoff: {err_msg}");
+        return None;
+    }
+
+    let addr: NonNull<u8> = addr.unwrap();
+    unsafe {
+        core::ptr::write_bytes(addr.as_ptr(), 0, page_count * 0x1000);
+    }
+
+    return Some(addr.as_ptr() as u64);
+}
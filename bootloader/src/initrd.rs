@@ -0,0 +1,34 @@
+use crate::file_loader::{self, LoadedFile};
+use crate::raw_mem_map::BOOT_DATA_MEMORY;
+use log::{error, info};
+use uefi::{Handle, Status};
+
+/// Loads the initial ramdisk from the given path. The initrd is optional, so None is returned if the
+/// path is empty or the file doesn't exist, as well as when it could not be loaded (the error is
+/// reported and the kernel boots without it).
+pub fn load_initrd(volume: Handle, path: &str) -> Option<LoadedFile> {
+    if path.is_empty() {
+        return None;
+    }
+
+    let initrd: Result<LoadedFile, uefi::Error> =
+        file_loader::load_file_from(volume, path, BOOT_DATA_MEMORY, boot_info::INITRD_MAX_SIZE);
+    if initrd.is_err() {
+        let err: uefi::Error = initrd.err().unwrap();
+        match err.status() {
+            Status::NOT_FOUND => info!("No initrd found at {path}, booting without it."),
+            Status::BUFFER_TOO_SMALL => error!(
+                "Error reading the initrd: it is larger than {} MiB.",
+                boot_info::INITRD_MAX_SIZE / 0x10_0000
+            ),
+            _ => error!("Error reading the initrd: {err}"),
+        }
+
+        return None;
+    }
+
+    let initrd: LoadedFile = initrd.unwrap();
+    info!("Loaded the initrd ({} KiB).", initrd.size() / 1024);
+
+    return Some(initrd);
+}
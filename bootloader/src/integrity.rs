@@ -0,0 +1,112 @@
+use crate::file_loader::{self, LoadedFile};
+use crate::fixed_string::FixedString;
+use crate::sys_config_reader::{self, Sha256Digest, MAX_PATH_LEN};
+use core::fmt;
+use log::{error, info};
+use sha2::{Digest, Sha256};
+use uefi::boot::MemoryType;
+use uefi::{Handle, Status};
+
+/// The extension of the sidecar files that hold the digest of a file (e.g. `boot\kernel.elf.sha256`).
+const SIDECAR_EXTENSION: &str = ".sha256";
+/// A sidecar file only holds a digest and maybe a file name, it can't be larger than this.
+const MAX_SIDECAR_SIZE: u64 = 0x1000;
+
+/// Checks the file against its expected SHA-256 digest: the one from dog.cfg if there is one, or the
+/// one from the `<path>.sha256` sidecar file otherwise. Returns false if the digests don't match or
+/// if the sidecar file exists but can't be used, so the file must not be booted. A file without any
+/// digest is not verified and true is returned.
+pub fn verify_file(
+    volume: Handle,
+    path: &str,
+    data: &[u8],
+    expected: Option<&Sha256Digest>,
+) -> bool {
+    let mut expected: Option<Sha256Digest> = expected.copied();
+    if expected.is_none() {
+        let sidecar: Result<Option<Sha256Digest>, ()> = read_sidecar(volume, path);
+        if sidecar.is_err() {
+            return false;
+        }
+
+        expected = sidecar.unwrap();
+    }
+
+    if expected.is_none() {
+        info!("No SHA-256 digest for {path}, it is not verified.");
+        return true;
+    }
+
+    let expected: Sha256Digest = expected.unwrap();
+    let mut actual: Sha256Digest = [0; 32];
+    actual.copy_from_slice(&Sha256::digest(data));
+
+    if actual != expected {
+        error!("Integrity check failed for {path}: the file is corrupted or was not fully copied.");
+        error!("Expected SHA-256: {}", DigestHex(&expected));
+        error!("Actual SHA-256:   {}", DigestHex(&actual));
+        return false;
+    }
+
+    info!("{path} passed the integrity check.");
+    return true;
+}
+
+/// Reads the digest from the sidecar file of the given file. Returns Ok(None) if there is no sidecar
+/// file and Err if it exists but can't be read or doesn't contain a digest. The sidecar file is on
+/// the same volume as the file.
+fn read_sidecar(volume: Handle, path: &str) -> Result<Option<Sha256Digest>, ()> {
+    let mut sidecar_path: FixedString<{ MAX_PATH_LEN + SIDECAR_EXTENSION.len() }> =
+        FixedString::new();
+    if !sidecar_path.push_str(path) || !sidecar_path.push_str(SIDECAR_EXTENSION) {
+        error!("Error reading the digest of {path}: the path is too long.");
+        return Err(());
+    }
+
+    let sidecar: Result<LoadedFile, uefi::Error> = file_loader::load_file_from(
+        volume,
+        sidecar_path.as_str(),
+        MemoryType::LOADER_DATA,
+        MAX_SIDECAR_SIZE,
+    );
+    if sidecar.is_err() {
+        let err_msg: uefi::Error = sidecar.err().unwrap();
+        if err_msg.status() == Status::NOT_FOUND {
+            return Ok(None);
+        }
+
+        error!("Error reading {}: {err_msg}", sidecar_path.as_str());
+        return Err(());
+    }
+
+    //the format of sha256sum: the digest, then optionally the file name
+    let sidecar: LoadedFile = sidecar.unwrap();
+    let digest: Option<Sha256Digest> = core::str::from_utf8(sidecar.data())
+        .ok()
+        .and_then(|text| text.split_whitespace().next())
+        .and_then(sys_config_reader::parse_sha256);
+    sidecar.free();
+
+    if digest.is_none() {
+        error!(
+            "Error reading {}: it doesn't start with a SHA-256 digest.",
+            sidecar_path.as_str()
+        );
+        return Err(());
+    }
+
+    return Ok(digest);
+}
+
+/// Formats a digest as hex digits.
+struct DigestHex<'a>(&'a Sha256Digest);
+
+impl fmt::Display for DigestHex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+
+        return Ok(());
+    }
+}
@@ -1,15 +1,23 @@
-use boot_info::KParams;
 use x86_64::{
     registers::control::{Cr0, Cr0Flags, Cr3, Cr4, Cr4Flags, Efer, EferFlags},
     structures::paging::PhysFrame,
 };
 
+/// Switches to the kernel page tables and jumps to the kernel entry point on the kernel stack, with
+/// the address of the handoff (see [`boot_info::handoff`]) as its only parameter. `stack_top` is
+/// the virtual address right above the stack (it must be 16-byte aligned).
 pub fn boot_kernel(
     entry_point: u64,
     page_table_address: x86_64::PhysAddr,
-    kernel_params: KParams,
+    handoff_addr: u64,
+    stack_top: u64,
 ) -> ! {
     unsafe {
+        // The kernel pages are mapped with the NX bit, which is a reserved bit unless NXE is set
+        if crate::paging::is_nx_supported() {
+            Efer::update(|efer: &mut EferFlags| efer.insert(EferFlags::NO_EXECUTE_ENABLE));
+        }
+
         // Set CR3 to the new page table
         Cr3::write(
             PhysFrame::containing_address(page_table_address),
@@ -24,19 +32,23 @@ pub fn boot_kernel(
         Cr0::update(|cr0: &mut Cr0Flags| {
             cr0.insert(Cr0Flags::PAGING);
             cr0.insert(Cr0Flags::PROTECTED_MODE_ENABLE);
+            // Read-only pages must be read-only for the kernel too
+            cr0.insert(Cr0Flags::WRITE_PROTECT);
         });
 
-        let k_params_ptr = &kernel_params as *const KParams;
-
-        // Far jump to kernel entry
-        let entry: extern "C" fn() -> ! = {
-            //core::arch::asm!("mov rsp, {}", in(reg) 0xffff_ffff_ffef_ffff as u64);
-
-            //dangerously set the kernel boot parameters in the rdi register (first param
-            // in SysV calling convention)
-            core::arch::asm!("mov rdi, {}", in(reg) k_params_ptr);
-            core::mem::transmute(entry_point)
-        };
-        entry();
-    };
+        // Jump to the kernel entry on the kernel stack. Nothing can touch the old stack after RSP
+        // is changed, so it's all done in one asm block. The address of the handoff goes in rdi
+        // (first param in SysV calling convention) and a 0 return address is pushed, so the stack
+        // is aligned like after a call and backtraces stop at kmain.
+        core::arch::asm!(
+            "mov rsp, {stack_top}",
+            "xor ebp, ebp",
+            "push rbp",
+            "jmp {entry}",
+            stack_top = in(reg) stack_top,
+            entry = in(reg) entry_point,
+            in("rdi") handoff_addr,
+            options(noreturn)
+        );
+    }
 }
@@ -1,199 +1,133 @@
-use elf;
-use elf::endian::LittleEndian;
-use log::error;
-use uefi::boot::{image_handle, AllocateType, MemoryType};
+use crate::file_loader::{self, LoadedFile};
+use crate::random;
+use crate::raw_mem_map::{self, KERNEL_IMAGE_MEMORY};
+use boot_planner::kernel::{self, KernelLayout, KernelPlan};
+use log::{error, info, warn};
+use uefi::boot::{self, AllocateType, MemoryType};
 use uefi::mem::memory_map::{MemoryMap, MemoryMapOwned};
-use uefi::proto::media::file::FileInfo;
-use uefi::{
-    prelude::*,
-    proto::media::{
-        file::{self, File, FileAttribute},
-        fs,
-    },
-};
-
-const IDEAL_PHYSICAL_ADDRESS: u64 = 0x8000_0000;
-
-/// Loads the kernel ELF and returns an option for a tuple where the first item is the physical
-/// address of the kernel and the second item is the virtual address of the entry point.
-pub fn read_kernel(mem_map: &MemoryMapOwned) -> Option<(u64, u64)> {
-    let start_physical_address: u64 = find_physical_region(mem_map)?;
-
-    if start_physical_address == 0 {
-        error!("Error finding a suitable memory region to load the kernel");
-        return None;
-    }
+use uefi::Handle;
+
+/// The kernel file can't be larger than this (256 MiB).
+const MAX_KERNEL_FILE_SIZE: u64 = 0x1000_0000;
+
+/// The kernel after it was loaded in memory. All the segments are in a single physically contiguous
+/// block, at the same offsets from each other as in the virtual address space.
+pub struct KernelImage {
+    /// The physical address of the first page of the image.
+    phys_addr: u64,
+    /// Where the kernel is mapped.
+    layout: KernelLayout,
+}
 
-    let fs = get_file_handle();
-    if fs.is_none() {
-        return None;
+impl KernelImage {
+    pub fn phys_addr(&self) -> u64 {
+        self.phys_addr
     }
 
-    let mut fs = fs.unwrap();
-
-    let mut info_buffer: [u8; 4096] = [0; 4096];
-    let info: uefi::Result<&mut FileInfo, Option<usize>> =
-        fs.get_info::<FileInfo>(&mut info_buffer);
-    if info.is_err() {
-        let msg = info.err().unwrap();
-        error!("Error reading kernel file info {msg}.");
-        return None;
+    pub fn virt_addr(&self) -> u64 {
+        self.layout.virt_addr()
     }
 
-    let info: &mut FileInfo = info.unwrap();
-    let k_size = info.file_size() as usize;
-
-    let mut buffer: &mut [u8];
-    let k_space = boot::allocate_pages(
-        AllocateType::AnyPages,
-        MemoryType::BOOT_SERVICES_DATA, //we want to reclaim this memory later
-        (k_size / 0x1000) + 1,
-    );
-
-    let k_space = k_space.unwrap();
-    let k_space_addr = k_space.as_ptr();
-    unsafe {
-        buffer = core::slice::from_raw_parts_mut(k_space_addr, k_size);
+    pub fn page_count(&self) -> u64 {
+        self.layout.page_count()
     }
 
-    let read: Result<usize, uefi::Error> = fs.read(&mut buffer);
-    if read.is_err() {
-        let err_msg: uefi::Error = read.err().unwrap();
-        error!("Error reading kernel file: {err_msg}");
-        return None;
+    /// The virtual address of the entry point.
+    pub fn entry_point(&self) -> u64 {
+        self.layout.entry_point()
     }
 
-    let elf_data: Result<elf::ElfBytes<LittleEndian>, elf::ParseError> =
-        elf::ElfBytes::<LittleEndian>::minimal_parse(&buffer[..read.unwrap()]);
-    if elf_data.is_err() {
-        let err_msg: elf::ParseError = elf_data.err().unwrap();
-        error!("Error parsing kernel file: {err_msg}");
-        return None;
+    /// The difference between the address where the kernel is mapped and its link address
+    /// (wrapping, so a kernel moved to a lower address has a "negative" slide).
+    pub fn slide(&self) -> u64 {
+        self.layout.slide()
     }
 
-    let elf_data: elf::ElfBytes<'_, LittleEndian> = elf_data.unwrap();
-    let prog_headers = elf_data.segments();
-    if prog_headers.is_none() {
-        error!("Error parsing kernel file: no segments found");
-        return None;
+    pub fn layout(&self) -> &KernelLayout {
+        &self.layout
     }
 
-    let prog_headers = prog_headers.unwrap();
-
-    for ph in prog_headers {
-        if ph.p_type == elf::abi::PT_LOAD {
-            let dest: u64 = start_physical_address;
-            let size: usize = ph.p_memsz as usize;
-            let src_offset: usize = ph.p_offset as usize;
-            let src_size: usize = ph.p_filesz as usize;
-
-            let ptr = boot::allocate_pages(
-                AllocateType::Address(dest),
-                MemoryType::LOADER_DATA,
-                // (size + 0xFFF) / 0x1000,
-                (size / 0x1000) + 1,
-            );
-
-            if ptr.is_err() {
-                let err_msg: uefi::Error = ptr.err().unwrap();
-                error!("Error allocating kernel memory: {err_msg}");
-                return None;
-            }
-
-            //copy the segment code at the physical address previously allocated
-            unsafe {
-                core::ptr::copy_nonoverlapping(
-                    buffer[src_offset..].as_ptr(),
-                    dest as *mut u8,
-                    src_size,
-                );
-
-                //zero-out BSS after the code segment
-                if size > src_size {
-                    core::ptr::write_bytes(
-                        (dest as usize + src_size) as *mut u8,
-                        0,
-                        size - src_size,
-                    );
-                }
-            }
-        }
+    /// Returns the physical address where the given virtual address of the kernel was loaded.
+    pub fn virt_to_phys(&self, virt_addr: u64) -> u64 {
+        self.phys_addr + (virt_addr - self.layout.virt_addr())
     }
-
-    return Some((start_physical_address, elf_data.ehdr.e_entry));
 }
 
-fn find_physical_region(mem_map: &MemoryMapOwned) -> Option<u64> {
-    //check for the region between 0x8000_0000 and 0xffff_ffff, because that's the region where
-    //the kernel should generally be loaded
-    for entry in mem_map.entries() {
-        if entry.ty != MemoryType::CONVENTIONAL
-            || entry.ty != MemoryType::LOADER_DATA
-            || entry.ty != MemoryType::LOADER_CODE
-        {
-            continue;
-        }
-
-        //if there is a region that starts at 0x8000_0000 (IDEAL_PHYSICAL_ADDRESS) and is at least
-        //1 MiB in size, use 0x8000_0000
-        if entry.phys_start <= IDEAL_PHYSICAL_ADDRESS
-            && entry.phys_start + (entry.page_count * 0x1000) > 0x8100_0000
-        {
-            return Some(IDEAL_PHYSICAL_ADDRESS);
-        }
-    }
-
-    //otherwise find any suitable region
-    for entry in mem_map.entries() {
-        if entry.ty != MemoryType::CONVENTIONAL {
-            continue;
-        }
-
-        //needs to be at least 1 MiB
-        if entry.page_count >= 0x10_00_00 / 0x1000 {
-            return Some(entry.phys_start as u64);
-        }
+/// Reads the kernel file in memory, as it is (see [`read_kernel`] for loading it).
+pub fn load_kernel_file(volume: Handle, kernel_path: &str) -> Option<LoadedFile> {
+    let file: Result<LoadedFile, uefi::Error> = file_loader::load_file_from(
+        volume,
+        kernel_path,
+        MemoryType::BOOT_SERVICES_DATA, //we want to reclaim this memory later
+        MAX_KERNEL_FILE_SIZE,
+    );
+    if file.is_err() {
+        let err_msg: uefi::Error = file.err().unwrap();
+        error!("Error reading kernel file {kernel_path}: {err_msg}");
+        return None;
     }
 
-    return None;
+    return Some(file.unwrap());
 }
 
-fn get_file_handle() -> Option<file::RegularFile> {
-    let img: Result<boot::ScopedProtocol<fs::SimpleFileSystem>, uefi::Error> =
-        boot::get_image_file_system(image_handle());
+/// Loads the kernel ELF where the [`boot_planner`] decided: every PT_LOAD segment is copied at its
+/// offset from the lowest segment, so the image can be mapped as a whole. If the kernel is
+/// relocatable (a static PIE) and `kaslr` is true, it is moved at a random virtual address;
+/// otherwise it is mapped at its link address. Returns None if the kernel could not be loaded.
+pub fn read_kernel(mem_map: &MemoryMapOwned, file_data: &[u8], kaslr: bool) -> Option<KernelImage> {
+    let mut kaslr_seed: Option<u64> = None;
+    if kaslr {
+        kaslr_seed = Some(random::random_u64());
+    }
 
-    if img.is_err() {
-        let err_msg: uefi::Error = img.err().unwrap();
-        error!("Error reading kernel file: {err_msg}");
+    let regions = mem_map.entries().map(raw_mem_map::to_region);
+    let plan: Result<KernelPlan, &'static str> =
+        kernel::plan_kernel(file_data, regions, kaslr_seed);
+    if plan.is_err() {
+        let err_msg: &str = plan.err().unwrap();
+        error!("Error loading the kernel: {err_msg}");
         return None;
     }
 
-    let mut img: boot::ScopedProtocol<fs::SimpleFileSystem> = img.unwrap();
-    let root_dir: Result<file::Directory, uefi::Error> = img.open_volume();
-    if root_dir.is_err() {
-        let err_msg: uefi::Error = root_dir.err().unwrap();
-        error!("Error reading kernel file: {err_msg}");
-        return None;
+    let plan: KernelPlan = plan.unwrap();
+    if kaslr && !plan.layout().is_relocatable() {
+        info!("The kernel is not relocatable, KASLR is disabled.");
+    } else if kaslr && kernel::kaslr_slot_count(plan.layout()).is_none() {
+        warn!("The kernel is too large for KASLR, loading it at its link address.");
     }
 
-    let mut root_dir: file::Directory = root_dir.unwrap();
-    let fs: Result<file::FileHandle, uefi::Error> = root_dir.open(
-        cstr16!("boot\\kernel.elf"),
-        file::FileMode::Read,
-        FileAttribute::empty(),
+    let ptr = boot::allocate_pages(
+        AllocateType::Address(plan.phys_addr()),
+        KERNEL_IMAGE_MEMORY,
+        plan.page_count() as usize,
     );
-    if fs.is_err() {
-        let err_msg: uefi::Error = fs.err().unwrap();
-        error!("Error reading kernel file: {err_msg}");
+    if ptr.is_err() {
+        let err_msg: uefi::Error = ptr.err().unwrap();
+        error!("Error allocating kernel memory: {err_msg}");
         return None;
     }
 
-    let fs: file::FileHandle = fs.unwrap();
-    let fs: Option<file::RegularFile> = fs.into_regular_file();
-    if fs.is_none() {
-        error!("Error reading kernel file: not a file");
+    //the pages are zeroed before they become a slice, so it doesn't cover uninitialized memory
+    let image: &mut [u8] = unsafe {
+        core::ptr::write_bytes(
+            plan.phys_addr() as *mut u8,
+            0,
+            (plan.page_count() * 0x1000) as usize,
+        );
+        core::slice::from_raw_parts_mut(
+            plan.phys_addr() as *mut u8,
+            (plan.page_count() * 0x1000) as usize,
+        )
+    };
+    let layout: Result<KernelLayout, &'static str> = plan.load(file_data, image);
+    if layout.is_err() {
+        let err_msg: &str = layout.err().unwrap();
+        error!("Error loading the kernel: {err_msg}");
         return None;
     }
 
-    return Some(fs.unwrap());
+    return Some(KernelImage {
+        phys_addr: plan.phys_addr(),
+        layout: layout.unwrap(),
+    });
 }
@@ -0,0 +1,63 @@
+use crate::raw_mem_map::KERNEL_STACK_MEMORY;
+use core::ptr::NonNull;
+use log::error;
+use uefi::boot;
+use uefi::boot::AllocateType;
+
+/// The stack the kernel starts on, mapped right above [`boot_info::KERNEL_STACK_GUARD_ADDRESS`].
+pub struct KernelStack {
+    phys_addr: u64,
+    page_count: u64,
+}
+
+impl KernelStack {
+    /// The physical address of the lowest page of the stack.
+    pub fn phys_addr(&self) -> u64 {
+        self.phys_addr
+    }
+
+    pub fn page_count(&self) -> u64 {
+        self.page_count
+    }
+
+    /// The lowest virtual address of the stack.
+    pub fn bottom(&self) -> u64 {
+        boot_info::KERNEL_STACK_GUARD_ADDRESS + 0x1000
+    }
+
+    /// The virtual address right above the stack.
+    pub fn top(&self) -> u64 {
+        self.bottom() + self.page_count * 0x1000
+    }
+}
+
+/// Allocates the kernel stack with the given size in bytes (rounded up to pages). The memory is
+/// zeroed, so the kernel can see how much of its stack was ever used.
+pub fn alloc_kernel_stack(size: u64) -> Option<KernelStack> {
+    if size == 0 || size > boot_info::KERNEL_STACK_MAX_SIZE {
+        error!("Error allocating the kernel stack: invalid size ({size} bytes).");
+        return None;
+    }
+
+    let page_count: u64 = size.div_ceil(0x1000);
+    let addr: uefi::Result<NonNull<u8>> = boot::allocate_pages(
+        AllocateType::AnyPages,
+        KERNEL_STACK_MEMORY,
+        page_count as usize,
+    );
+    if addr.is_err() {
+        let err_msg: uefi::Error = addr.err().unwrap();
+        error!("Error allocating memory for the kernel stack: {err_msg}");
+        return None;
+    }
+
+    let addr: NonNull<u8> = addr.unwrap();
+    unsafe {
+        core::ptr::write_bytes(addr.as_ptr(), 0, page_count as usize * 0x1000);
+    }
+
+    return Some(KernelStack {
+        phys_addr: addr.as_ptr() as u64,
+        page_count,
+    });
+}
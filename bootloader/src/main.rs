@@ -2,10 +2,14 @@
 #![no_main]
 
 use crate::paging::PageTableInfo;
+use boot_info::handoff::{
+    boot_stages, CmdlineTag, DirectMapTag, FirmwareTablesTag, HandoffWriter, InitrdTag,
+    KernelImageTag, KernelStackTag, MemoryMapTag, ModulesTag, PageTablesTag, UefiRuntimeTag,
+};
 use boot_info::memory_map::MemoryMapEntry;
 use core::ptr::NonNull;
 use log::{error, info, warn};
-use uefi::mem::memory_map::{MemoryMap, MemoryMapMut, MemoryMapOwned};
+use uefi::mem::memory_map::{MemoryMapMut, MemoryMapOwned};
 use uefi::{
     boot::MemoryType,
     prelude::*,
@@ -13,54 +17,159 @@ use uefi::{
 };
 use x86_64;
 
+use crate::boot_timeline::BootTimeline;
+use crate::failure_screen::Failure;
+use crate::file_loader::LoadedFile;
+use crate::firmware_tables::FirmwareTables;
+use crate::kernel_reader::KernelImage;
+use crate::kernel_stack::KernelStack;
+use crate::modules::LoadedModules;
 #[allow(dead_code)]
-use crate::sys_config_reader::SystemConfig;
-
+use crate::sys_config_reader::{BootEntry, SystemConfig};
+use crate::uefi_runtime::RuntimeLayout;
+
+mod boot_attempts;
+mod boot_log;
+mod boot_menu;
+mod boot_timeline;
+mod cmdline;
+mod failure_screen;
+mod file_loader;
+mod firmware_tables;
+mod fixed_string;
 mod graphics_config;
+mod handoff;
+mod initrd;
+mod integrity;
 mod kernel_loader;
 mod kernel_reader;
+mod kernel_stack;
+mod memtest;
+mod modules;
 mod paging;
 mod phys_memory_map;
+mod random;
 mod raw_mem_map;
+mod serial;
+mod splash;
 mod sys_config_reader;
+mod uefi_runtime;
 
 fn panic_fn(err: uefi::Error) -> ! {
-    error!("Fatal error: {err}. \r\nSystem halted. You can turn off the device now.");
-
-    loop {
-        boot::stall(1_000_000);
-    }
+    failure_screen::show(&Failure {
+        code: "UEFI_ERROR",
+        subject: None,
+        status: Some(err.status()),
+    });
 }
 
 fn panic_fn_str(err: &str) -> ! {
-    error!("Fatal error: {err}. \r\nSystem halted. You can turn off the device now.");
-
-    loop {
-        boot::stall(1_000_000);
-    }
+    failure_screen::show(&Failure {
+        code: err,
+        subject: None,
+        status: None,
+    });
 }
 
 #[entry]
 fn main() -> Status {
+    let mut timeline: BootTimeline = BootTimeline::start();
     uefi::helpers::init().unwrap();
+    boot_log::init();
     info!("Starting boot proces...");
 
-    let mem_map: MemoryMapOwned = get_efi_mmap();
     let config: SystemConfig = sys_config_reader::read_config().unwrap_or(SystemConfig::default());
+    boot_log::set_level(config.log_level());
+    timeline.record(boot_stages::CONFIG_READ);
+
+    let default_entry: usize = boot_attempts::choose_default_entry(&config);
+    let entry: BootEntry = boot_menu::choose_entry(&config, default_entry);
+    timeline.record(boot_stages::ENTRY_CHOSEN);
+    info!("Booting {}...", entry.title());
+
+    //before anything is loaded, so the faulty memory is reserved before it can be used
+    if config.memtest() {
+        memtest::run();
+    }
+
+    let volume: Result<Handle, uefi::Error> = file_loader::find_volume(entry.volume());
+    if volume.is_err() {
+        let err_msg: uefi::Error = volume.err().unwrap();
+        error!("Error finding {}: {err_msg}", entry.volume());
+        failure_screen::show(&Failure {
+            code: "KERNEL_VOLUME_NOT_FOUND",
+            subject: Some(entry.volume()),
+            status: Some(err_msg.status()),
+        });
+    }
+
+    let volume: Handle = volume.unwrap();
+    let kernel_file: Option<LoadedFile> =
+        kernel_reader::load_kernel_file(volume, entry.kernel_path());
+    if kernel_file.is_none() {
+        failure_screen::show(&Failure {
+            code: "KERNEL_NOT_LOADED",
+            subject: Some(&entry.kernel_path()),
+            status: None,
+        });
+    }
 
-    let k_load_data: Option<(u64, u64)> = kernel_reader::read_kernel(&mem_map);
-    if k_load_data.is_none() {
-        panic_fn_str("KERNEL_NOT_LOADED");
+    let kernel_file: LoadedFile = kernel_file.unwrap();
+    if !integrity::verify_file(
+        volume,
+        entry.kernel_path(),
+        kernel_file.data(),
+        entry.kernel_sha256(),
+    ) {
+        failure_screen::show(&Failure {
+            code: "KERNEL_INTEGRITY_CHECK_FAILED",
+            subject: Some(&entry.kernel_path()),
+            status: None,
+        });
     }
 
-    let k_load_data: (u64, u64) = k_load_data.unwrap();
-    let k_physical_address: u64 = k_load_data.0;
-    let k_entry_point: u64 = k_load_data.1;
+    //taken after the file is loaded, so its pages aren't chosen for the kernel image
+    let mem_map: MemoryMapOwned = get_efi_mmap();
+    let kernel: Option<KernelImage> =
+        kernel_reader::read_kernel(&mem_map, kernel_file.data(), config.kaslr());
+    if kernel.is_none() {
+        failure_screen::show(&Failure {
+            code: "KERNEL_NOT_LOADED",
+            subject: Some(&entry.kernel_path()),
+            status: None,
+        });
+    }
+
+    //the segments were copied, the file itself is no longer needed
+    kernel_file.free();
+    let kernel: KernelImage = kernel.unwrap();
+    timeline.record(boot_stages::KERNEL_READ);
+    let initrd: Option<LoadedFile> = initrd::load_initrd(volume, entry.initrd_path());
+    if initrd.as_ref().is_some_and(|initrd| {
+        !integrity::verify_file(
+            volume,
+            entry.initrd_path(),
+            initrd.data(),
+            entry.initrd_sha256(),
+        )
+    }) {
+        failure_screen::show(&Failure {
+            code: "INITRD_INTEGRITY_CHECK_FAILED",
+            subject: Some(&entry.initrd_path()),
+            status: None,
+        });
+    }
+
+    let modules: Option<LoadedModules> = modules::load_modules(volume, config.modules());
+    timeline.record(boot_stages::FILES_READ);
+    let firmware_tables: FirmwareTables = firmware_tables::find_firmware_tables();
 
     let mut fb_data: Option<boot_info::framebuffer::FramebufferData> =
         graphics_config::set_appropriate_framebuffer(
-            config.preferred_width(),
-            config.preferred_height(),
+            entry.preferred_width(),
+            entry.preferred_height(),
+            config.log_video_modes(),
+            config.prefer_rgb(),
         );
 
     if fb_data.is_none() {
@@ -79,13 +188,20 @@ fn main() -> Status {
     let width: u32 = fb_data.width();
     let height: u32 = fb_data.height();
     info!("Switched to graphics mode with resolution {width}x{height}.");
+    timeline.record(boot_stages::GOP_SET);
+
+    //the splash screen stays until the kernel draws on the framebuffer, so don't let the boot
+    //messages scroll over it (warnings and errors are still shown)
+    let fb_base: *mut u8 = get_gop().frame_buffer().as_mut_ptr();
+    let fb_size: u64 = get_gop().frame_buffer().size() as u64;
+    if splash::show_splash(config.splash_path(), &fb_data, fb_base) {
+        boot_log::set_console_level(config.log_level().min(log::LevelFilter::Warn));
+    }
 
-    //let _ = draw_test();
-
-    //here we don't need the updated map, just a sorted one, so we can determine the RAM size
-    let mut mem_map = mem_map;
-    mem_map.sort();
-    let pmm_sections_array: u64 = phys_memory_map::allocate_memory_for_pmm(&mem_map);
+    //here we don't need the updated map, the RAM size doesn't change
+    let pmm_sections_array: u64 =
+        phys_memory_map::allocate_memory_for_pmm(&mem_map, (fb_base as u64, fb_size));
+    timeline.record(boot_stages::PMM_ALLOCATED);
 
     let mem_map: MemoryMapOwned = get_efi_mmap();
     let raw_mem_map_result: Option<(u64, u32)> = raw_mem_map::alloc_memory_for_map(&mem_map);
@@ -97,53 +213,60 @@ fn main() -> Status {
     let raw_mem_map_result: (u64, u32) = raw_mem_map_result.unwrap();
     let raw_mem_map_addr: u64 = raw_mem_map_result.0;
     let raw_mem_map_page_count: u32 = raw_mem_map_result.1;
+
+    let cmdline_addr: Option<u64> = cmdline::alloc_cmdline(entry.cmdline());
+    if cmdline_addr.is_none() {
+        panic_fn_str("CMDLINE_ERROR");
+    }
+
+    let cmdline_addr: u64 = cmdline_addr.unwrap();
+    let kernel_stack: Option<KernelStack> =
+        kernel_stack::alloc_kernel_stack(config.kernel_stack_size());
+    if kernel_stack.is_none() {
+        panic_fn_str("KERNEL_STACK_ERROR");
+    }
+
+    let kernel_stack: KernelStack = kernel_stack.unwrap();
+    let handoff_addr: Option<u64> = handoff::alloc_handoff();
+    if handoff_addr.is_none() {
+        panic_fn_str("HANDOFF_ERROR");
+    }
+
+    let handoff_addr: u64 = handoff_addr.unwrap();
+
+    //saving the log allocates memory, so do it before the final memory map is read
+    if !boot_log::save() {
+        warn!("Error saving the boot log to the ESP.");
+    }
+
     let mem_map: MemoryMapOwned = get_efi_mmap();
+    let runtime_layout: Option<RuntimeLayout> = uefi_runtime::plan_runtime_layout(&mem_map);
+    if runtime_layout.is_none() {
+        warn!("The UEFI runtime services don't fit in their window, they won't be remapped.");
+    }
 
     let page_table_info: Option<PageTableInfo> = paging::setup_paging(
         &mem_map,
         &mut get_gop().frame_buffer(),
-        k_physical_address,
+        &kernel,
+        &kernel_stack,
         raw_mem_map_addr,
         pmm_sections_array,
+        cmdline_addr,
+        initrd.as_ref(),
+        modules.as_ref(),
+        &firmware_tables,
+        runtime_layout.as_ref(),
     );
     if page_table_info.is_none() {
         panic_fn_str("MEMORY_PAGING_NOT_MAPPED");
     }
 
     let page_table_info: PageTableInfo = page_table_info.unwrap();
+    timeline.record(boot_stages::PAGING_SET);
 
     info!("Paging setup complete");
-
-    let mut mem_map_size: u32 = 0;
-    unsafe {
-        let mut final_mem_map: MemoryMapOwned =
-            boot::exit_boot_services(Some(MemoryType::LOADER_DATA));
-        
-        final_mem_map.sort();
-        let mut map_writer = raw_mem_map_addr as *mut MemoryMapEntry;
-
-        for entry in final_mem_map.entries() {
-            //the EFI memory map now has so many additional entries (even with that +10 "safe zone"),
-            //that we can no longer give all the necessary information
-            if mem_map_size >= raw_mem_map_page_count * 0x1000 {
-                error!(
-                    "Error: EFI memory map is suddenly larger than expected for the direct memory map."
-                );
-                panic_fn_str("EFI_MEM_MAP_UNEXPECTEDLY_LARGE");
-            }
-
-            *map_writer = MemoryMapEntry::new(
-                boot_info::memory_map::MemoryType::from(entry.ty.0),
-                entry.att.bits(),
-                entry.phys_start,
-                entry.virt_start,
-                entry.page_count,
-            );
-
-            map_writer = map_writer.add(1);
-            mem_map_size += 40;
-        }
-    }
+    boot_log::exit_boot_services();
 
     let efi_sys_table = uefi::table::system_table_raw();
     if efi_sys_table.is_none() {
@@ -151,23 +274,120 @@ fn main() -> Status {
         panic_fn_str("EFI_SYS_TABLE_NOT_FOUND");
     }
 
-    let mut efi_rs_addr: u64 = 0;
-    let efi_sys_table = efi_sys_table.unwrap();
-    unsafe {
-        let efi_sys_table = efi_sys_table.read_unaligned();
-        efi_rs_addr = efi_sys_table.runtime_services as u64;
+    //read it now, the uefi crate switches to the virtual address after SetVirtualAddressMap
+    let efi_sys_table_addr: u64 = efi_sys_table.unwrap().as_ptr() as u64;
+    let efi_rs_addr: u64 = unsafe {
+        let efi_sys_table = efi_sys_table.unwrap().read_unaligned();
+        efi_sys_table.runtime_services as u64
+    };
+
+    let mut final_mem_map: MemoryMapOwned =
+        unsafe { boot::exit_boot_services(Some(MemoryType::LOADER_DATA)) };
+    timeline.record(boot_stages::BOOT_SERVICES_EXITED);
+    final_mem_map.sort();
+
+    let mem_map_size: Option<u32> = unsafe {
+        raw_mem_map::write_kernel_map(
+            &final_mem_map,
+            raw_mem_map_addr,
+            raw_mem_map_page_count,
+            (fb_base as u64, fb_size),
+            &firmware_tables,
+            runtime_layout.as_ref(),
+        )
+    };
+    //the EFI memory map now has so many additional entries (even with that +10 "safe zone"),
+    //that we can no longer give all the necessary information
+    if mem_map_size.is_none() {
+        error!("Error: EFI memory map is suddenly larger than expected for the direct memory map.");
+        panic_fn_str("EFI_MEM_MAP_UNEXPECTEDLY_LARGE");
+    }
+
+    let mem_map_size: u32 = mem_map_size.unwrap();
+
+    //if this fails, the firmware still expects physical addresses, and the kernel is told so
+    let mut uefi_runtime_tag: Option<UefiRuntimeTag> = None;
+    if let Some(layout) = runtime_layout.as_ref() {
+        if uefi_runtime::set_virtual_address_map(layout, &final_mem_map, efi_sys_table_addr) {
+            uefi_runtime_tag = Some(UefiRuntimeTag {
+                runtime_services_virt_addr: layout.phys_to_virt(efi_rs_addr).unwrap_or(0),
+                system_table_virt_addr: layout.phys_to_virt(efi_sys_table_addr).unwrap_or(0),
+            });
+        }
     }
 
-    let k_params = boot_info::KParams {
-        fb_data,
-        memory_map_size: mem_map_size,
-        page_table_num_entries: page_table_info.num_entries(),
-        uefi_rs_phys_addr: efi_rs_addr,
+    //the handoff page is identity-mapped and was zeroed, so it's a valid 8-byte aligned buffer
+    let handoff_buffer: &mut [u8] = unsafe {
+        core::slice::from_raw_parts_mut(
+            handoff_addr as *mut u8,
+            boot_info::handoff::HANDOFF_MAX_SIZE,
+        )
     };
+    let mut handoff_writer: HandoffWriter = HandoffWriter::new(handoff_buffer).unwrap();
+    let mut success: bool = handoff_writer.push(&fb_data)
+        && handoff_writer.push(&MemoryMapTag {
+            virt_addr: boot_info::MEM_MAP_VIRTUAL_ADDRESS,
+            size: mem_map_size,
+            entry_size: size_of::<MemoryMapEntry>() as u32,
+        })
+        && handoff_writer.push(&PageTablesTag {
+            num_entries: page_table_info.num_entries(),
+        })
+        && handoff_writer.push(&DirectMapTag {
+            offset: boot_info::PHYS_DIRECT_MAP_ADDRESS,
+            size: page_table_info.direct_map_size(),
+        })
+        && handoff_writer.push(&CmdlineTag {
+            virt_addr: boot_info::CMDLINE_VIRTUAL_ADDRESS,
+            size: entry.cmdline().len() as u32,
+            reserved: 0,
+        })
+        && handoff_writer.push(&KernelImageTag {
+            virt_base: kernel.virt_addr(),
+            slide: kernel.slide(),
+        })
+        && handoff_writer.push(&KernelStackTag {
+            bottom: kernel_stack.bottom(),
+            top: kernel_stack.top(),
+        })
+        && handoff_writer.push(&FirmwareTablesTag {
+            acpi_rsdp_phys_addr: firmware_tables.acpi_rsdp(),
+            smbios_phys_addr: firmware_tables.smbios_entry(),
+            uefi_rs_phys_addr: efi_rs_addr,
+        })
+        && handoff_writer.push(&timeline.tag());
+
+    if let Some(initrd) = initrd.as_ref() {
+        success = success
+            && handoff_writer.push(&InitrdTag {
+                virt_addr: boot_info::INITRD_VIRTUAL_ADDRESS,
+                size: initrd.size(),
+            });
+    }
+
+    if let Some(modules) = modules.as_ref() {
+        success = success
+            && handoff_writer.push(&ModulesTag {
+                table_virt_addr: boot_info::MODULES_VIRTUAL_ADDRESS,
+                count: modules.modules().len() as u32,
+                entry_size: core::mem::size_of::<boot_info::modules::ModuleEntry>() as u32,
+            });
+    }
+
+    if let Some(uefi_runtime_tag) = uefi_runtime_tag.as_ref() {
+        success = success && handoff_writer.push(uefi_runtime_tag);
+    }
+
+    if !success {
+        panic_fn_str("HANDOFF_TOO_LARGE");
+    }
+
+    handoff_writer.finish();
     kernel_loader::boot_kernel(
-        k_entry_point,
+        kernel.entry_point(),
         x86_64::PhysAddr::new(page_table_info.phys_addr()),
-        k_params,
+        handoff_addr,
+        kernel_stack.top(),
     );
 }
 
@@ -192,7 +412,11 @@ fn get_efi_mmap() -> MemoryMapOwned {
     if mem_map.is_err() {
         let err_msg: uefi::Error = mem_map.err().unwrap();
         error!("Error getting the memory map: {err_msg}");
-        panic_fn_str("MMAP_ERROR");
+        failure_screen::show(&Failure {
+            code: "MMAP_ERROR",
+            subject: None,
+            status: Some(err_msg.status()),
+        });
     }
 
     let mut mem_map: MemoryMapOwned = mem_map.unwrap();
@@ -200,38 +424,3 @@ fn get_efi_mmap() -> MemoryMapOwned {
 
     return mem_map;
 }
-
-//TODO: this will write a logo later
-
-// fn draw_test() -> uefi::Result {
-//     let gop_handle: Handle = boot::get_handle_for_protocol::<GraphicsOutput>()?;
-//     let gop: boot::ScopedProtocol<GraphicsOutput> =
-//         boot::open_protocol_exclusive::<GraphicsOutput>(gop_handle)?;
-
-//     draw_rect(gop, 200, 20, 350, 450, 0xff_00_00);
-//     return Ok(());
-// }
-
-// fn draw_rect(
-//     mut gop: ScopedProtocol<GraphicsOutput>,
-//     x: u32,
-//     y: u32,
-//     width: u32,
-//     height: u32,
-//     color: u32,
-// ) {
-//     let fb: *mut u8 = gop.frame_buffer().as_mut_ptr();
-//     let pitch: u32 = gop.current_mode_info().stride() as u32;
-
-//     unsafe {
-//         for y_pos in y..(y + height) {
-//             for x_pos in x..(x + width) {
-//                 let base_addr: *mut u8 = fb.add((4 * y_pos * pitch + 4 * x_pos) as usize);
-//                 *base_addr.add(0) = color as u8;
-//                 *base_addr.add(1) = (color >> 8) as u8;
-//                 *base_addr.add(2) = (color >> 16) as u8;
-//                 *base_addr.add(3) = (color >> 24) as u8;
-//             }
-//         }
-//     }
-// }
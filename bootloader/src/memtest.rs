@@ -0,0 +1,173 @@
+use crate::raw_mem_map::BAD_MEMORY;
+use core::ptr::NonNull;
+use log::{info, warn};
+use uefi::boot::{self, AllocateType, MemoryType};
+use uefi::mem::memory_map::{MemoryMap, MemoryMapOwned};
+
+/// The patterns of the moving inversions test, each one is also tested inverted.
+const PATTERNS: [u64; 2] = [0, 0x5555_5555_5555_5555];
+/// The free memory is allocated and tested in chunks of at most this many pages (16 MiB).
+const CHUNK_PAGES: u64 = 0x1000;
+/// More failing ranges than this are not remembered (the memory is probably beyond saving anyway).
+const MAX_BAD_RANGES: usize = 64;
+
+/// The failing memory found so far, as page-aligned (start, end) ranges.
+struct BadRanges {
+    ranges: [(u64, u64); MAX_BAD_RANGES],
+    count: usize,
+    /// Whether some failing pages didn't fit in `ranges`.
+    is_full: bool,
+}
+
+impl BadRanges {
+    /// Adds the page containing `addr`.
+    fn add(&mut self, addr: u64) {
+        let page: u64 = addr & !0xfff;
+        let ranges: &mut [(u64, u64)] = &mut self.ranges[..self.count];
+        if ranges
+            .iter()
+            .any(|(start, end)| (*start..*end).contains(&page))
+        {
+            return;
+        }
+
+        let adjacent: Option<&mut (u64, u64)> = ranges
+            .iter_mut()
+            .find(|(start, end)| *end == page || *start == page + 0x1000);
+        if let Some(range) = adjacent {
+            range.0 = range.0.min(page);
+            range.1 = range.1.max(page + 0x1000);
+            return;
+        }
+
+        if self.count >= MAX_BAD_RANGES {
+            self.is_full = true;
+            return;
+        }
+
+        self.ranges[self.count] = (page, page + 0x1000);
+        self.count += 1;
+    }
+}
+
+/// Tests all the free (conventional) memory with the address-in-address and moving inversions
+/// patterns. The failing pages are allocated and never freed, so nothing is loaded there and they
+/// are given to the kernel as unusable memory.
+pub fn run() {
+    let mem_map: uefi::Result<MemoryMapOwned> = boot::memory_map(MemoryType::LOADER_DATA);
+    if mem_map.is_err() {
+        let err_msg: uefi::Error = mem_map.err().unwrap();
+        warn!("Error reading the memory map, the memory test is skipped: {err_msg}");
+        return;
+    }
+
+    let mem_map: MemoryMapOwned = mem_map.unwrap();
+    let mut bad_ranges: BadRanges = BadRanges {
+        ranges: [(0, 0); MAX_BAD_RANGES],
+        count: 0,
+        is_full: false,
+    };
+    let mut tested_pages: u64 = 0;
+
+    info!("Testing the memory, this can take a while...");
+    for entry in mem_map.entries() {
+        if entry.ty != MemoryType::CONVENTIONAL {
+            continue;
+        }
+
+        //page 0 can't be allocated (it would be a null pointer)
+        let mut start: u64 = entry.phys_start.max(0x1000);
+        let end: u64 = entry.phys_start + entry.page_count * 0x1000;
+        while start < end {
+            let page_count: u64 = ((end - start) / 0x1000).min(CHUNK_PAGES);
+
+            //the memory is allocated while it's tested, so the firmware can't use it meanwhile
+            let chunk: uefi::Result<NonNull<u8>> = boot::allocate_pages(
+                AllocateType::Address(start),
+                MemoryType::LOADER_DATA,
+                page_count as usize,
+            );
+            if chunk.is_ok() {
+                test_chunk(start, page_count, &mut bad_ranges);
+                tested_pages += page_count;
+
+                unsafe {
+                    let _ = boot::free_pages(chunk.unwrap(), page_count as usize);
+                }
+            }
+
+            start += page_count * 0x1000;
+        }
+    }
+
+    for (start, end) in bad_ranges.ranges[..bad_ranges.count].iter() {
+        warn!("Memory test: the memory from {start:#x} to {end:#x} is faulty.");
+
+        let result: uefi::Result<NonNull<u8>> = boot::allocate_pages(
+            AllocateType::Address(*start),
+            BAD_MEMORY,
+            ((end - start) / 0x1000) as usize,
+        );
+        if result.is_err() {
+            warn!("Error reserving the faulty memory at {start:#x}, it might still be used.");
+        }
+    }
+
+    if bad_ranges.is_full {
+        warn!("Memory test: too many faulty ranges, only the first {MAX_BAD_RANGES} are reserved.");
+    }
+
+    info!(
+        "Memory test done: {} MiB tested, {} faulty range(s).",
+        tested_pages / 256,
+        bad_ranges.count
+    );
+}
+
+/// Runs the tests on the given pages, which must be allocated, and records the failing ones.
+fn test_chunk(start: u64, page_count: u64, bad_ranges: &mut BadRanges) {
+    let base: *mut u64 = start as *mut u64;
+    let word_count: usize = (page_count * 0x1000 / 8) as usize;
+
+    //volatile accesses, so the compiler doesn't assume the values that were just written are read
+    unsafe {
+        //address in address: finds the address lines that are stuck or shorted together
+        for i in 0..word_count {
+            let word: *mut u64 = base.add(i);
+            word.write_volatile(word as u64);
+        }
+
+        for i in 0..word_count {
+            let word: *mut u64 = base.add(i);
+            if word.read_volatile() != word as u64 {
+                bad_ranges.add(word as u64);
+            }
+        }
+
+        //moving inversions: finds the bits that are stuck or that are changed by writes to their
+        //neighbors
+        for pattern in PATTERNS {
+            for i in 0..word_count {
+                base.add(i).write_volatile(pattern);
+            }
+
+            for i in 0..word_count {
+                let word: *mut u64 = base.add(i);
+                if word.read_volatile() != pattern {
+                    bad_ranges.add(word as u64);
+                }
+
+                word.write_volatile(!pattern);
+            }
+
+            for i in (0..word_count).rev() {
+                let word: *mut u64 = base.add(i);
+                if word.read_volatile() != !pattern {
+                    bad_ranges.add(word as u64);
+                }
+
+                word.write_volatile(pattern);
+            }
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use crate::file_loader::{self, LoadedFile};
+use crate::raw_mem_map::BOOT_DATA_MEMORY;
+use crate::sys_config_reader::ModuleConfig;
+use boot_info::modules::{ModuleEntry, MAX_MODULES};
+use core::ptr::NonNull;
+use log::{error, info};
+use uefi::boot::{self, AllocateType};
+use uefi::{Handle, Status};
+
+/// A module in memory: where it is mapped for the kernel, where it was loaded and how many pages it
+/// takes.
+#[derive(Clone, Copy)]
+pub struct LoadedModule {
+    virt_addr: u64,
+    phys_addr: u64,
+    page_count: u64,
+}
+
+impl LoadedModule {
+    pub fn virt_addr(&self) -> u64 {
+        self.virt_addr
+    }
+
+    pub fn phys_addr(&self) -> u64 {
+        self.phys_addr
+    }
+
+    pub fn page_count(&self) -> u64 {
+        self.page_count
+    }
+}
+
+/// The modules that were loaded, along with the page holding their table (mapped at
+/// [`boot_info::MODULES_VIRTUAL_ADDRESS`]).
+pub struct LoadedModules {
+    table_phys_addr: u64,
+    modules: [LoadedModule; MAX_MODULES],
+    count: usize,
+}
+
+impl LoadedModules {
+    /// The physical address of the page holding the table.
+    pub fn table_phys_addr(&self) -> u64 {
+        self.table_phys_addr
+    }
+
+    pub fn modules(&self) -> &[LoadedModule] {
+        &self.modules[..self.count]
+    }
+}
+
+/// Loads the modules from the given volume and writes their table. The modules are optional, so the
+/// ones that can't be loaded are reported and skipped. Returns None if there are no modules at all.
+pub fn load_modules(volume: Handle, configs: &[ModuleConfig]) -> Option<LoadedModules> {
+    if configs.is_empty() {
+        return None;
+    }
+
+    let table: uefi::Result<NonNull<u8>> =
+        boot::allocate_pages(AllocateType::AnyPages, BOOT_DATA_MEMORY, 1);
+    if table.is_err() {
+        let err_msg: uefi::Error = table.err().unwrap();
+        error!("Error allocating memory for the table of modules, no module is loaded: {err_msg}");
+        return None;
+    }
+
+    let table: NonNull<u8> = table.unwrap();
+    unsafe {
+        core::ptr::write_bytes(table.as_ptr(), 0, 0x1000);
+    }
+
+    let mut loaded: LoadedModules = LoadedModules {
+        table_phys_addr: table.as_ptr() as u64,
+        modules: [LoadedModule {
+            virt_addr: 0,
+            phys_addr: 0,
+            page_count: 0,
+        }; MAX_MODULES],
+        count: 0,
+    };
+    //the first page of the range holds the table
+    let mut next_virt_addr: u64 = boot_info::MODULES_VIRTUAL_ADDRESS + 0x1000;
+    let table_entries: *mut ModuleEntry = table.as_ptr() as *mut ModuleEntry;
+
+    for config in configs {
+        let space_left: u64 =
+            boot_info::MODULES_VIRTUAL_ADDRESS + boot_info::MODULES_MAX_SIZE - next_virt_addr;
+        let file: Result<LoadedFile, uefi::Error> =
+            file_loader::load_file_from(volume, config.path(), BOOT_DATA_MEMORY, space_left);
+        if file.is_err() {
+            let err: uefi::Error = file.err().unwrap();
+            match err.status() {
+                Status::BUFFER_TOO_SMALL => error!(
+                    "Error reading the module {}: it doesn't fit in the {} MiB left for modules.",
+                    config.name(),
+                    space_left / 0x10_0000
+                ),
+                _ => error!(
+                    "Error reading the module {} from {}: {err}",
+                    config.name(),
+                    config.path()
+                ),
+            }
+
+            continue;
+        }
+
+        let file: LoadedFile = file.unwrap();
+        //the lengths were checked when the config was read
+        let entry: ModuleEntry =
+            ModuleEntry::new(next_virt_addr, file.size(), config.name(), config.cmdline()).unwrap();
+        unsafe {
+            table_entries.add(loaded.count).write(entry);
+        }
+
+        loaded.modules[loaded.count] = LoadedModule {
+            virt_addr: next_virt_addr,
+            phys_addr: file.phys_addr(),
+            page_count: file.page_count(),
+        };
+        loaded.count += 1;
+        next_virt_addr += file.page_count() * 0x1000;
+
+        info!(
+            "Loaded the module {} ({} KiB).",
+            config.name(),
+            file.size() / 1024
+        );
+    }
+
+    return Some(loaded);
+}
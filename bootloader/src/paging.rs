@@ -1,13 +1,21 @@
+use crate::file_loader::LoadedFile;
+use crate::firmware_tables::FirmwareTables;
+use crate::kernel_reader::KernelImage;
+use crate::kernel_stack::KernelStack;
+use crate::modules::LoadedModules;
+use crate::raw_mem_map::{self, PAGE_TABLES_MEMORY};
+use crate::uefi_runtime::RuntimeLayout;
+use boot_planner::kernel::PagePermissions;
+use boot_planner::paging::{can_use_page_size, SIZE_1_GIB, SIZE_2_MIB};
 use core::num::NonZero;
 use core::ptr::NonNull;
-use log::{error, info};
+use log::{error, info, warn};
 use uefi::boot::{self, AllocateType, MemoryType};
 use uefi::mem::memory_map::{self, MemoryMap};
 use uefi::proto::console::gop;
 use x86_64::structures::paging::page_table::{PageTable, PageTableEntry};
 use x86_64::structures::paging::{FrameAllocator, Page, PageTableFlags, PhysFrame, Size4KiB};
 
-const KERNEL_SIZE: u64 = 0x100000;
 const MEM_MAP_NEEDED_PAGES: u64 = 16;
 
 pub struct PageTableInfo {
@@ -15,6 +23,8 @@ pub struct PageTableInfo {
     phys_addr: u64,
     /// The number of entries in this page table.
     num_entries: u64,
+    /// The physical address right above the highest page in the direct map.
+    direct_map_size: u64,
 }
 
 impl PageTableInfo {
@@ -25,15 +35,25 @@ impl PageTableInfo {
     pub fn num_entries(&self) -> u64 {
         self.num_entries
     }
+
+    pub fn direct_map_size(&self) -> u64 {
+        self.direct_map_size
+    }
 }
 
 /// Returns an option for the page table info.
 pub fn setup_paging(
     mem_map: &memory_map::MemoryMapOwned,
     gop_fb: &mut gop::FrameBuffer,
-    k_physical_address: u64,
+    kernel: &KernelImage,
+    kernel_stack: &KernelStack,
     raw_mem_map_physical_address: u64,
     pmm_sections_array: u64,
+    cmdline_physical_address: u64,
+    initrd: Option<&LoadedFile>,
+    modules: Option<&LoadedModules>,
+    firmware_tables: &FirmwareTables,
+    runtime_layout: Option<&RuntimeLayout>,
 ) -> Option<PageTableInfo> {
     // let num_entries: usize = calculate_page_table_entries(gop_fb, pmm_sections_array);
     // let needed_pages: usize = (num_entries + 511) / 512;
@@ -58,7 +78,12 @@ pub fn setup_paging(
 
     let mut mapper = ManualMapper::new(page_table);
 
-    let success: bool = mmap_kernel(k_physical_address, &mut mapper);
+    let success: bool = mmap_kernel(kernel, &mut mapper);
+    if !success {
+        return None;
+    }
+
+    let success: bool = mmap_kernel_stack(kernel_stack, &mut mapper);
     if !success {
         return None;
     }
@@ -78,6 +103,42 @@ pub fn setup_paging(
         return None;
     }
 
+    let success: bool = mmap_cmdline(cmdline_physical_address, &mut mapper);
+    if !success {
+        return None;
+    }
+
+    if let Some(initrd) = initrd {
+        let success: bool = mmap_initrd(initrd, &mut mapper);
+        if !success {
+            return None;
+        }
+    }
+
+    if let Some(modules) = modules {
+        let success: bool = mmap_modules(modules, &mut mapper);
+        if !success {
+            return None;
+        }
+    }
+
+    let success: bool = mmap_firmware_tables(firmware_tables, &mut mapper);
+    if !success {
+        return None;
+    }
+
+    if let Some(runtime_layout) = runtime_layout {
+        let success: bool = mmap_uefi_runtime(runtime_layout, &mut mapper);
+        if !success {
+            return None;
+        }
+    }
+
+    let direct_map_size: Option<u64> = mmap_physical_memory(mem_map, &mut mapper);
+    if direct_map_size.is_none() {
+        return None;
+    }
+
     //map the page tables themselves, so we can access them from the kernel
     // for i in 0..num_entries as u64 {
     for i in 0..1u64 {
@@ -96,25 +157,28 @@ pub fn setup_paging(
         }
     }
 
-    //identity-map all the UEFI-used memory so we can continue without any problems
+    //identity-map all the UEFI-used memory so we can continue without any problems; the kernel
+    //image, its stack and the page tables are only reached through their own mappings, so that
+    //W^X isn't undone by an alias
     for map_entry in mem_map.entries() {
-        if map_entry.ty == MemoryType::CONVENTIONAL {
+        if map_entry.ty == MemoryType::CONVENTIONAL
+            || map_entry.ty == raw_mem_map::KERNEL_IMAGE_MEMORY
+            || map_entry.ty == raw_mem_map::KERNEL_STACK_MEMORY
+            || map_entry.ty == PAGE_TABLES_MEMORY
+        {
             continue;
         }
 
-        for i in 0..map_entry.page_count {
-            let frame: PhysFrame = PhysFrame::containing_address(x86_64::PhysAddr::new(
-                map_entry.phys_start + i * 0x1000,
-            ));
-            let flags: PageTableFlags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-            let page: Page =
-                Page::containing_address(x86_64::VirtAddr::new(map_entry.phys_start + i * 0x1000));
-
-            let success = mapper.map_to(page, frame, flags);
-            if !success {
-                error!("Error mapping the identity map.");
-                return None;
-            }
+        let flags: PageTableFlags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        let success: bool = mapper.map_range(
+            map_entry.phys_start,
+            map_entry.phys_start,
+            map_entry.page_count * 0x1000,
+            flags,
+        );
+        if !success {
+            error!("Error mapping the identity map.");
+            return None;
         }
     }
 
@@ -122,66 +186,92 @@ pub fn setup_paging(
         phys_addr: page_table_ptr.as_ptr() as u64,
         // num_entries: num_entries as u64,
         num_entries: 1u64,
+        direct_map_size: direct_map_size.unwrap(),
     });
 }
 
-fn mmap_kernel(k_physical_address: u64, mapper: &mut ManualMapper) -> bool {
-    //1 MiB
-    let mut needed_memory: u64 = KERNEL_SIZE;
-    let mut i: u64 = 0;
+fn mmap_kernel(kernel: &KernelImage, mapper: &mut ManualMapper) -> bool {
+    let nx_supported: bool = is_nx_supported();
+
+    for i in 0..kernel.page_count() {
+        let virt_addr: u64 = kernel.virt_addr() + i * 0x1000;
+
+        let permissions: Option<PagePermissions> = kernel.layout().page_permissions(virt_addr);
+        //a gap between segments, nothing to map
+        if permissions.is_none() {
+            continue;
+        }
+
+        let permissions: PagePermissions = permissions.unwrap();
+        let mut flags: PageTableFlags = PageTableFlags::PRESENT;
+        if permissions.writable {
+            flags |= PageTableFlags::WRITABLE;
+        }
+
+        if !permissions.executable && nx_supported {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
 
-    while needed_memory > 0 {
         let frame: PhysFrame =
-            PhysFrame::containing_address(x86_64::PhysAddr::new(k_physical_address + i * 0x1000));
-        let flags: PageTableFlags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-        let page: Page = Page::containing_address(x86_64::VirtAddr::new(
-            boot_info::KERNEL_VIRTUAL_ADDRESS + i * 0x1000,
-        ));
+            PhysFrame::containing_address(x86_64::PhysAddr::new(kernel.virt_to_phys(virt_addr)));
+        let page: Page = Page::containing_address(x86_64::VirtAddr::new(virt_addr));
 
         let success = mapper.map_to(page, frame, flags);
         if !success {
             error!("Error mapping a physical page to kernel memory.");
             return false;
         }
-
-        needed_memory -= 0x1000;
-        i += 1;
     }
 
     return true;
 }
 
-fn mmap_gop(gop_fb: &mut gop::FrameBuffer, mapper: &mut ManualMapper) -> bool {
-    let mut needed_memory: u64 = gop_fb.size() as u64;
-    let base_physical_address: u64 = gop_fb.as_mut_ptr() as u64;
+/// Maps the kernel stack above its guard page, which stays unmapped.
+fn mmap_kernel_stack(kernel_stack: &KernelStack, mapper: &mut ManualMapper) -> bool {
+    let mut flags: PageTableFlags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    if is_nx_supported() {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
 
-    let mut i: u64 = 0;
-    //can't have more than 65_536 pages of 4 KiB for a framebuffer
-    while needed_memory > 0 && i < 0x1_00_00 {
+    for i in 0..kernel_stack.page_count() {
         let frame: PhysFrame = PhysFrame::containing_address(x86_64::PhysAddr::new(
-            base_physical_address + i * 0x1000,
-        ));
-        let flags: PageTableFlags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-        let page: Page = Page::containing_address(x86_64::VirtAddr::new(
-            boot_info::GOP_VIRTUAL_ADDRESS + i * 0x1000,
+            kernel_stack.phys_addr() + i * 0x1000,
         ));
+        let page: Page =
+            Page::containing_address(x86_64::VirtAddr::new(kernel_stack.bottom() + i * 0x1000));
 
         let success = mapper.map_to(page, frame, flags);
         if !success {
-            error!("Error mapping a physical page to GOP memory.");
+            error!("Error mapping the kernel stack.");
             return false;
         }
-
-        needed_memory -= 0x1000;
-        i += 1;
     }
 
-    //just in case...
-    if i >= 0x1_00_00 {
+    return true;
+}
+
+fn mmap_gop(gop_fb: &mut gop::FrameBuffer, mapper: &mut ManualMapper) -> bool {
+    let size: u64 = gop_fb.size() as u64;
+    let base_physical_address: u64 = gop_fb.as_mut_ptr() as u64;
+
+    //can't have more than 65_536 pages of 4 KiB for a framebuffer
+    if size > 0x1_00_00 * 0x1000 {
         error!("Framebuffer is too big.");
         return false;
     }
 
+    let flags: PageTableFlags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let success: bool = mapper.map_range(
+        boot_info::GOP_VIRTUAL_ADDRESS,
+        base_physical_address,
+        size,
+        flags,
+    );
+    if !success {
+        error!("Error mapping a physical page to GOP memory.");
+        return false;
+    }
+
     return true;
 }
 
@@ -248,8 +338,188 @@ fn mmap_pmm_sections(pmm_sections_array: u64, mapper: &mut ManualMapper) -> bool
     return true;
 }
 
+fn mmap_cmdline(cmdline_phys_addr: u64, mapper: &mut ManualMapper) -> bool {
+    let frame: PhysFrame = PhysFrame::containing_address(x86_64::PhysAddr::new(cmdline_phys_addr));
+    //the kernel only needs to read it
+    let flags: PageTableFlags = PageTableFlags::PRESENT;
+    let page: Page =
+        Page::containing_address(x86_64::VirtAddr::new(boot_info::CMDLINE_VIRTUAL_ADDRESS));
+
+    let success = mapper.map_to(page, frame, flags);
+    if !success {
+        error!("Error mapping the kernel command line.");
+        return false;
+    }
+
+    return true;
+}
+
+fn mmap_initrd(initrd: &LoadedFile, mapper: &mut ManualMapper) -> bool {
+    //the kernel only needs to read it
+    let mut flags: PageTableFlags = PageTableFlags::PRESENT;
+    if is_nx_supported() {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    let success: bool = mapper.map_range(
+        boot_info::INITRD_VIRTUAL_ADDRESS,
+        initrd.phys_addr(),
+        initrd.page_count() * 0x1000,
+        flags,
+    );
+    if !success {
+        error!("Error mapping the initrd.");
+        return false;
+    }
+
+    return true;
+}
+
+/// Maps the table of modules at the start of the modules range, followed by every module at the
+/// address written in its entry.
+fn mmap_modules(modules: &LoadedModules, mapper: &mut ManualMapper) -> bool {
+    //the kernel only needs to read them
+    let mut flags: PageTableFlags = PageTableFlags::PRESENT;
+    if is_nx_supported() {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    let success: bool = mapper.map_range(
+        boot_info::MODULES_VIRTUAL_ADDRESS,
+        modules.table_phys_addr(),
+        0x1000,
+        flags,
+    );
+    if !success {
+        error!("Error mapping the table of modules.");
+        return false;
+    }
+
+    for module in modules.modules() {
+        let success: bool = mapper.map_range(
+            module.virt_addr(),
+            module.phys_addr(),
+            module.page_count() * 0x1000,
+            flags,
+        );
+        if !success {
+            error!("Error mapping a module.");
+            return false;
+        }
+    }
+
+    return true;
+}
+
+/// Maps the memory of the UEFI runtime services where the layout placed it, so the firmware can be
+/// told about it with SetVirtualAddressMap. Only the runtime code is executable.
+fn mmap_uefi_runtime(runtime_layout: &RuntimeLayout, mapper: &mut ManualMapper) -> bool {
+    for region in runtime_layout.regions() {
+        let mut flags: PageTableFlags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        match region.ty() {
+            MemoryType::RUNTIME_SERVICES_CODE => {}
+            MemoryType::MMIO | MemoryType::MMIO_PORT_SPACE => {
+                flags |= PageTableFlags::NO_CACHE;
+                if is_nx_supported() {
+                    flags |= PageTableFlags::NO_EXECUTE;
+                }
+            }
+            _ => {
+                if is_nx_supported() {
+                    flags |= PageTableFlags::NO_EXECUTE;
+                }
+            }
+        }
+
+        let success: bool = mapper.map_range(
+            region.virt_start(),
+            region.phys_start(),
+            region.page_count() * 0x1000,
+            flags,
+        );
+        if !success {
+            error!("Error mapping the UEFI runtime services.");
+            return false;
+        }
+    }
+
+    return true;
+}
+
+/// Maps all the RAM at [`boot_info::PHYS_DIRECT_MAP_ADDRESS`], so the kernel can reach any frame
+/// (including the page tables it allocates later). MMIO and reserved regions are left out, they
+/// must not be mapped as normal memory, and the kernel image is read-only there. Returns the
+/// physical address right above the highest mapped page.
+fn mmap_physical_memory(
+    mem_map: &memory_map::MemoryMapOwned,
+    mapper: &mut ManualMapper,
+) -> Option<u64> {
+    let nx_supported: bool = is_nx_supported();
+
+    for region in mem_map.entries().map(raw_mem_map::to_region) {
+        if !boot_planner::paging::is_ram(region.mem_type) {
+            continue;
+        }
+
+        if !boot_planner::paging::is_in_direct_map(&region) {
+            warn!(
+                "The memory at {:#x} is above the limit of the direct map, the kernel won't use it.",
+                region.start
+            );
+            continue;
+        }
+
+        let mut flags: PageTableFlags = PageTableFlags::PRESENT;
+        if region.mem_type != boot_info::memory_map::MemoryType::KernelImage {
+            flags |= PageTableFlags::WRITABLE;
+        }
+
+        if nx_supported {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
+
+        let success: bool = mapper.map_range(
+            boot_info::PHYS_DIRECT_MAP_ADDRESS + region.start,
+            region.start,
+            region.page_count * 0x1000,
+            flags,
+        );
+        if !success {
+            error!("Error mapping the physical memory.");
+            return None;
+        }
+    }
+
+    let direct_map_size: u64 =
+        boot_planner::paging::direct_map_size(mem_map.entries().map(raw_mem_map::to_region));
+
+    info!("Mapped the physical memory up to {:#x}.", direct_map_size);
+    return Some(direct_map_size);
+}
+
+/// Identity-maps the firmware tables. They are usually in ACPI or reserved memory, which is
+/// identity-mapped anyway, but some firmwares put them in memory that the kernel could reuse.
+fn mmap_firmware_tables(firmware_tables: &FirmwareTables, mapper: &mut ManualMapper) -> bool {
+    for (start, size) in firmware_tables.preserved_ranges() {
+        let first_page: u64 = start & !0xfff;
+        let flags: PageTableFlags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        let success: bool =
+            mapper.map_range(first_page, first_page, start + size - first_page, flags);
+        if !success {
+            error!("Error mapping the firmware tables.");
+            return false;
+        }
+    }
+
+    return true;
+}
+
 /// Returns the number of necessary entries for the page table itself.
-fn calculate_page_table_entries(gop_fb: &mut gop::FrameBuffer, pmm_sections_array: u64) -> usize {
+fn calculate_page_table_entries(
+    kernel: &KernelImage,
+    gop_fb: &mut gop::FrameBuffer,
+    pmm_sections_array: u64,
+) -> usize {
     let mut array_ptr: *mut u64 = pmm_sections_array as *mut u64;
     let mut pmm_needed_pages: u64 = 0;
 
@@ -262,7 +532,7 @@ fn calculate_page_table_entries(gop_fb: &mut gop::FrameBuffer, pmm_sections_arra
         }
     }
 
-    let page_count = KERNEL_SIZE / 0x1000
+    let page_count = kernel.page_count()
         + gop_fb.size() as u64 / 0x1000
         + MEM_MAP_NEEDED_PAGES
         + pmm_needed_pages;
@@ -271,17 +541,26 @@ fn calculate_page_table_entries(gop_fb: &mut gop::FrameBuffer, pmm_sections_arra
     return page_count as usize;
 }
 
+/// Returns true if the CPU supports the no-execute bit in page table entries (EFER.NXE).
+pub fn is_nx_supported() -> bool {
+    let max_leaf: u32 = core::arch::x86_64::__cpuid(0x8000_0000).eax;
+    if max_leaf < 0x8000_0001 {
+        return false;
+    }
+
+    let features: u32 = core::arch::x86_64::__cpuid(0x8000_0001).edx;
+    return features & (1 << 20) != 0;
+}
+
 fn allocate_pages(needed_pages: usize) -> Result<NonNull<u8>, uefi::Error> {
-    boot::allocate_pages(
-        AllocateType::AnyPages,
-        MemoryType::LOADER_DATA,
-        needed_pages,
-    )
+    boot::allocate_pages(AllocateType::AnyPages, PAGE_TABLES_MEMORY, needed_pages)
 }
 
 struct ManualMapper {
     p4: *mut PageTable,
     frame_allocator: UefiFrameAllocator,
+    /// Whether the CPU supports 1 GiB pages.
+    huge_1gib_pages: bool,
 }
 
 impl ManualMapper {
@@ -289,39 +568,136 @@ impl ManualMapper {
         Self {
             p4,
             frame_allocator: UefiFrameAllocator {},
+            huge_1gib_pages: is_1gib_pages_supported(),
         }
     }
 
     fn map_to(&mut self, page: Page, frame: PhysFrame, flags: PageTableFlags) -> bool {
-        let p4 = unsafe { &mut *self.p4 };
-
-        let p3_idx = page.start_address().p4_index();
-        let p3 = match Self::get_or_create_table(&mut self.frame_allocator, &mut p4[p3_idx]) {
+        let p1 = match self.get_table(page.start_address(), 1) {
             Some(table) => table,
             None => return false,
         };
 
-        let p2_idx = page.start_address().p3_index();
-        let p2 = match Self::get_or_create_table(&mut self.frame_allocator, &mut p3[p2_idx]) {
+        let p1_idx = page.start_address().p1_index();
+        p1[p1_idx].set_frame(frame, flags);
+
+        true
+    }
+
+    /// Maps `size` bytes (rounded up to 4 KiB pages) from `virt_addr` to `phys_addr`. 1 GiB and
+    /// 2 MiB pages are used where both addresses are aligned to them and the range is large enough,
+    /// 4 KiB pages for the rest.
+    fn map_range(
+        &mut self,
+        virt_addr: u64,
+        phys_addr: u64,
+        size: u64,
+        flags: PageTableFlags,
+    ) -> bool {
+        let size: u64 = size.next_multiple_of(0x1000);
+        let mut offset: u64 = 0;
+
+        while offset < size {
+            let virt: u64 = virt_addr + offset;
+            let phys: u64 = phys_addr + offset;
+            let remaining: u64 = size - offset;
+
+            //a huge page is not used if a part of its range is already mapped with smaller pages
+            if self.huge_1gib_pages
+                && can_use_page_size(virt, phys, remaining, SIZE_1_GIB)
+                && self.map_huge(virt, phys, flags, 3)
+            {
+                offset += SIZE_1_GIB;
+                continue;
+            }
+
+            if can_use_page_size(virt, phys, remaining, SIZE_2_MIB)
+                && self.map_huge(virt, phys, flags, 2)
+            {
+                offset += SIZE_2_MIB;
+                continue;
+            }
+
+            let page: Page = Page::containing_address(x86_64::VirtAddr::new(virt));
+            let frame: PhysFrame = PhysFrame::containing_address(x86_64::PhysAddr::new(phys));
+            if !self.map_to(page, frame, flags) {
+                return false;
+            }
+
+            offset += 0x1000;
+        }
+
+        return true;
+    }
+
+    /// Maps a 2 MiB (level 2) or 1 GiB (level 3) page. Returns false if the entry is already used.
+    fn map_huge(
+        &mut self,
+        virt_addr: u64,
+        phys_addr: u64,
+        flags: PageTableFlags,
+        level: u8,
+    ) -> bool {
+        let virt_addr: x86_64::VirtAddr = x86_64::VirtAddr::new(virt_addr);
+        let table = match self.get_table(virt_addr, level) {
             Some(table) => table,
             None => return false,
         };
 
-        let p1_idx = page.start_address().p2_index();
-        let p1 = match Self::get_or_create_table(&mut self.frame_allocator, &mut p2[p1_idx]) {
-            Some(table) => table,
-            None => return false,
+        let entry: &mut PageTableEntry = if level == 3 {
+            &mut table[virt_addr.p3_index()]
+        } else {
+            &mut table[virt_addr.p2_index()]
         };
+        if !entry.is_unused() {
+            return false;
+        }
 
-        let p1_idx = page.start_address().p1_index();
-        p1[p1_idx].set_frame(frame, flags);
+        entry.set_addr(
+            x86_64::PhysAddr::new(phys_addr),
+            flags | PageTableFlags::HUGE_PAGE,
+        );
 
-        true
+        return true;
+    }
+
+    /// Returns the table of the given level (3 for P3, 2 for P2, 1 for P1) that holds the entry for
+    /// the address, creating the missing tables on the way.
+    fn get_table(
+        &mut self,
+        virt_addr: x86_64::VirtAddr,
+        level: u8,
+    ) -> Option<&'static mut PageTable> {
+        let p4 = unsafe { &mut *self.p4 };
+        let p3 =
+            Self::get_or_create_table(&mut self.frame_allocator, &mut p4[virt_addr.p4_index()], 0)?;
+        if level == 3 {
+            return Some(p3);
+        }
+
+        let p2 = Self::get_or_create_table(
+            &mut self.frame_allocator,
+            &mut p3[virt_addr.p3_index()],
+            SIZE_1_GIB,
+        )?;
+        if level == 2 {
+            return Some(p2);
+        }
+
+        return Self::get_or_create_table(
+            &mut self.frame_allocator,
+            &mut p2[virt_addr.p2_index()],
+            SIZE_2_MIB,
+        );
     }
 
+    /// Returns the table the entry points to, creating it if the entry is unused. If the entry maps
+    /// a huge page (of `entry_page_size` bytes), it is split in smaller pages with the same
+    /// permissions.
     fn get_or_create_table(
         allocator: &mut UefiFrameAllocator,
         entry: &mut PageTableEntry,
+        entry_page_size: u64,
     ) -> Option<&'static mut PageTable> {
         if entry.is_unused() {
             let frame = allocator.allocate_frame()?;
@@ -329,6 +705,22 @@ impl ManualMapper {
             unsafe {
                 (*table_ptr).zero();
             }
+            entry.set_frame(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+        } else if entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            let frame = allocator.allocate_frame()?;
+            let table = unsafe { &mut *(frame.start_address().as_u64() as *mut PageTable) };
+
+            //a 1 GiB page becomes 2 MiB pages, a 2 MiB page becomes 4 KiB pages
+            let child_size: u64 = entry_page_size / 512;
+            let mut child_flags: PageTableFlags = entry.flags();
+            if child_size == 0x1000 {
+                child_flags.remove(PageTableFlags::HUGE_PAGE);
+            }
+
+            for (i, child) in table.iter_mut().enumerate() {
+                child.set_addr(entry.addr() + i as u64 * child_size, child_flags);
+            }
+
             entry.set_frame(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
         }
 
@@ -337,11 +729,22 @@ impl ManualMapper {
     }
 }
 
+/// Returns true if the CPU supports 1 GiB pages.
+fn is_1gib_pages_supported() -> bool {
+    let max_leaf: u32 = core::arch::x86_64::__cpuid(0x8000_0000).eax;
+    if max_leaf < 0x8000_0001 {
+        return false;
+    }
+
+    let features: u32 = core::arch::x86_64::__cpuid(0x8000_0001).edx;
+    return features & (1 << 26) != 0;
+}
+
 pub(crate) struct UefiFrameAllocator {}
 
 unsafe impl FrameAllocator<Size4KiB> for UefiFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        let page = boot::allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, 1);
+        let page = boot::allocate_pages(AllocateType::AnyPages, PAGE_TABLES_MEMORY, 1);
         if page.is_err() {
             return None;
         }
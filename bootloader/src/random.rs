@@ -0,0 +1,42 @@
+use log::warn;
+use uefi::proto::rng::Rng;
+use uefi::{boot, Handle};
+use x86_64::instructions::random::RdRand;
+
+/// Returns a random number. The EFI RNG protocol is used if the firmware has it, then RDRAND. If
+/// none of them is available, the time stamp counter is used, which is not random at all, but at
+/// least differs between boots.
+pub fn random_u64() -> u64 {
+    if let Some(value) = efi_random() {
+        return value;
+    }
+
+    if let Some(value) = RdRand::new().and_then(|rdrand| rdrand.get_u64()) {
+        return value;
+    }
+
+    warn!("No random number generator found, using the time stamp counter instead.");
+    return crate::boot_timeline::read_tsc();
+}
+
+fn efi_random() -> Option<u64> {
+    let handle: Result<Handle, uefi::Error> = boot::get_handle_for_protocol::<Rng>();
+    if handle.is_err() {
+        return None;
+    }
+
+    let rng: Result<boot::ScopedProtocol<Rng>, uefi::Error> =
+        boot::open_protocol_exclusive::<Rng>(handle.unwrap());
+    if rng.is_err() {
+        return None;
+    }
+
+    let mut rng: boot::ScopedProtocol<Rng> = rng.unwrap();
+    let mut buffer: [u8; 8] = [0; 8];
+    //let the firmware choose the algorithm
+    if rng.get_rng(None, &mut buffer).is_err() {
+        return None;
+    }
+
+    return Some(u64::from_le_bytes(buffer));
+}
@@ -1,20 +1,34 @@
+use crate::firmware_tables::FirmwareTables;
+use crate::uefi_runtime::RuntimeLayout;
+use boot_info::memory_map::MemoryMapEntry;
+use boot_planner::mem_map::MemRegion;
 use core::ptr::NonNull;
 use log::error;
 use uefi::boot;
 use uefi::boot::{AllocateType, MemoryType};
-use uefi::mem::memory_map::{MemoryMap, MemoryMapOwned};
+use uefi::mem::memory_map::{MemoryDescriptor, MemoryMap, MemoryMapOwned};
+
+//UEFI leaves the types from 0x80000000 up to the OS loaders, so the allocations the kernel needs
+//can be told apart from the ones it can reuse right away
+/// The loaded segments of the kernel.
+pub const KERNEL_IMAGE_MEMORY: MemoryType = MemoryType(0x8000_0000);
+/// The stack the kernel starts on.
+pub const KERNEL_STACK_MEMORY: MemoryType = MemoryType(0x8000_0001);
+/// The page tables given to the kernel.
+pub const PAGE_TABLES_MEMORY: MemoryType = MemoryType(0x8000_0002);
+/// The bitmaps of the physical memory manager and the array holding their addresses.
+pub const PMM_BITMAP_MEMORY: MemoryType = MemoryType(0x8000_0003);
+/// The memory map, the command line and the initrd.
+pub const BOOT_DATA_MEMORY: MemoryType = MemoryType(0x8000_0004);
+/// The memory that failed the memory test.
+pub const BAD_MEMORY: MemoryType = MemoryType(0x8000_0005);
 
 /// Returns the physical address of the raw map, as well as the number of pages taken by it.
 pub fn alloc_memory_for_map(mem_map: &MemoryMapOwned) -> Option<(u64, u32)> {
-    //each entry will occupy 40 bytes, but we will add 10 as a "safe zone" (in case there will
-    //be any allocations after this); then the total size is divided by 4096 to get the page count
-    let pages_needed: usize = (((mem_map.entries().len() + 10) * 40) / 0x1000) + 1;
-    
-    let addr: uefi::Result<NonNull<u8>> = boot::allocate_pages(
-        AllocateType::AnyPages,
-        MemoryType::LOADER_DATA,
-        pages_needed,
-    );
+    let pages_needed: usize = boot_planner::mem_map::map_pages_needed(mem_map.entries().len());
+
+    let addr: uefi::Result<NonNull<u8>> =
+        boot::allocate_pages(AllocateType::AnyPages, BOOT_DATA_MEMORY, pages_needed);
     if addr.is_err() {
         error!("Error allocating memory for the direct memory map.");
         return None;
@@ -27,3 +41,99 @@ pub fn alloc_memory_for_map(mem_map: &MemoryMapOwned) -> Option<(u64, u32)> {
 
     return Some((addr, pages_needed as u32));
 }
+
+/// Writes the memory map given to the kernel at `map_addr`: sorted, without overlapping ranges,
+/// with the adjacent ranges of the same type merged and with the bootloader allocations and the
+/// framebuffer given their own types. Returns the size of the map in bytes, or None if it doesn't
+/// fit in `page_count` pages.
+///
+/// # Safety
+/// `map_addr` must point to `page_count` writable pages.
+pub unsafe fn write_kernel_map(
+    final_mem_map: &MemoryMapOwned,
+    map_addr: u64,
+    page_count: u32,
+    framebuffer: (u64, u64),
+    firmware_tables: &FirmwareTables,
+    runtime_layout: Option<&RuntimeLayout>,
+) -> Option<u32> {
+    //zeroed first, so the memory holds valid entries (of the reserved type) before it's a slice
+    let dest: &mut [MemoryMapEntry] = unsafe {
+        core::ptr::write_bytes(map_addr as *mut u8, 0, page_count as usize * 0x1000);
+        core::slice::from_raw_parts_mut(
+            map_addr as *mut MemoryMapEntry,
+            page_count as usize * 0x1000 / size_of::<MemoryMapEntry>(),
+        )
+    };
+
+    //sorted, like the regions given to normalize_map
+    let mut tables: [(u64, u64); 3] = [(0, 0); 3];
+    let mut table_count: usize = 0;
+    for range in firmware_tables.preserved_ranges() {
+        tables[table_count] = range;
+        table_count += 1;
+    }
+    tables[..table_count].sort_unstable();
+    let tables: &[(u64, u64)] = &tables[..table_count];
+
+    let regions = final_mem_map
+        .entries()
+        .flat_map(move |entry: &MemoryDescriptor| {
+            let mut region: MemRegion = to_region(entry);
+
+            //the kernel must know where the runtime services will expect their memory
+            region.virt_start = runtime_layout
+                .and_then(|layout| layout.phys_to_virt(entry.phys_start))
+                .unwrap_or(entry.virt_start);
+
+            //the kernel would reuse the memory of the firmware tables right away, so they get their
+            //own ranges, kept until they are parsed. The entry keeps its type, normalize_map gives
+            //the part overlapped by the tables to the reclaimable type
+            let table_regions = tables
+                .iter()
+                .filter(move |(start, _)| *start >= region.start && *start < region.end())
+                .map(move |&(start, size): &(u64, u64)| {
+                    let first_page: u64 = start & !0xfff;
+                    return MemRegion {
+                        mem_type: boot_info::memory_map::MemoryType::AcpiReclaim,
+                        attributes: region.attributes,
+                        start: first_page,
+                        page_count: (start + size - first_page).div_ceil(0x1000),
+                        virt_start: 0,
+                    };
+                });
+
+            return core::iter::once(region).chain(table_regions);
+        });
+
+    let len: Option<usize> = boot_planner::mem_map::normalize_map(regions, Some(framebuffer), dest);
+    return len.map(|len: usize| (len * size_of::<MemoryMapEntry>()) as u32);
+}
+
+/// Converts an entry of the UEFI memory map for the [`boot_planner`], with the type the kernel
+/// will see.
+pub fn to_region(entry: &MemoryDescriptor) -> MemRegion {
+    return MemRegion {
+        mem_type: kernel_memory_type(entry.ty),
+        attributes: entry.att.bits(),
+        start: entry.phys_start,
+        page_count: entry.page_count,
+        virt_start: entry.virt_start,
+    };
+}
+
+/// The type the kernel sees for a UEFI memory type.
+fn kernel_memory_type(ty: MemoryType) -> boot_info::memory_map::MemoryType {
+    return match ty {
+        MemoryType::LOADER_CODE | MemoryType::LOADER_DATA => {
+            boot_info::memory_map::MemoryType::BootloaderReclaimable
+        }
+        KERNEL_IMAGE_MEMORY => boot_info::memory_map::MemoryType::KernelImage,
+        KERNEL_STACK_MEMORY => boot_info::memory_map::MemoryType::KernelStack,
+        PAGE_TABLES_MEMORY => boot_info::memory_map::MemoryType::PageTables,
+        PMM_BITMAP_MEMORY => boot_info::memory_map::MemoryType::PmmBitmap,
+        BOOT_DATA_MEMORY => boot_info::memory_map::MemoryType::BootData,
+        BAD_MEMORY => boot_info::memory_map::MemoryType::Unusable,
+        _ => boot_info::memory_map::MemoryType::from(ty.0),
+    };
+}
@@ -0,0 +1,54 @@
+use x86_64::instructions::port::Port;
+
+const PORT: u16 = 0x3f8; //COM1 interface
+
+/// The COM1 serial port. It keeps working after exiting the boot services, so it's the only output
+/// available right before the kernel starts.
+pub struct SerialPort {
+    is_initialized: bool,
+}
+
+impl SerialPort {
+    pub const fn new() -> Self {
+        SerialPort {
+            is_initialized: false,
+        }
+    }
+
+    /// Sets up the port for 38400 baud, 8N1. Returns false if there is no serial chip (in that case
+    /// nothing is ever written).
+    pub fn init(&mut self) -> bool {
+        unsafe {
+            Port::<u8>::new(PORT + 1).write(0x00); //disable all interrupts
+            Port::<u8>::new(PORT + 3).write(0x80); //enable DLAB (set baud rate divisor)
+            Port::<u8>::new(PORT).write(0x03); //set divisor to 3 (lo byte) 38400 baud
+            Port::<u8>::new(PORT + 1).write(0x00); //                  (hi byte)
+            Port::<u8>::new(PORT + 3).write(0x03); //8 bits, no parity, one stop bit
+            Port::<u8>::new(PORT + 2).write(0xc7); //enable FIFO, clear them, with 14-byte threshold
+            Port::<u8>::new(PORT + 4).write(0x1e); //set in loopback mode, test the serial chip
+
+            //the chip must return the same byte in loopback mode
+            Port::<u8>::new(PORT).write(0xae);
+            if Port::<u8>::new(PORT).read() != 0xae {
+                return false;
+            }
+
+            Port::<u8>::new(PORT + 4).write(0x0f); //normal operation mode
+        }
+
+        self.is_initialized = true;
+        return true;
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        if !self.is_initialized {
+            return;
+        }
+
+        for byte in bytes {
+            //wait until the transmit buffer is empty
+            while unsafe { Port::<u8>::new(PORT + 5).read() } & 0x20 == 0 {}
+            unsafe { Port::<u8>::new(PORT).write(*byte) };
+        }
+    }
+}
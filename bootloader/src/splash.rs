@@ -0,0 +1,238 @@
+use crate::file_loader::{self, LoadedFile};
+use boot_info::framebuffer::FramebufferData;
+use log::{info, warn};
+use uefi::boot::MemoryType;
+use uefi::Status;
+
+/// The splash image can't be larger than this (32 MiB, a 4K image with 24 bits per pixel is less).
+const MAX_SPLASH_SIZE: u64 = 0x200_0000;
+/// The largest width or height of a splash image.
+const MAX_SPLASH_DIMENSION: u32 = 16_384;
+
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+
+/// Draws the BMP image from the given path in the middle of the screen, on a black background. The
+/// image is scaled down if it doesn't fit on the screen. `fb_base` is the physical address of the
+/// framebuffer described by `fb_data`. Returns true if the splash screen is shown.
+pub fn show_splash(path: &str, fb_data: &FramebufferData, fb_base: *mut u8) -> bool {
+    if path.is_empty() {
+        return false;
+    }
+
+    let file: Result<LoadedFile, uefi::Error> =
+        file_loader::load_file(path, MemoryType::LOADER_DATA, MAX_SPLASH_SIZE);
+    if file.is_err() {
+        let err_msg: uefi::Error = file.err().unwrap();
+        if err_msg.status() == Status::NOT_FOUND {
+            info!("No splash image found at {path}.");
+        } else {
+            warn!("Error reading the splash image: {err_msg}");
+        }
+
+        return false;
+    }
+
+    let file: LoadedFile = file.unwrap();
+    let bmp: Result<Bmp, &'static str> = Bmp::parse(file.data());
+    if bmp.is_err() {
+        let err_msg: &str = bmp.err().unwrap();
+        warn!("Error reading the splash image: {err_msg}");
+        file.free();
+        return false;
+    }
+
+    let bmp: Bmp = bmp.unwrap();
+    draw_image(&bmp, fb_data, fb_base);
+    file.free();
+
+    return true;
+}
+
+fn draw_image(bmp: &Bmp, fb_data: &FramebufferData, fb_base: *mut u8) {
+    let screen_width: u64 = fb_data.width() as u64;
+    let screen_height: u64 = fb_data.height() as u64;
+    let img_width: u64 = bmp.width as u64;
+    let img_height: u64 = bmp.height as u64;
+
+    //keep the aspect ratio, shrink by the side that overflows the most
+    let (width, height): (u64, u64) = if img_width <= screen_width && img_height <= screen_height {
+        (img_width, img_height)
+    } else if img_width * screen_height > img_height * screen_width {
+        (screen_width, (img_height * screen_width / img_width).max(1))
+    } else {
+        (
+            (img_width * screen_height / img_height).max(1),
+            screen_height,
+        )
+    };
+
+    let left: u64 = (screen_width - width) / 2;
+    let top: u64 = (screen_height - height) / 2;
+    let bytes_per_pixel: usize = (fb_data.bits_per_pixel() as usize).div_ceil(8);
+    let pitch: usize = fb_data.pitch() as usize * bytes_per_pixel;
+
+    unsafe {
+        //black background (all the channels are 0 in any pixel format)
+        core::ptr::write_bytes(fb_base, 0, pitch * screen_height as usize);
+
+        for y in 0..height {
+            let src_y: u32 = (y * img_height / height) as u32;
+            let row: *mut u8 = fb_base.add((top + y) as usize * pitch);
+
+            for x in 0..width {
+                let src_x: u32 = (x * img_width / width) as u32;
+                let pixel: u32 = encode_pixel(bmp.pixel(src_x, src_y), fb_data);
+                let dest: *mut u8 = row.add((left + x) as usize * bytes_per_pixel);
+
+                //little-endian, so the first bytes are the lowest bits
+                for byte in 0..bytes_per_pixel.min(4) {
+                    *dest.add(byte) = (pixel >> (byte * 8)) as u8;
+                }
+            }
+        }
+    }
+}
+
+/// Converts an RGB color to the pixel format of the framebuffer.
+fn encode_pixel(color: (u8, u8, u8), fb_data: &FramebufferData) -> u32 {
+    return place_channel(color.0, fb_data.red_bitmask())
+        | place_channel(color.1, fb_data.green_bitmask())
+        | place_channel(color.2, fb_data.blue_bitmask());
+}
+
+/// Puts an 8-bit channel value at the bits of the mask, scaled to the number of bits of the mask.
+fn place_channel(value: u8, mask: u32) -> u32 {
+    if mask == 0 {
+        return 0;
+    }
+
+    let shift: u32 = mask.trailing_zeros();
+    let bits: u32 = (mask >> shift).count_ones();
+    let value: u32 = if bits >= 8 {
+        (value as u32) << (bits - 8)
+    } else {
+        (value as u32) >> (8 - bits)
+    };
+
+    return (value << shift) & mask;
+}
+
+/// Reads an 8-bit channel value from the bits of the mask.
+fn extract_channel(pixel: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+
+    let shift: u32 = mask.trailing_zeros();
+    let bits: u32 = (mask >> shift).count_ones();
+    let value: u32 = (pixel & mask) >> shift;
+    if bits >= 8 {
+        return (value >> (bits - 8)) as u8;
+    }
+
+    return (value * 255 / ((1 << bits) - 1)) as u8;
+}
+
+/// An uncompressed BMP image with 24 or 32 bits per pixel.
+struct Bmp<'a> {
+    pixels: &'a [u8],
+    width: u32,
+    height: u32,
+    /// BMP images are stored bottom-up, unless the height in the header is negative.
+    top_down: bool,
+    bytes_per_pixel: usize,
+    /// Rows are padded to 4 bytes.
+    row_size: usize,
+    red_mask: u32,
+    green_mask: u32,
+    blue_mask: u32,
+}
+
+impl<'a> Bmp<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, &'static str> {
+        //the file header (14 bytes) and at least a BITMAPINFOHEADER (40 bytes)
+        if data.len() < 54 || &data[..2] != b"BM" {
+            return Err("not a BMP file");
+        }
+
+        let pixels_offset: usize = read_u32(data, 10) as usize;
+        let header_size: u32 = read_u32(data, 14);
+        let width: i32 = read_u32(data, 18) as i32;
+        let height: i32 = read_u32(data, 22) as i32;
+        let bits_per_pixel: u16 = u16::from_le_bytes([data[28], data[29]]);
+        let compression: u32 = read_u32(data, 30);
+
+        if header_size < 40 {
+            return Err("unsupported BMP header");
+        }
+
+        if width <= 0
+            || height == 0
+            || width.unsigned_abs() > MAX_SPLASH_DIMENSION
+            || height.unsigned_abs() > MAX_SPLASH_DIMENSION
+        {
+            return Err("invalid image size");
+        }
+
+        let (red_mask, green_mask, blue_mask): (u32, u32, u32) = match (compression, bits_per_pixel)
+        {
+            (BI_RGB, 24) | (BI_RGB, 32) => (0xff_00_00, 0xff_00, 0xff),
+            //the masks come right after the BITMAPINFOHEADER (or are part of the V4/V5 headers)
+            (BI_BITFIELDS, 32) if data.len() >= 66 => {
+                (read_u32(data, 54), read_u32(data, 58), read_u32(data, 62))
+            }
+            _ => return Err("only uncompressed images with 24 or 32 bits per pixel are supported"),
+        };
+
+        let width: u32 = width as u32;
+        let bytes_per_pixel: usize = bits_per_pixel as usize / 8;
+        let row_size: usize = (width as usize * bits_per_pixel as usize).div_ceil(32) * 4;
+        let pixels_size: usize = row_size * height.unsigned_abs() as usize;
+        if pixels_offset.saturating_add(pixels_size) > data.len() {
+            return Err("the file is truncated");
+        }
+
+        return Ok(Bmp {
+            pixels: &data[pixels_offset..pixels_offset + pixels_size],
+            width,
+            height: height.unsigned_abs(),
+            top_down: height < 0,
+            bytes_per_pixel,
+            row_size,
+            red_mask,
+            green_mask,
+            blue_mask,
+        });
+    }
+
+    /// Returns the (red, green, blue) color of a pixel, (0, 0) being the top-left corner.
+    fn pixel(&self, x: u32, y: u32) -> (u8, u8, u8) {
+        let row: usize = if self.top_down {
+            y as usize
+        } else {
+            (self.height - 1 - y) as usize
+        };
+        let offset: usize = row * self.row_size + x as usize * self.bytes_per_pixel;
+
+        let mut value: u32 = 0;
+        for byte in 0..self.bytes_per_pixel {
+            value |= (self.pixels[offset + byte] as u32) << (byte * 8);
+        }
+
+        return (
+            extract_channel(value, self.red_mask),
+            extract_channel(value, self.green_mask),
+            extract_channel(value, self.blue_mask),
+        );
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
@@ -1,4 +1,10 @@
-use log::warn;
+use crate::fixed_string::FixedString;
+use boot_info::modules::{MAX_MODULES, MODULE_CMDLINE_MAX_SIZE, MODULE_NAME_MAX_SIZE};
+use core::fmt;
+use core::ptr::NonNull;
+use log::{warn, LevelFilter};
+use uefi::boot::{AllocateType, MemoryType};
+use uefi::proto::media::file::FileInfo;
 use uefi::{
     prelude::*,
     proto::media::{
@@ -7,6 +13,38 @@ use uefi::{
     },
 };
 
+/// The config file can't be larger than this (64 KiB), anything bigger is most likely not a config
+/// file at all.
+const MAX_CONFIG_SIZE: usize = 0x1_0000;
+/// The maximum length (in bytes) of a path read from the config file.
+pub const MAX_PATH_LEN: usize = 128;
+/// The maximum length (in bytes) of the kernel command line that can be given in the config file.
+pub const MAX_CMDLINE_LEN: usize = 256;
+/// The maximum number of boot entries that can be defined in the config file.
+pub const MAX_BOOT_ENTRIES: usize = 8;
+/// The maximum length (in bytes) of the title of a boot entry.
+pub const MAX_TITLE_LEN: usize = 48;
+/// The maximum length (in bytes) of a volume label given in the config file.
+pub const MAX_VOLUME_LABEL_LEN: usize = 36;
+/// The maximum number of keys that are not known by the bootloader that we still keep around.
+pub const MAX_EXTRA_ENTRIES: usize = 16;
+const MAX_KEY_LEN: usize = 32;
+const MAX_VALUE_LEN: usize = 128;
+const MAX_RESOLUTION: u64 = 16_384;
+/// The boot timeout can't be larger than 10 minutes.
+const MAX_BOOT_TIMEOUT: u64 = 600;
+/// The kernel stack size in KiB, if dog.cfg doesn't say otherwise.
+const DEFAULT_KERNEL_STACK_KIB: u64 = 64;
+/// The kernel stack can't be smaller than 16 KiB (the kernel wouldn't even get to log an overflow).
+const MIN_KERNEL_STACK_KIB: u64 = 16;
+/// The default entry can fail to boot this many times before the fallback entry is used, if
+/// dog.cfg doesn't say otherwise.
+const DEFAULT_MAX_BOOT_ATTEMPTS: u32 = 3;
+const MAX_BOOT_ATTEMPTS: u64 = 100;
+
+/// A SHA-256 digest.
+pub type Sha256Digest = [u8; 32];
+
 /// Will read the dog.cfg config file to establish boot preferences. Returns an Option<SystemConfig>, it also
 /// automatically writes a warning to the console if something went wrong.
 pub fn read_config() -> Option<SystemConfig> {
@@ -55,29 +93,376 @@ pub fn read_config() -> Option<SystemConfig> {
 }
 
 fn interpret_data(mut fs: file::RegularFile) -> Option<SystemConfig> {
-    let mut buffer: [u8; 1024] = [0; 1024];
-    let mut bytes_read_now: usize = 1;
+    let mut info_buffer: [u8; 512] = [0; 512];
+    let info: uefi::Result<&mut FileInfo, Option<usize>> =
+        fs.get_info::<FileInfo>(&mut info_buffer);
+    if info.is_err() {
+        let err_msg = info.err().unwrap();
+        warn!("Error reading system configuration: {err_msg}");
+        return None;
+    }
+
+    let file_size: usize = info.unwrap().file_size() as usize;
+    if file_size > MAX_CONFIG_SIZE {
+        warn!("Error reading system configuration: file is too large ({file_size} bytes)");
+        return None;
+    }
+
+    let page_count: usize = (file_size / 0x1000) + 1;
+    let data_ptr: uefi::Result<NonNull<u8>> =
+        boot::allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, page_count);
+    if data_ptr.is_err() {
+        let err_msg: uefi::Error = data_ptr.err().unwrap();
+        warn!("Error reading system configuration: {err_msg}");
+        return None;
+    }
+
+    let data_ptr: NonNull<u8> = data_ptr.unwrap();
+    let buffer: &mut [u8] =
+        unsafe { core::slice::from_raw_parts_mut(data_ptr.as_ptr(), file_size) };
     let mut total_bytes_read: usize = 0;
+    let mut config: Option<SystemConfig> = None;
+
+    loop {
+        if total_bytes_read == file_size {
+            config = Some(parse_config(&buffer[..total_bytes_read]));
+            break;
+        }
 
-    while bytes_read_now > 0 {
-        let read: Result<usize, uefi::Error> = fs.read(&mut buffer);
+        let read: Result<usize, uefi::Error> = fs.read(&mut buffer[total_bytes_read..]);
         if read.is_err() {
             let err_msg: uefi::Error = read.err().unwrap();
             warn!("Error reading system configuration: {err_msg}");
-            return None;
+            break;
+        }
+
+        let bytes_read_now: usize = read.unwrap();
+        //the file got shorter in the meantime, just use what we have
+        if bytes_read_now == 0 {
+            config = Some(parse_config(&buffer[..total_bytes_read]));
+            break;
         }
 
-        bytes_read_now = read.unwrap();
         total_bytes_read += bytes_read_now;
-        let result: Result<(), uefi::Error> = fs.set_position(total_bytes_read as u64);
-        if result.is_err() {
-            let err_msg: uefi::Error = result.err().unwrap();
-            warn!("Error reading system configuration: {err_msg}");
+    }
+
+    unsafe {
+        let _ = boot::free_pages(data_ptr, page_count);
+    }
+
+    return config;
+}
+
+/// Interprets the contents of dog.cfg. The format is line-based:
+/// - empty lines and lines starting with `#` or `;` are ignored;
+/// - every other line is `key = value`, where the value is an integer (decimal or `0x` hexadecimal),
+///   a boolean (`true`/`false`, `yes`/`no`, `on`/`off`) or a string (quoted or bare); bare values can
+///   be followed by a `#` comment;
+/// - an `[entry]` line starts a new boot entry; the keys after it (`title`, `kernel`, `cmdline`,
+///   `resolution`, `partition_guid` and `volume_label`, among others) belong to that entry, the
+///   missing ones are taken from the global keys above the first entry. If there are no entries, a
+///   single one is made from the global keys;
+/// - `partition_guid` or `volume_label` selects the volume the kernel and its initrd are loaded
+///   from, instead of the boot volume;
+/// - a `[module]` line starts a module, a file loaded for the kernel along with the initrd; the keys
+///   after it are `name` (what the kernel looks it up by), `path` and `cmdline` (optional).
+///
+/// Every invalid line is reported with its line number and skipped, so the corresponding setting
+/// keeps its default value.
+pub fn parse_config(data: &[u8]) -> SystemConfig {
+    let text: Result<&str, core::str::Utf8Error> = core::str::from_utf8(data);
+    if text.is_err() {
+        warn!("dog.cfg is not valid UTF-8, using the default configuration.");
+        return SystemConfig::default();
+    }
+
+    let mut config: SystemConfig = SystemConfig::without_entries();
+
+    let text: &str = text.unwrap();
+    let text: &str = text.strip_prefix('\u{feff}').unwrap_or(text);
+
+    let mut section: Section = Section::Global;
+
+    for (idx, line) in text.lines().enumerate() {
+        let line_num: usize = idx + 1;
+        let line: &str = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            section = config.begin_section(line, line_num);
+            continue;
+        }
+
+        let separator: Option<usize> = line.find('=');
+        if separator.is_none() {
+            warn!("dog.cfg:{line_num}: expected 'key = value', ignoring the line.");
+            continue;
+        }
+
+        let separator: usize = separator.unwrap();
+        let key: &str = line[..separator].trim();
+        if key.is_empty() {
+            warn!("dog.cfg:{line_num}: missing key before '=', ignoring the line.");
+            continue;
+        }
+
+        let value: Result<(ConfigValue, &str), &str> = parse_value(line[separator + 1..].trim());
+        if value.is_err() {
+            let err_msg: &str = value.err().unwrap();
+            warn!("dog.cfg:{line_num}: {err_msg}, ignoring '{key}'.");
+            continue;
+        }
+
+        let (value, text) = value.unwrap();
+        match section {
+            Section::Global => config.apply(key, value, text, line_num),
+            Section::Entry(entry_idx) => {
+                config.entries[entry_idx].apply(key, value, text, line_num)
+            }
+            Section::Module(module_idx) => {
+                config.modules[module_idx].apply(key, value, text, line_num)
+            }
+            Section::Ignored => {}
+        }
+    }
+
+    config.finalize();
+    return config;
+}
+
+/// The section of the config file that is currently parsed.
+#[derive(Clone, Copy)]
+enum Section {
+    /// The keys before the first section header.
+    Global,
+    /// An `[entry]` section, with the index of the entry.
+    Entry(usize),
+    /// A `[module]` section, with the index of the module.
+    Module(usize),
+    /// An unknown or invalid section, its keys are skipped.
+    Ignored,
+}
+
+/// A value from the config file.
+#[derive(Clone, Copy)]
+pub enum ConfigValue<'a> {
+    Integer(u64),
+    Boolean(bool),
+    String(&'a str),
+}
+
+impl<'a> ConfigValue<'a> {
+    pub fn as_integer(&self) -> Option<u64> {
+        match self {
+            ConfigValue::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            ConfigValue::Boolean(value) => Some(*value),
+            ConfigValue::Integer(0) => Some(false),
+            ConfigValue::Integer(1) => Some(true),
+            _ => None,
+        }
+    }
+
+    /// Strings are only returned for quoted or bare text values, not for integers or booleans.
+    pub fn as_str(&self) -> Option<&'a str> {
+        match self {
+            ConfigValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the typed value, as well as its text (without the quotes or the trailing comment).
+fn parse_value(raw_value: &str) -> Result<(ConfigValue<'_>, &str), &'static str> {
+    if raw_value.is_empty() {
+        return Err("missing value");
+    }
+
+    if let Some(quoted) = raw_value.strip_prefix('"') {
+        let end: Option<usize> = quoted.find('"');
+        if end.is_none() {
+            return Err("unterminated string");
+        }
+
+        let end: usize = end.unwrap();
+        let rest: &str = quoted[end + 1..].trim_start();
+        if !rest.is_empty() && !rest.starts_with('#') {
+            return Err("unexpected text after the closing quote");
+        }
+
+        return Ok((ConfigValue::String(&quoted[..end]), &quoted[..end]));
+    }
+
+    //bare values can have a comment after them
+    let value: &str = match raw_value.find('#') {
+        Some(idx) => raw_value[..idx].trim_end(),
+        None => raw_value,
+    };
+    if value.is_empty() {
+        return Err("missing value");
+    }
+
+    if let Some(number) = parse_integer(value) {
+        return Ok((ConfigValue::Integer(number), value));
+    }
+
+    return match value {
+        "true" | "yes" | "on" => Ok((ConfigValue::Boolean(true), value)),
+        "false" | "no" | "off" => Ok((ConfigValue::Boolean(false), value)),
+        _ => Ok((ConfigValue::String(value), value)),
+    };
+}
+
+fn parse_integer(value: &str) -> Option<u64> {
+    if let Some(hex) = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        return u64::from_str_radix(hex, 16).ok();
+    }
+
+    return value.parse::<u64>().ok();
+}
+
+/// Parses a resolution in the form of `<width>x<height>` (e.g. `1920x1080`).
+fn parse_resolution(value: &str) -> Option<(u32, u32)> {
+    let separator: usize = value.find(['x', 'X'])?;
+    let width: u64 = value[..separator].trim().parse::<u64>().ok()?;
+    let height: u64 = value[separator + 1..].trim().parse::<u64>().ok()?;
+
+    if !is_valid_dimension(width) || !is_valid_dimension(height) {
+        return None;
+    }
+
+    return Some((width as u32, height as u32));
+}
+
+/// Parses a GUID written as `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx` and returns its bytes in the
+/// order used by UEFI (the first three groups are little-endian).
+pub fn parse_guid(value: &str) -> Option<[u8; 16]> {
+    let mut groups: core::str::Split<char> = value.split('-');
+    let mut guid: [u8; 16] = [0; 16];
+    let mut idx: usize = 0;
+
+    for (group_idx, len) in [8, 4, 4, 4, 12].into_iter().enumerate() {
+        let group: &str = groups.next()?;
+        if group.len() != len || !group.bytes().all(|x| x.is_ascii_hexdigit()) {
             return None;
         }
+
+        let number: u64 = u64::from_str_radix(group, 16).ok()?;
+        let byte_count: usize = len / 2;
+        if group_idx < 3 {
+            guid[idx..idx + byte_count].copy_from_slice(&number.to_le_bytes()[..byte_count]);
+        } else {
+            guid[idx..idx + byte_count].copy_from_slice(&number.to_be_bytes()[8 - byte_count..]);
+        }
+
+        idx += byte_count;
     }
 
-    return Some(SystemConfig::default());
+    if groups.next().is_some() {
+        return None;
+    }
+
+    return Some(guid);
+}
+
+/// Parses a SHA-256 digest written as 64 hex digits (as `sha256sum` prints it).
+pub fn parse_sha256(value: &str) -> Option<Sha256Digest> {
+    let value: &[u8] = value.as_bytes();
+    if value.len() != 64 {
+        return None;
+    }
+
+    let mut digest: Sha256Digest = [0; 32];
+    for (idx, byte) in digest.iter_mut().enumerate() {
+        let high: u32 = (value[idx * 2] as char).to_digit(16)?;
+        let low: u32 = (value[idx * 2 + 1] as char).to_digit(16)?;
+        *byte = (high * 16 + low) as u8;
+    }
+
+    return Some(digest);
+}
+
+fn is_valid_dimension(value: u64) -> bool {
+    value > 0 && value <= MAX_RESOLUTION
+}
+
+fn parse_log_level(value: &str) -> Option<LevelFilter> {
+    match value {
+        "off" => Some(LevelFilter::Off),
+        "error" => Some(LevelFilter::Error),
+        "warn" | "warning" => Some(LevelFilter::Warn),
+        "info" => Some(LevelFilter::Info),
+        "debug" => Some(LevelFilter::Debug),
+        "trace" => Some(LevelFilter::Trace),
+        _ => None,
+    }
+}
+
+/// A key from the config file that the bootloader itself doesn't use, kept (as text) so it can be
+/// queried later.
+#[derive(Clone, Copy)]
+pub struct ExtraEntry {
+    key: FixedString<MAX_KEY_LEN>,
+    value: FixedString<MAX_VALUE_LEN>,
+}
+
+impl ExtraEntry {
+    pub fn key(&self) -> &str {
+        self.key.as_str()
+    }
+
+    pub fn value(&self) -> &str {
+        self.value.as_str()
+    }
+}
+
+/// The volume the kernel and its other files (the initrd and the digests) are loaded from.
+#[derive(Clone, Copy)]
+pub enum Volume {
+    /// The volume the bootloader itself was loaded from (usually the ESP).
+    Boot,
+    /// The GPT partition with this unique partition GUID (in the byte order used by UEFI).
+    PartitionGuid([u8; 16]),
+    /// The file system with this label (compared without regard to the ASCII case).
+    Label(FixedString<MAX_VOLUME_LABEL_LEN>),
+}
+
+impl fmt::Display for Volume {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Volume::Boot => write!(f, "the boot volume"),
+            Volume::PartitionGuid(guid) => {
+                write!(
+                    f,
+                    "partition {:08x}-{:04x}-{:04x}-",
+                    u32::from_le_bytes([guid[0], guid[1], guid[2], guid[3]]),
+                    u16::from_le_bytes([guid[4], guid[5]]),
+                    u16::from_le_bytes([guid[6], guid[7]])
+                )?;
+                for byte in &guid[8..10] {
+                    write!(f, "{byte:02x}")?;
+                }
+
+                write!(f, "-")?;
+                for byte in &guid[10..] {
+                    write!(f, "{byte:02x}")?;
+                }
+
+                return Ok(());
+            }
+            Volume::Label(label) => write!(f, "volume '{}'", label.as_str()),
+        }
+    }
 }
 
 /// The system configuration, the kernel preferences for boot. Respect if possible.
@@ -85,6 +470,41 @@ fn interpret_data(mut fs: file::RegularFile) -> Option<SystemConfig> {
 pub struct SystemConfig {
     pub preferred_width: u32,
     pub preferred_height: u32,
+    kernel_path: FixedString<MAX_PATH_LEN>,
+    cmdline: FixedString<MAX_CMDLINE_LEN>,
+    initrd_path: FixedString<MAX_PATH_LEN>,
+    kernel_sha256: Option<Sha256Digest>,
+    initrd_sha256: Option<Sha256Digest>,
+    volume: Volume,
+    splash_path: FixedString<MAX_PATH_LEN>,
+    log_level: LevelFilter,
+    /// In seconds.
+    boot_timeout: u32,
+    /// Whether the kernel is loaded at a random address (if it is relocatable).
+    kaslr: bool,
+    /// In bytes.
+    kernel_stack_size: u64,
+    /// Whether all the graphics modes are logged before one is chosen.
+    log_video_modes: bool,
+    /// Whether RGB modes are chosen over BGR and bitmask ones with the same resolution.
+    prefer_rgb: bool,
+    /// Whether the free memory is tested before the kernel is loaded.
+    memtest: bool,
+    extra_entries: [ExtraEntry; MAX_EXTRA_ENTRIES],
+    num_extra_entries: usize,
+    entries: [BootEntry; MAX_BOOT_ENTRIES],
+    num_entries: usize,
+    modules: [ModuleConfig; MAX_MODULES],
+    num_modules: usize,
+    default_entry: usize,
+    /// Set when the default entry is given by its title, it is resolved after all the entries are
+    /// known.
+    default_entry_title: FixedString<MAX_TITLE_LEN>,
+    /// Booted instead of the default entry after it failed to boot `max_boot_attempts` times.
+    fallback_entry: Option<usize>,
+    /// Same as `default_entry_title`, for the fallback entry.
+    fallback_entry_title: FixedString<MAX_TITLE_LEN>,
+    max_boot_attempts: u32,
 }
 
 impl SystemConfig {
@@ -96,18 +516,763 @@ impl SystemConfig {
         self.preferred_height
     }
 
+    /// The path of the kernel used by the entries that don't have their own.
+    pub fn kernel_path(&self) -> &str {
+        self.kernel_path.as_str()
+    }
+
+    /// The kernel command line used by the entries that don't have their own. Can be empty.
+    pub fn cmdline(&self) -> &str {
+        self.cmdline.as_str()
+    }
+
+    /// The path of the initial ramdisk used by the entries that don't have their own. Empty if there
+    /// is no initrd.
+    pub fn initrd_path(&self) -> &str {
+        self.initrd_path.as_str()
+    }
+
+    /// The volume of the kernel used by the entries that don't have their own.
+    pub fn volume(&self) -> &Volume {
+        &self.volume
+    }
+
+    /// The path of the BMP image shown while booting. Empty if there is no splash screen.
+    pub fn splash_path(&self) -> &str {
+        self.splash_path.as_str()
+    }
+
+    pub fn log_level(&self) -> LevelFilter {
+        self.log_level
+    }
+
+    /// The boot timeout in seconds.
+    pub fn boot_timeout(&self) -> u32 {
+        self.boot_timeout
+    }
+
+    pub fn kaslr(&self) -> bool {
+        self.kaslr
+    }
+
+    /// The size of the kernel stack in bytes.
+    pub fn kernel_stack_size(&self) -> u64 {
+        self.kernel_stack_size
+    }
+
+    pub fn log_video_modes(&self) -> bool {
+        self.log_video_modes
+    }
+
+    pub fn prefer_rgb(&self) -> bool {
+        self.prefer_rgb
+    }
+
+    pub fn memtest(&self) -> bool {
+        self.memtest
+    }
+
+    /// Returns the value of a key that is not known by the bootloader, as it was written in the
+    /// config file (without the quotes).
+    pub fn extra(&self, key: &str) -> Option<&str> {
+        self.extra_entries[..self.num_extra_entries]
+            .iter()
+            .find(|entry| entry.key() == key)
+            .map(|entry| entry.value())
+    }
+
+    pub fn extra_entries(&self) -> &[ExtraEntry] {
+        &self.extra_entries[..self.num_extra_entries]
+    }
+
+    /// The boot entries. There is always at least one entry.
+    pub fn entries(&self) -> &[BootEntry] {
+        &self.entries[..self.num_entries]
+    }
+
+    /// The modules loaded for the kernel of every entry, from the volume of the entry.
+    pub fn modules(&self) -> &[ModuleConfig] {
+        &self.modules[..self.num_modules]
+    }
+
+    /// The index of the entry booted when the user doesn't choose another one.
+    pub fn default_entry(&self) -> usize {
+        self.default_entry
+    }
+
+    /// The index of the entry booted instead of the default one once it failed to boot
+    /// [`SystemConfig::max_boot_attempts`] times in a row. None if there is no fallback entry.
+    pub fn fallback_entry(&self) -> Option<usize> {
+        self.fallback_entry
+    }
+
+    /// How many times the default entry can be booted without the kernel reporting success before
+    /// the fallback entry is used.
+    pub fn max_boot_attempts(&self) -> u32 {
+        self.max_boot_attempts
+    }
+
     pub fn new(width: u32, height: u32) -> Self {
-        SystemConfig {
-            preferred_width: width,
-            preferred_height: height,
-        }
+        let mut config: SystemConfig = SystemConfig::without_entries();
+        config.preferred_width = width;
+        config.preferred_height = height;
+
+        config.finalize();
+        return config;
     }
 
-    /// Returns a default SystemConfig with a width of 1920 and a height of 1080.
+    /// Returns a default SystemConfig with a width of 1920, a height of 1080, the kernel at
+    /// `boot\kernel.elf` without a command line, the initrd at `boot\initrd`, the splash screen at
+    /// `boot\splash.bmp` (all on the boot volume), the `info` log level, a boot timeout of 5
+    /// seconds, KASLR enabled, a 64 KiB kernel stack, the graphics modes not logged, no preference
+    /// for RGB modes, no memory test and a single entry made from these settings, without a
+    /// fallback entry (3 boot attempts if one is set).
     pub fn default() -> Self {
+        let mut config: SystemConfig = SystemConfig::without_entries();
+        config.finalize();
+        return config;
+    }
+
+    /// The default settings without any boot entry, what the config file is parsed into.
+    fn without_entries() -> Self {
         SystemConfig {
             preferred_width: 1920,
             preferred_height: 1080,
+            kernel_path: FixedString::from_str("boot\\kernel.elf").unwrap(),
+            cmdline: FixedString::new(),
+            initrd_path: FixedString::from_str("boot\\initrd").unwrap(),
+            kernel_sha256: None,
+            initrd_sha256: None,
+            volume: Volume::Boot,
+            splash_path: FixedString::from_str("boot\\splash.bmp").unwrap(),
+            log_level: LevelFilter::Info,
+            boot_timeout: 5,
+            kaslr: true,
+            kernel_stack_size: DEFAULT_KERNEL_STACK_KIB * 1024,
+            log_video_modes: false,
+            prefer_rgb: false,
+            memtest: false,
+            extra_entries: [ExtraEntry {
+                key: FixedString::new(),
+                value: FixedString::new(),
+            }; MAX_EXTRA_ENTRIES],
+            num_extra_entries: 0,
+            entries: [BootEntry::empty(); MAX_BOOT_ENTRIES],
+            num_entries: 0,
+            modules: [ModuleConfig::empty(); MAX_MODULES],
+            num_modules: 0,
+            default_entry: 0,
+            default_entry_title: FixedString::new(),
+            fallback_entry: None,
+            fallback_entry_title: FixedString::new(),
+            max_boot_attempts: DEFAULT_MAX_BOOT_ATTEMPTS,
+        }
+    }
+
+    /// Handles a section header line (e.g. `[entry]`) and returns the section that starts with it.
+    fn begin_section(&mut self, line: &str, line_num: usize) -> Section {
+        let name: Option<&str> = line.strip_prefix('[').and_then(|x| x.strip_suffix(']'));
+        if name.is_none() {
+            warn!("dog.cfg:{line_num}: invalid section header, ignoring the section.");
+            return Section::Ignored;
+        }
+
+        match name.unwrap().trim() {
+            "entry" => {
+                if self.num_entries >= MAX_BOOT_ENTRIES {
+                    warn!("dog.cfg:{line_num}: there can't be more than {MAX_BOOT_ENTRIES} entries, ignoring the entry.");
+                    return Section::Ignored;
+                }
+
+                //the global keys are always above the first section, so they are all known here
+                self.entries[self.num_entries] = BootEntry::from_globals(self);
+                self.num_entries += 1;
+                return Section::Entry(self.num_entries - 1);
+            }
+            "module" => {
+                if self.num_modules >= MAX_MODULES {
+                    warn!("dog.cfg:{line_num}: there can't be more than {MAX_MODULES} modules, ignoring the module.");
+                    return Section::Ignored;
+                }
+
+                self.modules[self.num_modules] = ModuleConfig::empty();
+                self.num_modules += 1;
+                return Section::Module(self.num_modules - 1);
+            }
+            other => {
+                warn!("dog.cfg:{line_num}: unknown section '{other}', ignoring it.");
+                return Section::Ignored;
+            }
+        }
+    }
+
+    /// Called after the whole file is parsed.
+    fn finalize(&mut self) {
+        //the modules without a name or a path can't be used, and a name can only be looked up once
+        let mut num_modules: usize = 0;
+        for idx in 0..self.num_modules {
+            let module: ModuleConfig = self.modules[idx];
+            if module.name.is_empty() || module.path.is_empty() {
+                warn!(
+                    "dog.cfg: module {} needs a name and a path, ignoring it.",
+                    idx + 1
+                );
+                continue;
+            }
+
+            if self.modules[..num_modules]
+                .iter()
+                .any(|x| x.name() == module.name())
+            {
+                warn!(
+                    "dog.cfg: there is already a module called '{}', ignoring the other one.",
+                    module.name()
+                );
+                continue;
+            }
+
+            self.modules[num_modules] = module;
+            num_modules += 1;
+        }
+
+        self.num_modules = num_modules;
+
+        if self.num_entries == 0 {
+            self.entries[0] = BootEntry::from_globals(self);
+            self.num_entries = 1;
+        }
+
+        if !self.default_entry_title.is_empty() {
+            let title: &str = self.default_entry_title.as_str();
+            let idx: Option<usize> = self.entries().iter().position(|x| x.title() == title);
+            if idx.is_none() {
+                warn!(
+                    "dog.cfg: there is no entry called '{title}', using the first one as default."
+                );
+            }
+
+            self.default_entry = idx.unwrap_or(0);
+        }
+
+        if self.default_entry >= self.num_entries {
+            warn!("dog.cfg: the default entry doesn't exist, using the first one.");
+            self.default_entry = 0;
+        }
+
+        if !self.fallback_entry_title.is_empty() {
+            let title: &str = self.fallback_entry_title.as_str();
+            self.fallback_entry = self.entries().iter().position(|x| x.title() == title);
+            if self.fallback_entry.is_none() {
+                warn!("dog.cfg: there is no entry called '{title}', there is no fallback entry.");
+            }
+        }
+
+        if self
+            .fallback_entry
+            .is_some_and(|x| x >= self.num_entries || x == self.default_entry)
+        {
+            warn!("dog.cfg: the fallback entry doesn't exist or is the default one, ignoring it.");
+            self.fallback_entry = None;
+        }
+    }
+
+    fn apply(&mut self, key: &str, value: ConfigValue, text: &str, line_num: usize) {
+        match key {
+            "width" | "height" => {
+                let dimension: Option<u64> = value.as_integer().filter(|x| is_valid_dimension(*x));
+                if dimension.is_none() {
+                    warn!("dog.cfg:{line_num}: '{key}' must be an integer between 1 and {MAX_RESOLUTION}.");
+                    return;
+                }
+
+                if key == "width" {
+                    self.preferred_width = dimension.unwrap() as u32;
+                } else {
+                    self.preferred_height = dimension.unwrap() as u32;
+                }
+            }
+            "resolution" => {
+                let resolution: Option<(u32, u32)> = read_resolution(value, line_num);
+                if resolution.is_some() {
+                    (self.preferred_width, self.preferred_height) = resolution.unwrap();
+                }
+            }
+            "kernel" | "kernel_path" => {
+                let path: Option<FixedString<MAX_PATH_LEN>> = read_path(key, value, line_num);
+                if path.is_some() {
+                    self.kernel_path = path.unwrap();
+                }
+            }
+            "cmdline" => {
+                let cmdline: Option<FixedString<MAX_CMDLINE_LEN>> = read_cmdline(text, line_num);
+                if cmdline.is_some() {
+                    self.cmdline = cmdline.unwrap();
+                }
+            }
+            "initrd" => {
+                let path: Option<FixedString<MAX_PATH_LEN>> =
+                    read_optional_path(key, value, line_num);
+                if path.is_some() {
+                    self.initrd_path = path.unwrap();
+                }
+            }
+            "kernel_sha256" => {
+                let digest: Option<Sha256Digest> = read_sha256(key, text, line_num);
+                if digest.is_some() {
+                    self.kernel_sha256 = digest;
+                }
+            }
+            "initrd_sha256" => {
+                let digest: Option<Sha256Digest> = read_sha256(key, text, line_num);
+                if digest.is_some() {
+                    self.initrd_sha256 = digest;
+                }
+            }
+            "partition_guid" | "volume_label" => {
+                let volume: Option<Volume> = read_volume(key, text, line_num);
+                if volume.is_some() {
+                    self.volume = volume.unwrap();
+                }
+            }
+            "splash" => {
+                let path: Option<FixedString<MAX_PATH_LEN>> =
+                    read_optional_path(key, value, line_num);
+                if path.is_some() {
+                    self.splash_path = path.unwrap();
+                }
+            }
+            "log_level" => {
+                //"off" is also a boolean, so look at the text itself
+                let level: Option<LevelFilter> = parse_log_level(text);
+                if level.is_none() {
+                    warn!(
+                        "dog.cfg:{line_num}: 'log_level' must be one of off, error, warn, info, debug or trace."
+                    );
+                    return;
+                }
+
+                self.log_level = level.unwrap();
+            }
+            "timeout" | "boot_timeout" => {
+                let timeout: Option<u64> = value.as_integer().filter(|x| *x <= MAX_BOOT_TIMEOUT);
+                if timeout.is_none() {
+                    warn!("dog.cfg:{line_num}: '{key}' must be a number of seconds up to {MAX_BOOT_TIMEOUT}.");
+                    return;
+                }
+
+                self.boot_timeout = timeout.unwrap() as u32;
+            }
+            "kaslr" => {
+                let kaslr: Option<bool> = value.as_bool();
+                if kaslr.is_none() {
+                    warn!("dog.cfg:{line_num}: 'kaslr' must be a boolean.");
+                    return;
+                }
+
+                self.kaslr = kaslr.unwrap();
+            }
+            "kernel_stack_size" => {
+                const MAX_KERNEL_STACK_KIB: u64 = boot_info::KERNEL_STACK_MAX_SIZE / 1024;
+
+                let size: Option<u64> = value
+                    .as_integer()
+                    .filter(|x| (MIN_KERNEL_STACK_KIB..=MAX_KERNEL_STACK_KIB).contains(x));
+                if size.is_none() {
+                    warn!(
+                        "dog.cfg:{line_num}: 'kernel_stack_size' must be a size in KiB between {MIN_KERNEL_STACK_KIB} and {MAX_KERNEL_STACK_KIB}."
+                    );
+                    return;
+                }
+
+                self.kernel_stack_size = size.unwrap() * 1024;
+            }
+            "log_video_modes" | "prefer_rgb" | "memtest" => {
+                let flag: Option<bool> = value.as_bool();
+                if flag.is_none() {
+                    warn!("dog.cfg:{line_num}: '{key}' must be a boolean.");
+                    return;
+                }
+
+                match key {
+                    "log_video_modes" => self.log_video_modes = flag.unwrap(),
+                    "prefer_rgb" => self.prefer_rgb = flag.unwrap(),
+                    _ => self.memtest = flag.unwrap(),
+                }
+            }
+            "default" => {
+                if let Some(idx) = value.as_integer() {
+                    self.default_entry = idx as usize;
+                    return;
+                }
+
+                let title: Option<FixedString<MAX_TITLE_LEN>> = FixedString::from_str(text);
+                if title.is_none() {
+                    warn!("dog.cfg:{line_num}: 'default' must be an entry index or title.");
+                    return;
+                }
+
+                self.default_entry_title = title.unwrap();
+            }
+            "fallback" => {
+                if let Some(idx) = value.as_integer() {
+                    self.fallback_entry = Some(idx as usize);
+                    return;
+                }
+
+                let title: Option<FixedString<MAX_TITLE_LEN>> = FixedString::from_str(text);
+                if title.is_none() {
+                    warn!("dog.cfg:{line_num}: 'fallback' must be an entry index or title.");
+                    return;
+                }
+
+                self.fallback_entry_title = title.unwrap();
+            }
+            "max_boot_attempts" => {
+                let attempts: Option<u64> = value
+                    .as_integer()
+                    .filter(|x| (1..=MAX_BOOT_ATTEMPTS).contains(x));
+                if attempts.is_none() {
+                    warn!(
+                        "dog.cfg:{line_num}: 'max_boot_attempts' must be a number between 1 and {MAX_BOOT_ATTEMPTS}."
+                    );
+                    return;
+                }
+
+                self.max_boot_attempts = attempts.unwrap() as u32;
+            }
+            _ => self.add_extra_entry(key, text, line_num),
+        }
+    }
+
+    fn add_extra_entry(&mut self, key: &str, value: &str, line_num: usize) {
+        let entry_key: Option<FixedString<MAX_KEY_LEN>> = FixedString::from_str(key);
+        let entry_value: Option<FixedString<MAX_VALUE_LEN>> = FixedString::from_str(value);
+        if entry_key.is_none() || entry_value.is_none() {
+            warn!("dog.cfg:{line_num}: '{key}' or its value is too long, ignoring it.");
+            return;
+        }
+
+        //a key that appears again overrides the previous value
+        let num_entries: usize = self.num_extra_entries;
+        let existing: Option<&mut ExtraEntry> = self.extra_entries[..num_entries]
+            .iter_mut()
+            .find(|entry| entry.key() == key);
+        if let Some(existing) = existing {
+            existing.value = entry_value.unwrap();
+            return;
+        }
+
+        if num_entries >= MAX_EXTRA_ENTRIES {
+            warn!("dog.cfg:{line_num}: too many unknown keys, ignoring '{key}'.");
+            return;
+        }
+
+        self.extra_entries[num_entries] = ExtraEntry {
+            key: entry_key.unwrap(),
+            value: entry_value.unwrap(),
+        };
+        self.num_extra_entries += 1;
+    }
+}
+
+/// A file loaded for the kernel from a `[module]` section.
+#[derive(Clone, Copy)]
+pub struct ModuleConfig {
+    name: FixedString<MODULE_NAME_MAX_SIZE>,
+    path: FixedString<MAX_PATH_LEN>,
+    cmdline: FixedString<MODULE_CMDLINE_MAX_SIZE>,
+}
+
+impl ModuleConfig {
+    /// The name the kernel looks the module up by.
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// The path of the module, relative to the root of the volume of the booted entry.
+    pub fn path(&self) -> &str {
+        self.path.as_str()
+    }
+
+    /// The command line given to the module. Can be empty.
+    pub fn cmdline(&self) -> &str {
+        self.cmdline.as_str()
+    }
+
+    const fn empty() -> Self {
+        ModuleConfig {
+            name: FixedString::new(),
+            path: FixedString::new(),
+            cmdline: FixedString::new(),
         }
     }
+
+    fn apply(&mut self, key: &str, value: ConfigValue, text: &str, line_num: usize) {
+        match key {
+            "name" => {
+                let name: Option<FixedString<MODULE_NAME_MAX_SIZE>> =
+                    FixedString::from_str(text).filter(|x| !x.is_empty());
+                if name.is_none() {
+                    warn!("dog.cfg:{line_num}: 'name' must have between 1 and {MODULE_NAME_MAX_SIZE} bytes.");
+                    return;
+                }
+
+                self.name = name.unwrap();
+            }
+            "path" => {
+                let path: Option<FixedString<MAX_PATH_LEN>> = read_path(key, value, line_num);
+                if path.is_some() {
+                    self.path = path.unwrap();
+                }
+            }
+            "cmdline" => {
+                let cmdline: Option<FixedString<MODULE_CMDLINE_MAX_SIZE>> =
+                    FixedString::from_str(text);
+                if cmdline.is_none() {
+                    warn!("dog.cfg:{line_num}: the command line of a module can't have more than {MODULE_CMDLINE_MAX_SIZE} bytes.");
+                    return;
+                }
+
+                self.cmdline = cmdline.unwrap();
+            }
+            _ => {
+                warn!("dog.cfg:{line_num}: unknown key '{key}' for a module, ignoring it.");
+            }
+        }
+    }
+}
+
+/// A kernel that can be booted, with its own command line and resolution.
+#[derive(Clone, Copy)]
+pub struct BootEntry {
+    title: FixedString<MAX_TITLE_LEN>,
+    kernel_path: FixedString<MAX_PATH_LEN>,
+    cmdline: FixedString<MAX_CMDLINE_LEN>,
+    initrd_path: FixedString<MAX_PATH_LEN>,
+    kernel_sha256: Option<Sha256Digest>,
+    initrd_sha256: Option<Sha256Digest>,
+    volume: Volume,
+    preferred_width: u32,
+    preferred_height: u32,
+}
+
+impl BootEntry {
+    pub fn title(&self) -> &str {
+        self.title.as_str()
+    }
+
+    /// The path of the kernel, relative to the root of [`BootEntry::volume`].
+    pub fn kernel_path(&self) -> &str {
+        self.kernel_path.as_str()
+    }
+
+    /// The command line given to the kernel. Can be empty.
+    pub fn cmdline(&self) -> &str {
+        self.cmdline.as_str()
+    }
+
+    /// Replaces the command line (used when the user edits it from the boot menu).
+    pub fn set_cmdline(&mut self, cmdline: FixedString<MAX_CMDLINE_LEN>) {
+        self.cmdline = cmdline;
+    }
+
+    /// The path of the initial ramdisk, relative to the root of [`BootEntry::volume`]. Empty if there
+    /// is no initrd.
+    pub fn initrd_path(&self) -> &str {
+        self.initrd_path.as_str()
+    }
+
+    /// The expected SHA-256 digest of the kernel, if it is given in the config file.
+    pub fn kernel_sha256(&self) -> Option<&Sha256Digest> {
+        self.kernel_sha256.as_ref()
+    }
+
+    /// The expected SHA-256 digest of the initrd, if it is given in the config file.
+    pub fn initrd_sha256(&self) -> Option<&Sha256Digest> {
+        self.initrd_sha256.as_ref()
+    }
+
+    /// The volume the kernel, the initrd and their digests are loaded from.
+    pub fn volume(&self) -> &Volume {
+        &self.volume
+    }
+
+    pub fn preferred_width(&self) -> u32 {
+        self.preferred_width
+    }
+
+    pub fn preferred_height(&self) -> u32 {
+        self.preferred_height
+    }
+
+    const fn empty() -> Self {
+        BootEntry {
+            title: FixedString::new(),
+            kernel_path: FixedString::new(),
+            cmdline: FixedString::new(),
+            initrd_path: FixedString::new(),
+            kernel_sha256: None,
+            initrd_sha256: None,
+            volume: Volume::Boot,
+            preferred_width: 0,
+            preferred_height: 0,
+        }
+    }
+
+    fn from_globals(config: &SystemConfig) -> Self {
+        BootEntry {
+            title: FixedString::from_str("ChihuahuaOS").unwrap(),
+            kernel_path: config.kernel_path,
+            cmdline: config.cmdline,
+            initrd_path: config.initrd_path,
+            kernel_sha256: config.kernel_sha256,
+            initrd_sha256: config.initrd_sha256,
+            volume: config.volume,
+            preferred_width: config.preferred_width,
+            preferred_height: config.preferred_height,
+        }
+    }
+
+    fn apply(&mut self, key: &str, value: ConfigValue, text: &str, line_num: usize) {
+        match key {
+            "title" => {
+                let title: Option<FixedString<MAX_TITLE_LEN>> =
+                    FixedString::from_str(text).filter(|x| !x.is_empty());
+                if title.is_none() {
+                    warn!("dog.cfg:{line_num}: 'title' must have between 1 and {MAX_TITLE_LEN} bytes.");
+                    return;
+                }
+
+                self.title = title.unwrap();
+            }
+            "kernel" | "kernel_path" => {
+                let path: Option<FixedString<MAX_PATH_LEN>> = read_path(key, value, line_num);
+                if path.is_some() {
+                    self.kernel_path = path.unwrap();
+                    //a digest inherited from the globals is for another file, so the digest of
+                    //the entry must come after its path
+                    self.kernel_sha256 = None;
+                }
+            }
+            "cmdline" => {
+                let cmdline: Option<FixedString<MAX_CMDLINE_LEN>> = read_cmdline(text, line_num);
+                if cmdline.is_some() {
+                    self.cmdline = cmdline.unwrap();
+                }
+            }
+            "initrd" => {
+                let path: Option<FixedString<MAX_PATH_LEN>> =
+                    read_optional_path(key, value, line_num);
+                if path.is_some() {
+                    self.initrd_path = path.unwrap();
+                    self.initrd_sha256 = None;
+                }
+            }
+            "kernel_sha256" => {
+                let digest: Option<Sha256Digest> = read_sha256(key, text, line_num);
+                if digest.is_some() {
+                    self.kernel_sha256 = digest;
+                }
+            }
+            "initrd_sha256" => {
+                let digest: Option<Sha256Digest> = read_sha256(key, text, line_num);
+                if digest.is_some() {
+                    self.initrd_sha256 = digest;
+                }
+            }
+            "resolution" => {
+                let resolution: Option<(u32, u32)> = read_resolution(value, line_num);
+                if resolution.is_some() {
+                    (self.preferred_width, self.preferred_height) = resolution.unwrap();
+                }
+            }
+            "partition_guid" | "volume_label" => {
+                let volume: Option<Volume> = read_volume(key, text, line_num);
+                if volume.is_some() {
+                    self.volume = volume.unwrap();
+                }
+            }
+            _ => {
+                warn!("dog.cfg:{line_num}: unknown key '{key}' for a boot entry, ignoring it.");
+            }
+        }
+    }
+}
+
+fn read_path(key: &str, value: ConfigValue, line_num: usize) -> Option<FixedString<MAX_PATH_LEN>> {
+    let path: Option<FixedString<MAX_PATH_LEN>> = value
+        .as_str()
+        .filter(|x| !x.is_empty())
+        .and_then(FixedString::<MAX_PATH_LEN>::from_str);
+    if path.is_none() {
+        warn!("dog.cfg:{line_num}: '{key}' must be a path of at most {MAX_PATH_LEN} bytes.");
+    }
+
+    return path;
+}
+
+/// Same as [`read_path`], but an empty string is allowed (it means "none").
+fn read_optional_path(
+    key: &str,
+    value: ConfigValue,
+    line_num: usize,
+) -> Option<FixedString<MAX_PATH_LEN>> {
+    if value.as_str() == Some("") {
+        return Some(FixedString::new());
+    }
+
+    return read_path(key, value, line_num);
+}
+
+/// Reads a `partition_guid` or a `volume_label`. An empty value means the boot volume.
+fn read_volume(key: &str, text: &str, line_num: usize) -> Option<Volume> {
+    if text.is_empty() {
+        return Some(Volume::Boot);
+    }
+
+    if key == "partition_guid" {
+        let guid: Option<[u8; 16]> = parse_guid(text);
+        if guid.is_none() {
+            warn!("dog.cfg:{line_num}: 'partition_guid' must be a GUID (xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx).");
+        }
+
+        return guid.map(Volume::PartitionGuid);
+    }
+
+    let label: Option<FixedString<MAX_VOLUME_LABEL_LEN>> = FixedString::from_str(text);
+    if label.is_none() {
+        warn!(
+            "dog.cfg:{line_num}: 'volume_label' can't have more than {MAX_VOLUME_LABEL_LEN} bytes."
+        );
+    }
+
+    return label.map(Volume::Label);
+}
+
+fn read_sha256(key: &str, text: &str, line_num: usize) -> Option<Sha256Digest> {
+    let digest: Option<Sha256Digest> = parse_sha256(text);
+    if digest.is_none() {
+        warn!("dog.cfg:{line_num}: '{key}' must be a SHA-256 digest (64 hex digits).");
+    }
+
+    return digest;
+}
+
+fn read_cmdline(text: &str, line_num: usize) -> Option<FixedString<MAX_CMDLINE_LEN>> {
+    //the command line is free text, so flags like "nosmp" or numbers are fine as well
+    let cmdline: Option<FixedString<MAX_CMDLINE_LEN>> = FixedString::from_str(text);
+    if cmdline.is_none() {
+        warn!("dog.cfg:{line_num}: 'cmdline' can't be longer than {MAX_CMDLINE_LEN} bytes.");
+    }
+
+    return cmdline;
+}
+
+fn read_resolution(value: ConfigValue, line_num: usize) -> Option<(u32, u32)> {
+    let resolution: Option<(u32, u32)> = value.as_str().and_then(parse_resolution);
+    if resolution.is_none() {
+        warn!("dog.cfg:{line_num}: 'resolution' must look like 1920x1080.");
+    }
+
+    return resolution;
 }
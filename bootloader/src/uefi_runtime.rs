@@ -0,0 +1,147 @@
+use uefi::boot::{MemoryAttribute, MemoryType};
+use uefi::mem::memory_map::{MemoryDescriptor, MemoryMap, MemoryMapOwned};
+
+/// The maximum number of memory regions the runtime services can use.
+const MAX_RUNTIME_REGIONS: usize = 64;
+
+/// A memory region the runtime services need, and where it is mapped in the kernel address space.
+#[derive(Clone, Copy)]
+pub struct RuntimeRegion {
+    ty: MemoryType,
+    phys_start: u64,
+    virt_start: u64,
+    page_count: u64,
+}
+
+impl RuntimeRegion {
+    pub fn ty(&self) -> MemoryType {
+        self.ty
+    }
+
+    pub fn phys_start(&self) -> u64 {
+        self.phys_start
+    }
+
+    pub fn virt_start(&self) -> u64 {
+        self.virt_start
+    }
+
+    pub fn page_count(&self) -> u64 {
+        self.page_count
+    }
+}
+
+/// Where the memory of the runtime services is mapped, in the window at
+/// [`boot_info::UEFI_RUNTIME_VIRTUAL_ADDRESS`]. The regions are placed one after another, in the
+/// order of their physical addresses.
+pub struct RuntimeLayout {
+    regions: [RuntimeRegion; MAX_RUNTIME_REGIONS],
+    num_regions: usize,
+}
+
+impl RuntimeLayout {
+    pub fn regions(&self) -> &[RuntimeRegion] {
+        &self.regions[..self.num_regions]
+    }
+
+    /// Returns the virtual address of a physical address inside one of the runtime regions.
+    pub fn phys_to_virt(&self, phys_addr: u64) -> Option<u64> {
+        self.regions()
+            .iter()
+            .find(|x| phys_addr >= x.phys_start && phys_addr < x.phys_start + x.page_count * 0x1000)
+            .map(|x| x.virt_start + (phys_addr - x.phys_start))
+    }
+}
+
+/// Chooses the virtual addresses of all the memory map entries with the RUNTIME attribute. Must be
+/// done before the page tables are set up, so they can be mapped. Returns None if they don't fit
+/// in the runtime window (the runtime services are then only usable with physical addresses).
+pub fn plan_runtime_layout(mem_map: &MemoryMapOwned) -> Option<RuntimeLayout> {
+    let mut layout: RuntimeLayout = RuntimeLayout {
+        regions: [RuntimeRegion {
+            ty: MemoryType::RESERVED,
+            phys_start: 0,
+            virt_start: 0,
+            page_count: 0,
+        }; MAX_RUNTIME_REGIONS],
+        num_regions: 0,
+    };
+
+    let mut virt_addr: u64 = boot_info::UEFI_RUNTIME_VIRTUAL_ADDRESS;
+    for entry in mem_map
+        .entries()
+        .filter(|x| x.att.contains(MemoryAttribute::RUNTIME))
+    {
+        let size: u64 = entry.page_count * 0x1000;
+        if layout.num_regions >= MAX_RUNTIME_REGIONS
+            || virt_addr + size
+                > boot_info::UEFI_RUNTIME_VIRTUAL_ADDRESS + boot_info::UEFI_RUNTIME_MAX_SIZE
+        {
+            return None;
+        }
+
+        layout.regions[layout.num_regions] = RuntimeRegion {
+            ty: entry.ty,
+            phys_start: entry.phys_start,
+            virt_start: virt_addr,
+            page_count: entry.page_count,
+        };
+        layout.num_regions += 1;
+        virt_addr += size;
+    }
+
+    return Some(layout);
+}
+
+/// Tells the firmware where the runtime services are mapped (SetVirtualAddressMap). Must be called
+/// after exiting the boot services, with the final memory map. After this, the runtime services
+/// can only be called with the kernel page tables (and the EFI system table must not be read
+/// through the uefi crate anymore). Returns false if the firmware rejected the layout or if the
+/// runtime regions changed since the layout was planned.
+pub fn set_virtual_address_map(
+    layout: &RuntimeLayout,
+    final_mem_map: &MemoryMapOwned,
+    system_table_phys_addr: u64,
+) -> bool {
+    let mut descriptors: [MemoryDescriptor; MAX_RUNTIME_REGIONS] = [MemoryDescriptor {
+        ty: MemoryType::RESERVED,
+        phys_start: 0,
+        virt_start: 0,
+        page_count: 0,
+        att: MemoryAttribute::empty(),
+    }; MAX_RUNTIME_REGIONS];
+    let mut num_descriptors: usize = 0;
+
+    for entry in final_mem_map
+        .entries()
+        .filter(|x| x.att.contains(MemoryAttribute::RUNTIME))
+    {
+        //all the runtime regions must be mapped, otherwise the firmware would crash later
+        let region: Option<&RuntimeRegion> = layout
+            .regions()
+            .iter()
+            .find(|x| x.phys_start == entry.phys_start && x.page_count == entry.page_count);
+        if region.is_none() || num_descriptors >= MAX_RUNTIME_REGIONS {
+            return false;
+        }
+
+        let mut descriptor: MemoryDescriptor = *entry;
+        descriptor.virt_start = region.unwrap().virt_start;
+        descriptors[num_descriptors] = descriptor;
+        num_descriptors += 1;
+    }
+
+    let system_table_virt_addr: Option<u64> = layout.phys_to_virt(system_table_phys_addr);
+    if system_table_virt_addr.is_none() {
+        return false;
+    }
+
+    let result: uefi::Result = unsafe {
+        uefi::runtime::set_virtual_address_map(
+            &mut descriptors[..num_descriptors],
+            system_table_virt_addr.unwrap() as *const _,
+        )
+    };
+
+    return result.is_ok();
+}
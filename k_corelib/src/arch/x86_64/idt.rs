@@ -1,8 +1,10 @@
 use crate::arch::x86_64::gdt_tss;
 use crate::interrupts::x86_64_pic_interrupts;
 use crate::interrupts::{InterruptArguments, InterruptHandler};
+use crate::kernel_stack;
 use dog_essentials::lazy_static::lazy_static;
 use dog_essentials::static_cell::StaticCell;
+use x86_64::registers::control::Cr2;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
 
 lazy_static! {
@@ -122,6 +124,10 @@ extern "x86-interrupt" fn on_page_fault(
     _error_code: PageFaultErrorCode,
 ) {
     PAGE_FAULT_HANDLER.get_value_unsafe()(get_args(&stack_frame));
+    if kernel_stack::is_in_guard_page(Cr2::read_raw()) {
+        panic!("Kernel stack overflow!");
+    }
+
     panic!("Page fault!");
 }
 
@@ -132,6 +138,12 @@ extern "x86-interrupt" fn on_simd_fpe(stack_frame: InterruptStackFrame) {
 extern "x86-interrupt" fn on_double_fault(stack_frame: InterruptStackFrame, error_code: u64) -> ! {
     DOUBLE_FAULT_HANDLER.get_value_unsafe()(get_args(&stack_frame));
 
+    //an overflow of the kernel stack faults again when the CPU pushes the page fault frame, so it
+    //ends up here (on its own stack) with CR2 still in the guard page
+    if kernel_stack::is_in_guard_page(Cr2::read_raw()) {
+        panic!("Kernel stack overflow! Stack frame:\n{:#?}", stack_frame);
+    }
+
     panic!(
         "Double fault! Error code: {:#?}\n Stack frame:\n{:#?}",
         error_code, stack_frame
@@ -1,3 +1,4 @@
 pub mod gdt_tss;
 pub mod idt;
 pub mod ports;
+pub mod tsc;
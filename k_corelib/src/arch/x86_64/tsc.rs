@@ -0,0 +1,24 @@
+use core::arch::x86_64::{__cpuid, _rdtsc};
+
+/// Reads the time stamp counter.
+#[inline]
+pub fn read() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Returns the frequency of the TSC in Hz if the CPU reports it (CPUID leaf 0x15, only on newer
+/// Intel CPUs and not on most hypervisors).
+pub fn frequency_from_cpuid() -> Option<u64> {
+    let max_leaf: u32 = __cpuid(0).eax;
+    if max_leaf < 0x15 {
+        return None;
+    }
+
+    //TSC frequency = crystal frequency * numerator / denominator
+    let leaf = __cpuid(0x15);
+    if leaf.eax == 0 || leaf.ebx == 0 || leaf.ecx == 0 {
+        return None;
+    }
+
+    return Some(leaf.ecx as u64 * leaf.ebx as u64 / leaf.eax as u64);
+}
@@ -0,0 +1,97 @@
+use crate::arch::x86_64::tsc;
+use crate::k_drivers::x86_64::pit;
+use crate::log;
+use boot_info::handoff::boot_stages;
+use dog_essentials::format_non_alloc::u64_to_str;
+use dog_essentials::static_cell::StaticCell;
+
+/// The TSC at the end of each stage of the bootloader, followed by the TSC when the kernel was
+/// entered.
+static TIMESTAMPS: StaticCell<[u64; boot_stages::COUNT + 1]> =
+    StaticCell::new([0; boot_stages::COUNT + 1]);
+
+/// Remembers the timestamps given by the bootloader and timestamps the entry in the kernel. Should
+/// be called as early as possible.
+pub fn init(boot_tsc_timestamps: &[u64; boot_stages::COUNT]) {
+    let mut timestamps: [u64; boot_stages::COUNT + 1] = [0; boot_stages::COUNT + 1];
+    timestamps[..boot_stages::COUNT].copy_from_slice(boot_tsc_timestamps);
+    timestamps[boot_stages::COUNT] = tsc::read();
+
+    TIMESTAMPS.set_value_unsafe(timestamps);
+}
+
+/// Calibrates the TSC and logs how long each stage of the boot took, in microseconds since the
+/// bootloader was started.
+pub fn log_timeline() {
+    let timestamps: &[u64; boot_stages::COUNT + 1] = TIMESTAMPS.get_value_unsafe();
+    let start: u64 = timestamps[boot_stages::ENTRY];
+    if start == 0 {
+        log::log_info("No boot timestamps were given by the bootloader.");
+        return;
+    }
+
+    let frequency: Option<u64> = tsc::frequency_from_cpuid().or_else(pit::calibrate_tsc);
+    if frequency.is_none() {
+        log::log_warn("Couldn't calibrate the TSC, the boot timeline is not available.");
+        return;
+    }
+
+    let frequency: u64 = frequency.unwrap();
+    log::log_info("Boot timeline (microseconds since the bootloader was started):");
+
+    let mut previous: u64 = start;
+    for (stage, &timestamp) in timestamps.iter().enumerate().skip(1) {
+        //stages that were not timestamped (or a TSC that went backwards) are skipped
+        if timestamp < previous {
+            continue;
+        }
+
+        let name: &str = if stage < boot_stages::COUNT {
+            boot_stages::NAMES[stage]
+        } else {
+            "kernel entered"
+        };
+        let mut line: LineBuffer = LineBuffer::new();
+        line.push("  ");
+        line.push(name);
+        line.push(": ");
+        line.push(u64_to_str(ticks_to_us(timestamp - start, frequency)).to_str());
+        line.push(" (+");
+        line.push(u64_to_str(ticks_to_us(timestamp - previous, frequency)).to_str());
+        line.push(")");
+        log::log_info(line.as_str());
+
+        previous = timestamp;
+    }
+}
+
+fn ticks_to_us(ticks: u64, frequency: u64) -> u64 {
+    return (ticks as u128 * 1_000_000 / frequency as u128) as u64;
+}
+
+/// A line of the timeline, so it can be logged at once.
+struct LineBuffer {
+    data: [u8; 80],
+    len: usize,
+}
+
+impl LineBuffer {
+    fn new() -> Self {
+        LineBuffer {
+            data: [0; 80],
+            len: 0,
+        }
+    }
+
+    /// Appends as much of the string as fits.
+    fn push(&mut self, s: &str) {
+        let count: usize = s.len().min(self.data.len() - self.len);
+        self.data[self.len..self.len + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.len += count;
+    }
+
+    fn as_str(&self) -> &str {
+        //only whole ASCII strings are pushed
+        core::str::from_utf8(&self.data[..self.len]).unwrap_or("")
+    }
+}
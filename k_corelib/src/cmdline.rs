@@ -0,0 +1,209 @@
+use crate::log;
+use crate::log::Severity;
+use dog_essentials::static_cell::StaticCell;
+
+static CMDLINE: StaticCell<&'static str> = StaticCell::new("");
+static OPTIONS: StaticCell<KernelOptions> = StaticCell::new(KernelOptions::default());
+
+/// The options the kernel understands, parsed from the command line given by the bootloader.
+/// Options that are not known by the kernel can still be read with [`get`] and [`has_flag`].
+pub struct KernelOptions {
+    /// `loglevel=<verbose|debug|info|warn|error|fatal|1-6>`: messages below this severity are not
+    /// written to the debug log.
+    log_level: Severity,
+    /// `serial=<on|off>`: whether the debug log is written to the serial port.
+    serial: bool,
+    /// `nosmp`: only use the boot processor.
+    smp: bool,
+    /// `pmm.debug`: the physical memory manager reports what it does.
+    pmm_debug: bool,
+}
+
+impl KernelOptions {
+    /// Returns the options used when there is no command line.
+    pub const fn default() -> Self {
+        KernelOptions {
+            log_level: Severity::Verbose,
+            serial: true,
+            smp: true,
+            pmm_debug: false,
+        }
+    }
+
+    pub fn log_level(&self) -> Severity {
+        self.log_level
+    }
+
+    pub fn serial(&self) -> bool {
+        self.serial
+    }
+
+    pub fn smp(&self) -> bool {
+        self.smp
+    }
+
+    pub fn pmm_debug(&self) -> bool {
+        self.pmm_debug
+    }
+
+    /// Parses the given command line. Invalid values are reported and ignored, so the option keeps
+    /// its default value.
+    pub fn parse(cmdline: &str) -> Self {
+        let mut options: KernelOptions = KernelOptions::default();
+
+        for (key, value) in CmdlineIter::new(cmdline) {
+            match key {
+                "loglevel" => {
+                    let level: Option<Severity> = value.and_then(parse_severity);
+                    if level.is_none() {
+                        log::log_warn("cmdline: invalid value for loglevel, ignoring it.");
+                        continue;
+                    }
+
+                    options.log_level = level.unwrap();
+                }
+                "serial" => {
+                    let serial: Option<bool> = value.and_then(parse_bool);
+                    if serial.is_none() {
+                        log::log_warn("cmdline: serial must be on or off, ignoring it.");
+                        continue;
+                    }
+
+                    options.serial = serial.unwrap();
+                }
+                "nosmp" => {
+                    options.smp = !is_flag_set(value);
+                }
+                "pmm.debug" => {
+                    options.pmm_debug = is_flag_set(value);
+                }
+                _ => {}
+            }
+        }
+
+        return options;
+    }
+}
+
+/// Iterates over the options of a command line. Each option is a `(key, value)` pair, where the value
+/// is None for flags (options without `=`). Options are separated by whitespace; a value can be
+/// quoted if it contains spaces (e.g. `root="my disk"`), the quotes are not part of the value.
+pub struct CmdlineIter<'a> {
+    rest: &'a str,
+}
+
+impl<'a> CmdlineIter<'a> {
+    pub fn new(cmdline: &'a str) -> Self {
+        CmdlineIter { rest: cmdline }
+    }
+}
+
+impl<'a> Iterator for CmdlineIter<'a> {
+    type Item = (&'a str, Option<&'a str>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rest = self.rest.trim_start();
+        if self.rest.is_empty() {
+            return None;
+        }
+
+        //find the end of the option, whitespace inside quotes doesn't count
+        let mut in_quotes: bool = false;
+        let mut end: usize = self.rest.len();
+        for (idx, chr) in self.rest.char_indices() {
+            if chr == '"' {
+                in_quotes = !in_quotes;
+            } else if chr.is_whitespace() && !in_quotes {
+                end = idx;
+                break;
+            }
+        }
+
+        let option: &'a str = &self.rest[..end];
+        self.rest = &self.rest[end..];
+
+        return match option.find('=') {
+            Some(idx) => {
+                let value: &'a str = &option[idx + 1..];
+                let value: &'a str = value
+                    .strip_prefix('"')
+                    .and_then(|x| x.strip_suffix('"'))
+                    .unwrap_or(value);
+                Some((&option[..idx], Some(value)))
+            }
+            None => Some((option, None)),
+        };
+    }
+}
+
+/// Reads the command line given by the bootloader and parses the options the kernel knows about.
+/// Should be called as early as possible, as other subsystems (e.g. logging) depend on it.
+pub fn init(cmdline_addr: u64, cmdline_size: u32) {
+    if cmdline_size == 0 || cmdline_size > boot_info::CMDLINE_MAX_SIZE {
+        return;
+    }
+
+    let cmdline: &'static [u8] =
+        unsafe { core::slice::from_raw_parts(cmdline_addr as *const u8, cmdline_size as usize) };
+    let cmdline: Result<&'static str, core::str::Utf8Error> = core::str::from_utf8(cmdline);
+    if cmdline.is_err() {
+        log::log_warn("cmdline: the command line is not valid UTF-8, ignoring it.");
+        return;
+    }
+
+    let cmdline: &'static str = cmdline.unwrap();
+    CMDLINE.set_value_unsafe(cmdline);
+    OPTIONS.set_value_unsafe(KernelOptions::parse(cmdline));
+}
+
+/// Returns the whole command line, as given by the bootloader.
+pub fn cmdline() -> &'static str {
+    CMDLINE.get_value_unsafe()
+}
+
+pub fn options() -> &'static KernelOptions {
+    OPTIONS.get_value_unsafe()
+}
+
+/// Returns the value of the given option. If the option appears multiple times, the last one wins.
+/// Returns None if the option is missing or if it is a flag.
+pub fn get(key: &str) -> Option<&'static str> {
+    CmdlineIter::new(cmdline())
+        .filter(|option| option.0 == key)
+        .last()
+        .and_then(|option| option.1)
+}
+
+/// Returns true if the given option is present, either as a flag (`nosmp`) or with a truthy value
+/// (`nosmp=1`).
+pub fn has_flag(key: &str) -> bool {
+    CmdlineIter::new(cmdline())
+        .filter(|option| option.0 == key)
+        .last()
+        .is_some_and(|option| is_flag_set(option.1))
+}
+
+/// A flag is set if it has no value or if its value is truthy.
+fn is_flag_set(value: Option<&str>) -> bool {
+    value.is_none_or(|x| parse_bool(x).unwrap_or(false))
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "1" | "on" | "yes" | "true" => Some(true),
+        "0" | "off" | "no" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_severity(value: &str) -> Option<Severity> {
+    match value {
+        "verbose" | "1" => Some(Severity::Verbose),
+        "debug" | "2" => Some(Severity::Debug),
+        "info" | "3" => Some(Severity::Info),
+        "warn" | "4" => Some(Severity::Warn),
+        "error" | "5" => Some(Severity::Error),
+        "fatal" | "6" => Some(Severity::Fatal),
+        _ => None,
+    }
+}
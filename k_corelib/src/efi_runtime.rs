@@ -0,0 +1,57 @@
+use crate::log;
+use dog_essentials::static_cell::StaticCell;
+use r_efi::efi;
+
+/// The virtual address of the EFI Runtime Services table, 0 if they can't be used.
+static RUNTIME_SERVICES: StaticCell<u64> = StaticCell::new(0);
+
+/// Remembers where the runtime services are. They can only be used if the bootloader remapped them
+/// in the runtime window, the firmware expects virtual addresses after that.
+pub fn init(rs_virt_addr: u64) {
+    let window: core::ops::Range<u64> = boot_info::UEFI_RUNTIME_VIRTUAL_ADDRESS
+        ..boot_info::UEFI_RUNTIME_VIRTUAL_ADDRESS + boot_info::UEFI_RUNTIME_MAX_SIZE;
+    if !window.contains(&rs_virt_addr) {
+        log::log_warn("UEFI runtime services: not remapped by the bootloader, they won't be used.");
+        return;
+    }
+
+    RUNTIME_SERVICES.set_value_unsafe(rs_virt_addr);
+}
+
+/// Deletes the boot attempt counter of the bootloader, so it keeps booting the default entry. Must
+/// be called once the kernel booted successfully. Returns false if the runtime services can't be
+/// used or the firmware refused.
+pub fn clear_boot_attempts() -> bool {
+    let rs_addr: u64 = *RUNTIME_SERVICES.get_value_unsafe();
+    if rs_addr == 0 {
+        return false;
+    }
+
+    let mut name: [u16; 32] = [0; 32];
+    for (i, chr) in boot_info::BOOT_ATTEMPTS_VARIABLE_NAME
+        .encode_utf16()
+        .take(name.len() - 1)
+        .enumerate()
+    {
+        name[i] = chr;
+    }
+
+    let mut vendor: efi::Guid = efi::Guid::from_bytes(&boot_info::BOOTLOADER_VENDOR_GUID);
+    let attributes: u32 = efi::VARIABLE_NON_VOLATILE
+        | efi::VARIABLE_BOOTSERVICE_ACCESS
+        | efi::VARIABLE_RUNTIME_ACCESS;
+
+    //a size of 0 deletes the variable
+    let status: efi::Status = unsafe {
+        let rs: &efi::RuntimeServices = &*(rs_addr as *const efi::RuntimeServices);
+        (rs.set_variable)(
+            name.as_mut_ptr(),
+            &mut vendor,
+            attributes,
+            0,
+            core::ptr::null_mut(),
+        )
+    };
+
+    return status == efi::Status::SUCCESS || status == efi::Status::NOT_FOUND;
+}
@@ -0,0 +1,28 @@
+use crate::log;
+use dog_essentials::static_cell::StaticCell;
+
+static INITRD: StaticCell<Option<&'static [u8]>> = StaticCell::new(None);
+
+/// Remembers where the bootloader put the initial ramdisk. Should be called before the memory
+/// managers are set up, so the initrd is not overwritten.
+pub fn init(initrd_addr: u64, initrd_size: u64) {
+    if initrd_addr == 0 || initrd_size == 0 {
+        log::log_info("No initrd was given by the bootloader.");
+        return;
+    }
+
+    if initrd_addr != boot_info::INITRD_VIRTUAL_ADDRESS || initrd_size > boot_info::INITRD_MAX_SIZE
+    {
+        log::log_warn("initrd: invalid address or size, ignoring it.");
+        return;
+    }
+
+    let data: &'static [u8] =
+        unsafe { core::slice::from_raw_parts(initrd_addr as *const u8, initrd_size as usize) };
+    INITRD.set_value_unsafe(Some(data));
+}
+
+/// Returns the contents of the initial ramdisk (read-only), if the bootloader loaded one.
+pub fn data() -> Option<&'static [u8]> {
+    *INITRD.get_value_unsafe()
+}
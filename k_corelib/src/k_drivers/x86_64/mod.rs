@@ -1,2 +1,3 @@
 pub mod com_debug;
 pub mod pic;
+pub mod pit;
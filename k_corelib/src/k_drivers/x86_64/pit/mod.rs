@@ -0,0 +1,51 @@
+use crate::arch::x86_64::tsc;
+use crate::ports;
+
+/// The frequency of the PIT oscillator in Hz.
+const PIT_FREQUENCY: u64 = 1_193_182;
+const CHANNEL_2_DATA_PORT: u32 = 0x42;
+const COMMAND_PORT: u32 = 0x43;
+/// Bit 0 is the gate of channel 2, bit 1 enables the speaker and bit 5 is the output of channel 2.
+const SPEAKER_CONTROL_PORT: u32 = 0x61;
+
+/// Channel 2, low byte then high byte, mode 0 (interrupt on terminal count), binary.
+const CMD_CHANNEL_2_ONE_SHOT: u8 = 0b1011_0000;
+
+/// How long the TSC is measured for.
+const CALIBRATION_MS: u64 = 10;
+/// If the output of channel 2 doesn't go high after this many TSC ticks, there is no working PIT.
+const CALIBRATION_MAX_TICKS: u64 = 10_000_000_000;
+
+/// Measures the frequency of the TSC in Hz, by counting its ticks during a one-shot of PIT channel
+/// 2. This doesn't need interrupts. Returns None if the PIT doesn't seem to work.
+pub fn calibrate_tsc() -> Option<u64> {
+    unsafe {
+        let speaker_control: u8 = ports::read_u8(SPEAKER_CONTROL_PORT);
+        //gate off and speaker off, so the count doesn't start yet
+        ports::write_u8(SPEAKER_CONTROL_PORT, speaker_control & !0b11);
+
+        let count: u64 = PIT_FREQUENCY * CALIBRATION_MS / 1000;
+        ports::write_u8(COMMAND_PORT, CMD_CHANNEL_2_ONE_SHOT);
+        ports::write_u8(CHANNEL_2_DATA_PORT, (count & 0xff) as u8);
+        ports::write_u8(CHANNEL_2_DATA_PORT, (count >> 8) as u8);
+
+        //a rising edge on the gate starts the count
+        ports::write_u8(SPEAKER_CONTROL_PORT, (speaker_control & !0b10) | 0b1);
+        let start: u64 = tsc::read();
+        let mut end: u64 = start;
+        while ports::read_u8(SPEAKER_CONTROL_PORT) & 0x20 == 0 {
+            end = tsc::read();
+            if end - start > CALIBRATION_MAX_TICKS {
+                ports::write_u8(SPEAKER_CONTROL_PORT, speaker_control);
+                return None;
+            }
+        }
+
+        ports::write_u8(SPEAKER_CONTROL_PORT, speaker_control);
+        if end == start {
+            return None;
+        }
+
+        return Some((end - start) * 1000 / CALIBRATION_MS);
+    }
+}
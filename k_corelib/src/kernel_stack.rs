@@ -0,0 +1,42 @@
+use crate::log;
+use dog_essentials::static_cell::StaticCell;
+
+/// The range of the kernel stack (bottom, top), as given by the bootloader.
+static KERNEL_STACK: StaticCell<(u64, u64)> = StaticCell::new((0, 0));
+
+/// Remembers where the bootloader put the kernel stack, so overflows can be recognized.
+pub fn init(stack_bottom: u64, stack_top: u64) {
+    if stack_bottom != boot_info::KERNEL_STACK_GUARD_ADDRESS + 0x1000
+        || stack_top <= stack_bottom
+        || stack_top - stack_bottom > boot_info::KERNEL_STACK_MAX_SIZE
+    {
+        log::log_warn(
+            "Kernel stack: invalid range given by the bootloader, overflows won't be detected.",
+        );
+        return;
+    }
+
+    KERNEL_STACK.set_value_unsafe((stack_bottom, stack_top));
+}
+
+/// Returns the lowest address and the address right above the kernel stack, or None if the
+/// bootloader didn't report it.
+pub fn range() -> Option<(u64, u64)> {
+    let range: (u64, u64) = *KERNEL_STACK.get_value_unsafe();
+    if range.1 == 0 {
+        return None;
+    }
+
+    return Some(range);
+}
+
+/// Returns true if the address is in the guard page below the kernel stack, which means a page
+/// fault at that address is a stack overflow.
+pub fn is_in_guard_page(addr: u64) -> bool {
+    if range().is_none() {
+        return false;
+    }
+
+    return (boot_info::KERNEL_STACK_GUARD_ADDRESS..boot_info::KERNEL_STACK_GUARD_ADDRESS + 0x1000)
+        .contains(&addr);
+}
@@ -5,8 +5,14 @@
 #[allow(unused_imports)]
 use k_panic_handler;
 
+pub mod boot_timeline;
+pub mod cmdline;
+pub mod efi_runtime;
+pub mod initrd;
 pub mod interrupts;
+pub mod kernel_stack;
 pub mod log;
+pub mod modules;
 pub mod platform_initializer;
 pub mod ports;
 pub mod renderer;
@@ -1,3 +1,4 @@
+use crate::cmdline;
 use crate::k_drivers::x86_64;
 use core::str;
 use dog_essentials::sync::mutex::Mutex;
@@ -5,6 +6,11 @@ use dog_essentials::sync::mutex::Mutex;
 static WRITE_LOCK: Mutex<bool> = Mutex::new(false);
 
 pub fn log(severity: Severity, message: &str) {
+    let options: &cmdline::KernelOptions = cmdline::options();
+    if !options.serial() || severity < options.log_level() {
+        return;
+    }
+
     init();
 
     match severity {
@@ -57,6 +63,10 @@ pub fn log_fatal(message: &str) {
 }
 
 pub fn log_raw(message: &str) {
+    if !cmdline::options().serial() {
+        return;
+    }
+
     init();
     write(message);
 }
@@ -145,6 +155,7 @@ fn init() {
     x86_64::com_debug::init_serial();
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Severity {
     Verbose = 1,
     Debug = 2,
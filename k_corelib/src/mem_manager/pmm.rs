@@ -1,7 +1,8 @@
-use crate::log;
-use boot_info::memory_map::{MemoryMapEntry, MemoryType};
+use crate::{cmdline, log};
+use boot_info::memory_map::MemoryMapEntry;
 use core::ptr;
 use core::sync::atomic::{AtomicU64, Ordering};
+use dog_essentials::format_non_alloc::u64_to_str;
 use dog_essentials::static_cell::StaticCell;
 
 static PHYS_MEMORY_USED: StaticCell<AtomicU64> = StaticCell::new(AtomicU64::new(0));
@@ -447,10 +448,7 @@ pub(super) fn init_from_mem_map(mem_map_size: u32) {
                 }
             }
 
-            if data.mem_type() != MemoryType::Conventional
-                && data.mem_type() != MemoryType::BootServicesCode
-                && data.mem_type() != MemoryType::BootServicesData
-            {
+            if !data.mem_type().is_free_after_boot() {
                 for j in 0..data.num_pages() {
                     mark_page_used(data.physical_addr() + j * 0x1000);
                 }
@@ -486,6 +484,11 @@ pub(super) fn init_from_mem_map(mem_map_size: u32) {
         *(l3_virt_addr as *mut u64) |= u64::MAX << l3_index + 1;
         *(l4_virt_addr as *mut u64) |= u64::MAX << l4_index + 1;
     }
+
+    if cmdline::options().pmm_debug() {
+        log::log_debug("PMM: initialized from the memory map, KiB in use:");
+        log::log_debug(u64_to_str(get_phys_memory_used() / 1024).to_str());
+    }
 }
 
 /// Returns the virtual address of the bitmap for the next free table. Only for superior levels 5,
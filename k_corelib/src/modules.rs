@@ -0,0 +1,53 @@
+use crate::log;
+use boot_info::modules::{ModuleEntry, MAX_MODULES};
+use dog_essentials::static_cell::StaticCell;
+
+static MODULES: StaticCell<&'static [ModuleEntry]> = StaticCell::new(&[]);
+
+/// Remembers the table of modules the bootloader loaded. Should be called before the memory
+/// managers are set up, so the modules are not overwritten.
+pub fn init(table_addr: u64, count: u32) {
+    if table_addr == 0 || count == 0 {
+        log::log_info("No modules were given by the bootloader.");
+        return;
+    }
+
+    if table_addr != boot_info::MODULES_VIRTUAL_ADDRESS || count as usize > MAX_MODULES {
+        log::log_warn("modules: invalid table address or entry count, ignoring them.");
+        return;
+    }
+
+    let table: &'static [ModuleEntry] =
+        unsafe { core::slice::from_raw_parts(table_addr as *const ModuleEntry, count as usize) };
+
+    //an entry pointing outside the modules range would make data() read arbitrary memory
+    let modules_end: u64 = boot_info::MODULES_VIRTUAL_ADDRESS + boot_info::MODULES_MAX_SIZE;
+    let is_valid: bool = table.iter().all(|entry: &ModuleEntry| {
+        entry.virt_addr() > boot_info::MODULES_VIRTUAL_ADDRESS
+            && entry.virt_addr() < modules_end
+            && entry.size() <= modules_end - entry.virt_addr()
+    });
+    if !is_valid {
+        log::log_warn("modules: an entry is outside the modules range, ignoring them.");
+        return;
+    }
+
+    MODULES.set_value_unsafe(table);
+}
+
+/// Returns all the modules the bootloader loaded, in the order they were listed in `dog.cfg`.
+pub fn all() -> &'static [ModuleEntry] {
+    MODULES.get_value_unsafe()
+}
+
+/// Returns the module with the given name, if the bootloader loaded one.
+pub fn find(name: &str) -> Option<&'static ModuleEntry> {
+    all()
+        .iter()
+        .find(|entry: &&ModuleEntry| entry.name() == name)
+}
+
+/// Returns the contents of a module (read-only).
+pub fn data(entry: &ModuleEntry) -> &'static [u8] {
+    unsafe { core::slice::from_raw_parts(entry.virt_addr() as *const u8, entry.size() as usize) }
+}
@@ -304,11 +304,9 @@ fn convert_color_format(color: u32) -> u32 {
             return color;
         }
 
-        //if BGR32, skip expensive calculations and just rotate bits
+        //if red is in the first byte, skip expensive calculations and just swap red and blue
         if RED_MASK == 0xff && GREEN_MASK == 0xff_00 && BLUE_MASK == 0xff_00_00 {
-            //red from byte 3 to byte 2, green from byte 2 to byte 3, and blue from byte 1 to byte 4
-            //last byte is reserved
-            return ((color & 0xff_00_00) >> 8) | ((color & 0xff_00) << 8) | ((color & 0xff) << 24);
+            return ((color & 0xff_00_00) >> 16) | (color & 0xff_00) | ((color & 0xff) << 16);
         }
     }
 
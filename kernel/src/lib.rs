@@ -3,17 +3,63 @@
 
 #[allow(dead_code)]
 use boot_info;
+use boot_info::handoff::{Handoff, HandoffError};
+use boot_info::KParams;
+use dog_essentials::format_non_alloc;
+use k_corelib::boot_timeline;
+use k_corelib::cmdline;
+use k_corelib::efi_runtime;
+use k_corelib::initrd;
+use k_corelib::kernel_stack;
 use k_corelib::log;
 use k_corelib::mem_manager::vmm;
+use k_corelib::modules;
 use k_corelib::platform_initializer;
 use k_corelib::renderer;
 use k_corelib::renderer::text_writer;
 
+mod limine;
+
 #[unsafe(no_mangle)]
-pub extern "C" fn kmain(k_params: *const boot_info::KParams) -> ! {
+pub extern "C" fn kmain(handoff_addr: *const u8) -> ! {
+    //the bootloader and the kernel are built separately, make sure we understand each other
+    let handoff: Result<Handoff, HandoffError> = unsafe { Handoff::from_ptr(handoff_addr) };
+    if handoff.is_err() {
+        halt_on_bad_handoff(handoff.err().unwrap());
+    }
+
+    let k_params: Result<KParams, HandoffError> = KParams::from_handoff(&handoff.unwrap());
+    if k_params.is_err() {
+        halt_on_bad_handoff(k_params.err().unwrap());
+    }
+
+    kernel_main(k_params.unwrap());
+}
+
+/// The entry point used when the kernel is started by Limine (see [`limine`]).
+pub(crate) extern "C" fn kmain_limine() -> ! {
+    let k_params: Option<KParams> = limine::read_params();
+    if k_params.is_none() {
+        log::log_error("The kernel can't be booted by this version of Limine.");
+        halt();
+    }
+
+    kernel_main(k_params.unwrap());
+}
+
+/// Everything after the boot parameters were read, whatever bootloader started the kernel.
+fn kernel_main(k_params: KParams) -> ! {
+    boot_timeline::init(&k_params.boot_tsc_timestamps);
+    //the command line decides how (and if) we log, so read it first
+    cmdline::init(boot_info::CMDLINE_VIRTUAL_ADDRESS, k_params.cmdline_size);
     log::log_debug("Entered in kernel.");
+    log_kernel_base(k_params.kernel_virt_base);
+    initrd::init(k_params.initrd_addr, k_params.initrd_size);
+    modules::init(k_params.modules_table_addr, k_params.modules_count);
+    kernel_stack::init(k_params.kernel_stack_bottom, k_params.kernel_stack_top);
+    efi_runtime::init(k_params.uefi_rs_virt_addr);
 
-    let fb_info: &boot_info::framebuffer::FramebufferData = unsafe { &(*k_params).fb_data };
+    let fb_info: &boot_info::framebuffer::FramebufferData = &k_params.fb_data;
     renderer::setup_fb(fb_info);
 
     let fg_col: renderer::Color = renderer::Color::from_u32(0xff_ff_ff);
@@ -22,12 +68,13 @@ pub extern "C" fn kmain(k_params: *const boot_info::KParams) -> ! {
     text_writer::write(b"Kernel booted!\n", fg_col, bg_col);
     platform_initializer::initialize_platform();
 
-    unsafe {
-        vmm::init(
-            (*k_params).memory_map_size,
-            (*k_params).page_table_num_entries,
-        );
-        text_writer::write(b"Setup memory.\n", fg_col, bg_col);
+    vmm::init(k_params.memory_map_size, k_params.phys_direct_map_offset);
+    text_writer::write(b"Setup memory.\n", fg_col, bg_col);
+    boot_timeline::log_timeline();
+
+    //we got this far, so the bootloader can keep booting this kernel
+    if !efi_runtime::clear_boot_attempts() {
+        log::log_warn("Couldn't clear the boot attempt counter, a fallback entry might be booted.");
     }
 
     //trigger double fault
@@ -52,3 +99,37 @@ pub extern "C" fn kmain(k_params: *const boot_info::KParams) -> ! {
         }
     }
 }
+
+/// The kernel might have been moved by the bootloader (KASLR), so the addresses in a crash are only
+/// useful with the base address.
+fn log_kernel_base(kernel_base: u64) {
+    log::log_debug("Kernel base:");
+    log::log_debug(format_non_alloc::u64_to_str_base(kernel_base, 16).to_str());
+}
+
+/// We can't use anything the bootloader gave us (not even the framebuffer), so only the serial log
+/// can tell what went wrong.
+fn halt_on_bad_handoff(err: HandoffError) -> ! {
+    match err {
+        HandoffError::IncompatibleVersion { major, minor: _ } => {
+            log::log_error("The bootloader uses an incompatible boot handoff version:");
+            log::log_error(format_non_alloc::u64_to_str(major as u64).to_str());
+        }
+        HandoffError::MissingTag(tag_type) => {
+            log::log_error("The bootloader didn't give a required boot handoff tag:");
+            log::log_error(format_non_alloc::u64_to_str(tag_type as u64).to_str());
+        }
+        _ => log::log_error("The kernel was not started by a compatible bootloader."),
+    }
+
+    halt();
+}
+
+fn halt() -> ! {
+    loop {
+        unsafe {
+            core::arch::asm!("cli");
+            core::arch::asm!("hlt");
+        }
+    }
+}
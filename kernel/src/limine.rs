@@ -0,0 +1,562 @@
+use boot_info::framebuffer::FramebufferData;
+use boot_info::memory_map::{MemoryMapEntry, MemoryType};
+use boot_info::KParams;
+use core::cell::UnsafeCell;
+use core::ptr;
+use k_corelib::log;
+use k_corelib::mem_manager::vmm;
+
+//the Limine boot protocol (https://github.com/limine-bootloader/limine/blob/trunk/PROTOCOL.md):
+//the bootloader finds the requests in the kernel image and fills in their responses before jumping
+//to the entry point of ENTRY_POINT_REQUEST; our own bootloader ignores all of this and jumps to the
+//ELF entry point (kmain) instead
+
+const COMMON_MAGIC: [u64; 2] = [0xc7b1dd30df4c8b88, 0x0a82e883a194f07b];
+/// Revision 3: the HHDM only covers memory (not MMIO) and the firmware table addresses are physical.
+const BASE_REVISION: u64 = 3;
+
+const MEMMAP_USABLE: u64 = 0;
+const MEMMAP_RESERVED: u64 = 1;
+const MEMMAP_ACPI_RECLAIMABLE: u64 = 2;
+const MEMMAP_ACPI_NVS: u64 = 3;
+const MEMMAP_BAD_MEMORY: u64 = 4;
+const MEMMAP_BOOTLOADER_RECLAIMABLE: u64 = 5;
+const MEMMAP_EXECUTABLE_AND_MODULES: u64 = 6;
+const MEMMAP_FRAMEBUFFER: u64 = 7;
+
+/// Our bootloader gives the memory map in 16 pages, so the kernel doesn't expect more entries.
+const MEM_MAP_PAGES: u64 = 16;
+const MAX_MEM_MAP_ENTRIES: usize = (MEM_MAP_PAGES as usize * 0x1000) / size_of::<MemoryMapEntry>();
+/// The PMM needs a 36 KiB bitmap section for each GiB of RAM, up to 4 TiB.
+const PMM_SECTION_PAGES: u64 = 9;
+const PMM_MAX_RAM: u64 = 0x400_0000_0000;
+/// The frames set aside for the page tables needed to map everything at our fixed addresses.
+const PAGE_TABLE_PAGES: u64 = 64;
+
+#[used]
+#[unsafe(link_section = ".requests_start_marker")]
+static REQUESTS_START_MARKER: [u64; 4] = [
+    0xf6b8f4b39de7d1ae,
+    0xfab91a6940fcb9cf,
+    0x785c6ed015d3e316,
+    0x181e920a7852b9d9,
+];
+
+#[used]
+#[unsafe(link_section = ".requests")]
+static BASE_REVISION_TAG: BaseRevision = BaseRevision(UnsafeCell::new([
+    0xf9562b2d5c95a6c8,
+    0x6a7b384944536bdc,
+    BASE_REVISION,
+]));
+
+#[used]
+#[unsafe(link_section = ".requests")]
+static ENTRY_POINT_REQUEST: EntryPointRequest = EntryPointRequest {
+    id: [
+        COMMON_MAGIC[0],
+        COMMON_MAGIC[1],
+        0x13d86c035a1cd3e1,
+        0x2b0caa89d8f3026a,
+    ],
+    revision: 0,
+    response: UnsafeCell::new(ptr::null()),
+    entry: crate::kmain_limine,
+};
+
+#[used]
+#[unsafe(link_section = ".requests")]
+static FRAMEBUFFER_REQUEST: Request<FramebufferResponse> =
+    Request::new([0x9d5827dcd881dd75, 0xa3148604f6fab11b]);
+
+#[used]
+#[unsafe(link_section = ".requests")]
+static MEMMAP_REQUEST: Request<MemmapResponse> =
+    Request::new([0x67cf3d9d378a806f, 0xe304acdfc50c3c62]);
+
+#[used]
+#[unsafe(link_section = ".requests")]
+static HHDM_REQUEST: Request<HhdmResponse> = Request::new([0x48dcf1cb8ad2b852, 0x63984e959a98244b]);
+
+#[used]
+#[unsafe(link_section = ".requests")]
+static EXECUTABLE_ADDRESS_REQUEST: Request<ExecutableAddressResponse> =
+    Request::new([0x71ba76863cc55f63, 0xb2644a48c516a487]);
+
+#[used]
+#[unsafe(link_section = ".requests")]
+static EXECUTABLE_FILE_REQUEST: Request<ExecutableFileResponse> =
+    Request::new([0xad97e90e83f1ed67, 0x31eb5d1c5ff23b69]);
+
+/// The first module is used as the initrd.
+#[used]
+#[unsafe(link_section = ".requests")]
+static MODULE_REQUEST: Request<ModuleResponse> =
+    Request::new([0x3e7e279702be32af, 0xca1c4f3bd1280cee]);
+
+#[used]
+#[unsafe(link_section = ".requests")]
+static RSDP_REQUEST: Request<RsdpResponse> = Request::new([0xc5e77b6b397e7b43, 0x27637845accdcf3c]);
+
+#[used]
+#[unsafe(link_section = ".requests")]
+static SMBIOS_REQUEST: Request<SmbiosResponse> =
+    Request::new([0x9e9046f11e095391, 0xaa4a520fefbde5ee]);
+
+#[used]
+#[unsafe(link_section = ".requests_end_marker")]
+static REQUESTS_END_MARKER: [u64; 2] = [0xadc0e0531bb10d03, 0x9572709f31764c62];
+
+/// Limine sets the last element to 0 if it supports [`BASE_REVISION`].
+#[repr(transparent)]
+struct BaseRevision(UnsafeCell<[u64; 3]>);
+
+unsafe impl Sync for BaseRevision {}
+
+//only the bootloader reads or writes some of the fields
+#[allow(dead_code)]
+#[repr(C)]
+struct Request<T> {
+    id: [u64; 4],
+    revision: u64,
+    /// Written by the bootloader, null if it doesn't support the request.
+    response: UnsafeCell<*const T>,
+}
+
+unsafe impl<T> Sync for Request<T> {}
+
+impl<T> Request<T> {
+    const fn new(id: [u64; 2]) -> Self {
+        Request {
+            id: [COMMON_MAGIC[0], COMMON_MAGIC[1], id[0], id[1]],
+            revision: 0,
+            response: UnsafeCell::new(ptr::null()),
+        }
+    }
+
+    fn response(&self) -> Option<&'static T> {
+        //the compiler can't know that the bootloader wrote it
+        unsafe { self.response.get().read_volatile().as_ref() }
+    }
+}
+
+//only the bootloader reads or writes some of the fields
+#[allow(dead_code)]
+#[repr(C)]
+struct EntryPointRequest {
+    id: [u64; 4],
+    revision: u64,
+    response: UnsafeCell<*const u64>,
+    entry: extern "C" fn() -> !,
+}
+
+unsafe impl Sync for EntryPointRequest {}
+
+#[allow(dead_code)]
+#[repr(C)]
+struct FramebufferResponse {
+    revision: u64,
+    framebuffer_count: u64,
+    framebuffers: *const *const Framebuffer,
+}
+
+#[allow(dead_code)]
+#[repr(C)]
+struct Framebuffer {
+    /// In the HHDM.
+    address: u64,
+    width: u64,
+    height: u64,
+    /// In bytes.
+    pitch: u64,
+    bpp: u16,
+    memory_model: u8,
+    red_mask_size: u8,
+    red_mask_shift: u8,
+    green_mask_size: u8,
+    green_mask_shift: u8,
+    blue_mask_size: u8,
+    blue_mask_shift: u8,
+    unused: [u8; 7],
+    edid_size: u64,
+    edid: u64,
+}
+
+#[allow(dead_code)]
+#[repr(C)]
+struct MemmapResponse {
+    revision: u64,
+    entry_count: u64,
+    /// Sorted by base address, without overlaps.
+    entries: *const *const MemmapEntry,
+}
+
+#[repr(C)]
+struct MemmapEntry {
+    base: u64,
+    length: u64,
+    ty: u64,
+}
+
+#[allow(dead_code)]
+#[repr(C)]
+struct HhdmResponse {
+    revision: u64,
+    offset: u64,
+}
+
+#[allow(dead_code)]
+#[repr(C)]
+struct ExecutableAddressResponse {
+    revision: u64,
+    physical_base: u64,
+    virtual_base: u64,
+}
+
+#[allow(dead_code)]
+#[repr(C)]
+struct ExecutableFileResponse {
+    revision: u64,
+    executable_file: *const File,
+}
+
+#[allow(dead_code)]
+#[repr(C)]
+struct ModuleResponse {
+    revision: u64,
+    module_count: u64,
+    modules: *const *const File,
+}
+
+#[allow(dead_code)]
+#[repr(C)]
+struct File {
+    revision: u64,
+    /// In the HHDM, page-aligned.
+    address: u64,
+    size: u64,
+    path: *const u8,
+    /// The command line of the file (NUL-terminated).
+    string: *const u8,
+    media_type: u32,
+    unused: u32,
+    tftp_ip: u32,
+    tftp_port: u32,
+    partition_index: u32,
+    mbr_disk_id: u32,
+    gpt_disk_uuid: [u8; 16],
+    gpt_part_uuid: [u8; 16],
+    part_uuid: [u8; 16],
+}
+
+#[allow(dead_code)]
+#[repr(C)]
+struct RsdpResponse {
+    revision: u64,
+    address: u64,
+}
+
+#[allow(dead_code)]
+#[repr(C)]
+struct SmbiosResponse {
+    revision: u64,
+    entry_32: u64,
+    entry_64: u64,
+}
+
+/// Hands out the frames set aside for the boot structures, from the lowest one.
+struct FrameBump {
+    next: u64,
+    end: u64,
+}
+
+impl FrameBump {
+    fn alloc(&mut self, count: u64) -> Option<u64> {
+        if self.next + count * 0x1000 > self.end {
+            return None;
+        }
+
+        let addr: u64 = self.next;
+        self.next += count * 0x1000;
+        return Some(addr);
+    }
+}
+
+/// Translates the responses of Limine into our own boot parameters. Everything the kernel expects
+/// at the fixed addresses of our bootloader (the memory map, the PMM bitmaps, the command line and
+/// the initrd) is copied or mapped there, in frames taken from the top of a usable region (which is
+/// then reported as loader data). Returns None if a required response is missing.
+pub fn read_params() -> Option<KParams> {
+    let base_revision: [u64; 3] = unsafe { BASE_REVISION_TAG.0.get().read_volatile() };
+    if base_revision[2] != 0 {
+        log::log_error("Limine: the bootloader doesn't support base revision 3.");
+        return None;
+    }
+
+    let hhdm: Option<&HhdmResponse> = HHDM_REQUEST.response();
+    let memmap: Option<&MemmapResponse> = MEMMAP_REQUEST.response();
+    let fb_data: Option<FramebufferData> = read_framebuffer();
+    if hhdm.is_none() || memmap.is_none() || fb_data.is_none() {
+        log::log_error("Limine: no direct map, memory map or framebuffer.");
+        return None;
+    }
+
+    let hhdm_offset: u64 = hhdm.unwrap().offset;
+    let entries: &[*const MemmapEntry] = unsafe {
+        core::slice::from_raw_parts(
+            memmap.unwrap().entries,
+            memmap.unwrap().entry_count as usize,
+        )
+    };
+
+    //reserved regions above the highest RAM page don't need PMM bitmaps
+    let ram_end: u64 = entries
+        .iter()
+        .map(|x| unsafe { &**x })
+        .filter(|x| x.ty != MEMMAP_RESERVED && x.ty != MEMMAP_FRAMEBUFFER)
+        .map(|x| (x.base + x.length).next_multiple_of(0x1000))
+        .max()
+        .unwrap_or(0)
+        .min(PMM_MAX_RAM);
+    let pmm_sections: u64 = ram_end.div_ceil(0x4000_0000);
+    let needed_pages: u64 = MEM_MAP_PAGES + pmm_sections * PMM_SECTION_PAGES + 1 + PAGE_TABLE_PAGES;
+
+    let carved_entry: Option<&MemmapEntry> = entries
+        .iter()
+        .map(|x| unsafe { &**x })
+        .filter(|x| x.ty == MEMMAP_USABLE && x.length >= needed_pages * 0x1000)
+        .filter(|x| x.base + x.length <= ram_end)
+        .last();
+    if carved_entry.is_none() {
+        log::log_error("Limine: not enough memory for the boot structures.");
+        return None;
+    }
+
+    let carved_entry: &MemmapEntry = carved_entry.unwrap();
+    let carved_start: u64 = carved_entry.base + carved_entry.length - needed_pages * 0x1000;
+    let mut frames: FrameBump = FrameBump {
+        next: carved_start,
+        end: carved_entry.base + carved_entry.length,
+    };
+
+    let mem_map_phys_addr: u64 = frames.alloc(MEM_MAP_PAGES).unwrap();
+    let pmm_phys_addr: u64 = frames.alloc(pmm_sections * PMM_SECTION_PAGES).unwrap();
+    let cmdline_phys_addr: u64 = frames.alloc(1).unwrap();
+
+    let mem_map_size: Option<u32> = write_mem_map(
+        entries,
+        ram_end,
+        carved_start,
+        hhdm_offset + mem_map_phys_addr,
+    );
+    if mem_map_size.is_none() {
+        log::log_error("Limine: the memory map is too large.");
+        return None;
+    }
+
+    let cmdline_size: u32 = copy_cmdline(hhdm_offset + cmdline_phys_addr);
+
+    let mut next_frame = || frames.alloc(1);
+    let mut success: bool = vmm::map_early(
+        boot_info::MEM_MAP_VIRTUAL_ADDRESS,
+        mem_map_phys_addr,
+        MEM_MAP_PAGES * 0x1000,
+        true,
+        hhdm_offset,
+        &mut next_frame,
+    ) && vmm::map_early(
+        boot_info::PHYS_BITMAP_MANAGER_ADDRESS,
+        pmm_phys_addr,
+        pmm_sections * PMM_SECTION_PAGES * 0x1000,
+        true,
+        hhdm_offset,
+        &mut next_frame,
+    ) && vmm::map_early(
+        boot_info::CMDLINE_VIRTUAL_ADDRESS,
+        cmdline_phys_addr,
+        0x1000,
+        false,
+        hhdm_offset,
+        &mut next_frame,
+    );
+
+    let initrd: Option<&File> = MODULE_REQUEST
+        .response()
+        .filter(|x| x.module_count > 0)
+        .map(|x| unsafe { &**x.modules });
+    let mut initrd_size: u64 = 0;
+    if let Some(initrd) = initrd {
+        if initrd.size <= boot_info::INITRD_MAX_SIZE {
+            initrd_size = initrd.size;
+            success = success
+                && vmm::map_early(
+                    boot_info::INITRD_VIRTUAL_ADDRESS,
+                    initrd.address - hhdm_offset,
+                    initrd.size,
+                    false,
+                    hhdm_offset,
+                    &mut next_frame,
+                );
+        } else {
+            log::log_warn("Limine: the first module is too large to be the initrd, ignoring it.");
+        }
+    }
+
+    if !success {
+        log::log_error("Limine: error mapping the boot structures.");
+        return None;
+    }
+
+    let kernel_virt_base: u64 = EXECUTABLE_ADDRESS_REQUEST
+        .response()
+        .map_or(boot_info::KERNEL_VIRTUAL_ADDRESS, |x| x.virtual_base);
+
+    return Some(KParams {
+        fb_data: fb_data.unwrap(),
+        memory_map_size: mem_map_size.unwrap(),
+        page_table_num_entries: 0,
+        //Limine doesn't remap the runtime services and the HHDM doesn't cover their memory
+        uefi_rs_phys_addr: 0,
+        uefi_rs_virt_addr: 0,
+        uefi_system_table_virt_addr: 0,
+        cmdline_size,
+        kernel_virt_base,
+        kernel_slide: kernel_virt_base.wrapping_sub(boot_info::KERNEL_VIRTUAL_ADDRESS),
+        initrd_addr: if initrd_size == 0 {
+            0
+        } else {
+            boot_info::INITRD_VIRTUAL_ADDRESS
+        },
+        initrd_size,
+        //the Limine modules are only used for the initrd
+        modules_table_addr: 0,
+        modules_count: 0,
+        acpi_rsdp_phys_addr: RSDP_REQUEST.response().map_or(0, |x| x.address),
+        smbios_phys_addr: SMBIOS_REQUEST.response().map_or(0, |x| {
+            if x.entry_64 != 0 {
+                x.entry_64
+            } else {
+                x.entry_32
+            }
+        }),
+        //the stack is given by Limine, without a guard page
+        kernel_stack_bottom: 0,
+        kernel_stack_top: 0,
+        phys_direct_map_offset: hhdm_offset,
+        phys_direct_map_size: ram_end,
+        //Limine doesn't timestamp its stages
+        boot_tsc_timestamps: [0; boot_info::handoff::boot_stages::COUNT],
+    });
+}
+
+/// Converts the first framebuffer to our format. Only RGB framebuffers are supported.
+fn read_framebuffer() -> Option<FramebufferData> {
+    let response: &FramebufferResponse = FRAMEBUFFER_REQUEST.response()?;
+    if response.framebuffer_count == 0 {
+        return None;
+    }
+
+    let fb: &Framebuffer = unsafe { &**response.framebuffers };
+    let bytes_per_pixel: u64 = (fb.bpp as u64).div_ceil(8);
+    if fb.memory_model != 1 || bytes_per_pixel == 0 {
+        return None;
+    }
+
+    let mask = |size: u8, shift: u8| -> u32 { (((1u64 << size) - 1) << shift) as u32 };
+    return Some(FramebufferData::new(
+        fb.address,
+        fb.width as u32,
+        fb.height as u32,
+        //our pitch is in pixels
+        (fb.pitch / bytes_per_pixel) as u32,
+        fb.bpp as u8,
+        mask(fb.red_mask_size, fb.red_mask_shift),
+        mask(fb.green_mask_size, fb.green_mask_shift),
+        mask(fb.blue_mask_size, fb.blue_mask_shift),
+    ));
+}
+
+/// Writes the memory map in our format at `dest` (in the HHDM) and returns its size in bytes. The
+/// frames from `carved_start` to the end of their usable entry hold the data the kernel gets at its
+/// fixed addresses, so they become boot data.
+fn write_mem_map(
+    entries: &[*const MemmapEntry],
+    ram_end: u64,
+    carved_start: u64,
+    dest: u64,
+) -> Option<u32> {
+    let dest: *mut MemoryMapEntry = dest as *mut MemoryMapEntry;
+    let mut count: usize = 0;
+    let mut push = |mem_type: MemoryType, start: u64, end: u64| -> bool {
+        if end <= start {
+            return true;
+        }
+
+        if count >= MAX_MEM_MAP_ENTRIES {
+            return false;
+        }
+
+        unsafe {
+            dest.add(count).write_unaligned(MemoryMapEntry::new(
+                mem_type,
+                0,
+                start,
+                0,
+                (end - start) / 0x1000,
+            ));
+        }
+        count += 1;
+        return true;
+    };
+
+    for entry in entries.iter().map(|x| unsafe { &**x }) {
+        if entry.base >= ram_end {
+            break;
+        }
+
+        let start: u64 = entry.base & !0xfff;
+        let end: u64 = (entry.base + entry.length)
+            .next_multiple_of(0x1000)
+            .min(ram_end);
+        let success: bool = match entry.ty {
+            MEMMAP_USABLE if (start..end).contains(&carved_start) => {
+                push(MemoryType::Conventional, start, carved_start)
+                    && push(MemoryType::BootData, carved_start, end)
+            }
+            MEMMAP_USABLE => push(MemoryType::Conventional, start, end),
+            MEMMAP_ACPI_RECLAIMABLE => push(MemoryType::AcpiReclaim, start, end),
+            MEMMAP_ACPI_NVS => push(MemoryType::AcpiNonVolatile, start, end),
+            MEMMAP_BAD_MEMORY => push(MemoryType::Unusable, start, end),
+            //the page tables, the stack and the responses are still in use
+            MEMMAP_BOOTLOADER_RECLAIMABLE => push(MemoryType::LoaderData, start, end),
+            MEMMAP_EXECUTABLE_AND_MODULES => push(MemoryType::KernelImage, start, end),
+            MEMMAP_FRAMEBUFFER => push(MemoryType::Framebuffer, start, end),
+            _ => push(MemoryType::Reserved, start, end),
+        };
+        if !success {
+            return None;
+        }
+    }
+
+    return Some((count * size_of::<MemoryMapEntry>()) as u32);
+}
+
+/// Copies the command line of the kernel file at `dest` (in the HHDM) and returns its size.
+fn copy_cmdline(dest: u64) -> u32 {
+    let file: Option<&File> = EXECUTABLE_FILE_REQUEST
+        .response()
+        .and_then(|x| unsafe { x.executable_file.as_ref() });
+    if file.is_none() || file.unwrap().string.is_null() {
+        return 0;
+    }
+
+    let cmdline: &core::ffi::CStr =
+        unsafe { core::ffi::CStr::from_ptr(file.unwrap().string as *const core::ffi::c_char) };
+    let bytes: &[u8] = cmdline.to_bytes();
+    let size: usize = bytes.len().min(boot_info::CMDLINE_MAX_SIZE as usize);
+    unsafe {
+        ptr::copy_nonoverlapping(bytes.as_ptr(), dest as *mut u8, size);
+    }
+
+    return size as u32;
+}
@@ -0,0 +1,568 @@
+use crate::mem_map::MemRegion;
+use boot_info::memory_map::MemoryType;
+
+/// The physical address where the kernel is loaded if the memory there is free.
+const IDEAL_PHYSICAL_ADDRESS: u64 = 0x8000_0000;
+/// The maximum number of PT_LOAD segments the kernel can have.
+pub const MAX_KERNEL_SEGMENTS: usize = 16;
+/// The alignment of the random kernel base (2 MiB, so the kernel could be mapped with huge pages).
+pub const KASLR_ALIGNMENT: u64 = 0x20_0000;
+
+const ELF_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EM_X86_64: u16 = 62;
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+const DT_NULL: i64 = 0;
+const DT_RELA: i64 = 7;
+const DT_RELASZ: i64 = 8;
+const DT_RELAENT: i64 = 9;
+const DT_REL: i64 = 17;
+const R_X86_64_NONE: u32 = 0;
+const R_X86_64_RELATIVE: u32 = 8;
+/// The size of an Elf64_Rela entry.
+const RELA_ENTRY_SIZE: u64 = 24;
+/// The size of an Elf64_Dyn entry.
+const DYN_ENTRY_SIZE: usize = 16;
+
+/// A PT_LOAD segment of the kernel, as it must be mapped.
+#[derive(Debug, Clone, Copy)]
+pub struct Segment {
+    /// The virtual address of the first page of the segment (page-aligned).
+    virt_addr: u64,
+    page_count: u64,
+    writable: bool,
+    executable: bool,
+    /// The virtual address of the data of the segment (p_vaddr).
+    data_addr: u64,
+    file_offset: u64,
+    file_size: u64,
+}
+
+impl Segment {
+    pub fn virt_addr(&self) -> u64 {
+        self.virt_addr
+    }
+
+    pub fn page_count(&self) -> u64 {
+        self.page_count
+    }
+
+    pub fn writable(&self) -> bool {
+        self.writable
+    }
+
+    pub fn executable(&self) -> bool {
+        self.executable
+    }
+
+    /// Returns true if the page at the given virtual address is part of this segment.
+    pub fn contains(&self, virt_addr: u64) -> bool {
+        return virt_addr >= self.virt_addr
+            && virt_addr < self.virt_addr + self.page_count * 0x1000;
+    }
+
+    /// Returns true if the two segments share at least a page.
+    fn overlaps(&self, other: &Segment) -> bool {
+        return self.virt_addr < other.virt_addr + other.page_count * 0x1000
+            && other.virt_addr < self.virt_addr + self.page_count * 0x1000;
+    }
+
+    const fn empty() -> Self {
+        Segment {
+            virt_addr: 0,
+            page_count: 0,
+            writable: false,
+            executable: false,
+            data_addr: 0,
+            file_offset: 0,
+            file_size: 0,
+        }
+    }
+}
+
+/// How a page of the kernel must be mapped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PagePermissions {
+    pub writable: bool,
+    pub executable: bool,
+}
+
+/// Where the segments of the kernel are in its virtual address space. All of them are loaded in a
+/// single physically contiguous block, at the same offsets from each other as in the virtual
+/// address space.
+#[derive(Debug, Clone, Copy)]
+pub struct KernelLayout {
+    /// The virtual address of the first page of the image (the page of the lowest segment).
+    virt_addr: u64,
+    page_count: u64,
+    entry_point: u64,
+    /// How much the kernel was moved from its link address (KASLR), 0 if it wasn't.
+    slide: u64,
+    segments: [Segment; MAX_KERNEL_SEGMENTS],
+    num_segments: usize,
+    /// The file offset and the size of the dynamic segment, only a relocatable kernel (a static
+    /// PIE) has one.
+    dynamic: Option<(u64, u64)>,
+}
+
+impl KernelLayout {
+    /// Reads the layout of an x86_64 ELF64 kernel from its program headers.
+    pub fn parse(file_data: &[u8]) -> Result<Self, &'static str> {
+        let elf: ElfFile = ElfFile::parse(file_data)?;
+        let mut layout: KernelLayout = KernelLayout {
+            virt_addr: u64::MAX,
+            page_count: 0,
+            entry_point: elf.entry_point,
+            slide: 0,
+            segments: [Segment::empty(); MAX_KERNEL_SEGMENTS],
+            num_segments: 0,
+            dynamic: None,
+        };
+        let mut virt_end: u64 = 0;
+
+        for ph in elf.program_headers() {
+            if ph.p_type == PT_DYNAMIC {
+                layout.dynamic = Some((ph.p_offset, ph.p_filesz));
+            }
+
+            if ph.p_type != PT_LOAD || ph.p_memsz == 0 {
+                continue;
+            }
+
+            if ph.p_filesz > ph.p_memsz
+                || ph.p_offset.saturating_add(ph.p_filesz) > file_data.len() as u64
+            {
+                return Err("a segment is outside of the file");
+            }
+
+            let data_end: Option<u64> = ph
+                .p_memsz
+                .checked_add(0xfff)
+                .and_then(|x| ph.p_vaddr.checked_add(x));
+            if data_end.is_none() {
+                return Err("a segment is outside of the address space");
+            }
+
+            if layout.num_segments >= MAX_KERNEL_SEGMENTS {
+                return Err("too many loadable segments");
+            }
+
+            let seg_start: u64 = ph.p_vaddr & !0xfff;
+            let seg_end: u64 = data_end.unwrap() & !0xfff;
+            layout.segments[layout.num_segments] = Segment {
+                virt_addr: seg_start,
+                page_count: (seg_end - seg_start) / 0x1000,
+                writable: ph.p_flags & PF_W != 0,
+                executable: ph.p_flags & PF_X != 0,
+                data_addr: ph.p_vaddr,
+                file_offset: ph.p_offset,
+                file_size: ph.p_filesz,
+            };
+            layout.num_segments += 1;
+
+            layout.virt_addr = layout.virt_addr.min(seg_start);
+            virt_end = virt_end.max(seg_end);
+        }
+
+        if layout.num_segments == 0 {
+            return Err("no loadable segments found");
+        }
+
+        //a page shared by a writable and an executable segment would have to be mapped as both
+        let segments: &[Segment] = layout.segments();
+        for (idx, first) in segments.iter().enumerate() {
+            let has_wx_overlap: bool = segments[idx + 1..].iter().any(|second: &Segment| {
+                first.overlaps(second)
+                    && ((first.writable && second.executable)
+                        || (first.executable && second.writable))
+            });
+            if has_wx_overlap {
+                return Err("a page is shared by a writable and an executable segment");
+            }
+        }
+
+        layout.page_count = (virt_end - layout.virt_addr) / 0x1000;
+        return Ok(layout);
+    }
+
+    pub fn virt_addr(&self) -> u64 {
+        self.virt_addr
+    }
+
+    pub fn page_count(&self) -> u64 {
+        self.page_count
+    }
+
+    /// The virtual address of the entry point.
+    pub fn entry_point(&self) -> u64 {
+        self.entry_point
+    }
+
+    /// The difference between the address where the kernel is mapped and its link address
+    /// (wrapping, so a kernel moved to a lower address has a "negative" slide).
+    pub fn slide(&self) -> u64 {
+        self.slide
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments[..self.num_segments]
+    }
+
+    /// A static PIE always has a dynamic segment (with the relocations).
+    pub fn is_relocatable(&self) -> bool {
+        self.dynamic.is_some()
+    }
+
+    /// Returns how the page at the given virtual address must be mapped, or None if it's in a gap
+    /// between segments. A page shared by two segments gets the permissions of both.
+    pub fn page_permissions(&self, virt_addr: u64) -> Option<PagePermissions> {
+        let mut permissions: Option<PagePermissions> = None;
+        for segment in self.segments().iter().filter(|x| x.contains(virt_addr)) {
+            let current: PagePermissions = permissions.unwrap_or(PagePermissions {
+                writable: false,
+                executable: false,
+            });
+            permissions = Some(PagePermissions {
+                writable: current.writable || segment.writable,
+                executable: current.executable || segment.executable,
+            });
+        }
+
+        return permissions;
+    }
+
+    /// Moves the kernel to the given virtual base. Only changes where the image will be mapped, the
+    /// relocations must already be applied.
+    fn relocate(&mut self, virt_base: u64) {
+        let slide: u64 = virt_base.wrapping_sub(self.virt_addr);
+
+        self.virt_addr = virt_base;
+        self.entry_point = self.entry_point.wrapping_add(slide);
+        self.slide = slide;
+        for segment in self.segments[..self.num_segments].iter_mut() {
+            segment.virt_addr = segment.virt_addr.wrapping_add(slide);
+            segment.data_addr = segment.data_addr.wrapping_add(slide);
+        }
+    }
+
+    /// Applies the relocations of the kernel (already copied in `image`), as if it was loaded
+    /// `slide` bytes after its link address. Only R_X86_64_RELATIVE relocations are supported,
+    /// which is all a static PIE needs.
+    fn apply_relocations(
+        &self,
+        file_data: &[u8],
+        image: &mut [u8],
+        slide: u64,
+    ) -> Result<(), &'static str> {
+        let (dyn_offset, dyn_size): (u64, u64) = match self.dynamic {
+            Some(dynamic) => dynamic,
+            None => return Ok(()),
+        };
+
+        let dyn_start: usize = dyn_offset as usize;
+        let dyn_end: usize = dyn_start.saturating_add(dyn_size as usize);
+        if dyn_end > file_data.len() {
+            return Err("the dynamic segment is outside of the file");
+        }
+
+        let mut rela_addr: u64 = 0;
+        let mut rela_size: u64 = 0;
+        let mut rela_entry_size: u64 = RELA_ENTRY_SIZE;
+        for entry in file_data[dyn_start..dyn_end].chunks_exact(DYN_ENTRY_SIZE) {
+            let tag: i64 = i64::from_le_bytes(entry[..8].try_into().unwrap());
+            let value: u64 = u64::from_le_bytes(entry[8..].try_into().unwrap());
+
+            match tag {
+                DT_NULL => break,
+                DT_RELA => rela_addr = value,
+                DT_RELASZ => rela_size = value,
+                DT_RELAENT => rela_entry_size = value,
+                DT_REL => return Err("REL relocations are not supported"),
+                _ => {}
+            }
+        }
+
+        //no relocations at all, the kernel is fully position-independent
+        if rela_size == 0 {
+            return Ok(());
+        }
+
+        let image_start: u64 = self.virt_addr;
+        let image_end: u64 = self.virt_addr + self.page_count * 0x1000;
+        if rela_entry_size != RELA_ENTRY_SIZE
+            || rela_addr < image_start
+            || rela_addr.saturating_add(rela_size) > image_end
+        {
+            return Err("invalid relocation table");
+        }
+
+        let rela_offset: usize = (rela_addr - image_start) as usize;
+        for i in 0..(rela_size / RELA_ENTRY_SIZE) as usize {
+            let entry_offset: usize = rela_offset + i * RELA_ENTRY_SIZE as usize;
+            let offset: u64 = read_u64(image, entry_offset);
+            let info: u64 = read_u64(image, entry_offset + 8);
+            let addend: u64 = read_u64(image, entry_offset + 16);
+
+            match info as u32 {
+                R_X86_64_NONE => {}
+                R_X86_64_RELATIVE => {
+                    if offset < image_start || offset.saturating_add(8) > image_end {
+                        return Err("a relocation is outside of the kernel");
+                    }
+
+                    let target: usize = (offset - image_start) as usize;
+                    image[target..target + 8]
+                        .copy_from_slice(&addend.wrapping_add(slide).to_le_bytes());
+                }
+                _ => return Err("unsupported relocation type"),
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+/// Where the kernel is loaded and where it will be mapped.
+pub struct KernelPlan {
+    /// The layout at the link address.
+    layout: KernelLayout,
+    /// The physical address of the first page of the image.
+    phys_addr: u64,
+    /// The virtual address where the first page of the image will be mapped.
+    virt_base: u64,
+}
+
+impl KernelPlan {
+    /// The layout of the kernel at its link address.
+    pub fn layout(&self) -> &KernelLayout {
+        &self.layout
+    }
+
+    pub fn phys_addr(&self) -> u64 {
+        self.phys_addr
+    }
+
+    pub fn page_count(&self) -> u64 {
+        self.layout.page_count
+    }
+
+    pub fn virt_base(&self) -> u64 {
+        self.virt_base
+    }
+
+    /// Loads the kernel in `image`, the memory at [`KernelPlan::phys_addr`]: every PT_LOAD segment
+    /// is copied at its offset from the lowest segment (the rest is zeroed, including BSS) and, for
+    /// a relocatable kernel, the relocations are applied. Returns the layout of the kernel where it
+    /// will be mapped.
+    pub fn load(&self, file_data: &[u8], image: &mut [u8]) -> Result<KernelLayout, &'static str> {
+        let image_size: usize = (self.layout.page_count * 0x1000) as usize;
+        if image.len() < image_size {
+            return Err("the memory for the kernel image is too small");
+        }
+
+        let image: &mut [u8] = &mut image[..image_size];
+        image.fill(0);
+
+        for segment in self.layout.segments() {
+            let dest: usize = (segment.data_addr - self.layout.virt_addr) as usize;
+            let src: usize = segment.file_offset as usize;
+            let size: usize = segment.file_size as usize;
+            image[dest..dest + size].copy_from_slice(&file_data[src..src + size]);
+        }
+
+        let mut layout: KernelLayout = self.layout;
+        if layout.is_relocatable() {
+            let slide: u64 = self.virt_base.wrapping_sub(layout.virt_addr);
+            layout.apply_relocations(file_data, image, slide)?;
+            layout.relocate(self.virt_base);
+        }
+
+        return Ok(layout);
+    }
+}
+
+/// Decides where the kernel is loaded and mapped. If the kernel is relocatable and `kaslr_seed` is
+/// given, it is mapped at a random address chosen with it; otherwise it is mapped at its link
+/// address. The regions are the firmware memory map.
+pub fn plan_kernel(
+    file_data: &[u8],
+    regions: impl IntoIterator<Item = MemRegion>,
+    kaslr_seed: Option<u64>,
+) -> Result<KernelPlan, &'static str> {
+    let layout: KernelLayout = KernelLayout::parse(file_data)?;
+    let virt_base: u64 = choose_virt_base(&layout, kaslr_seed);
+    if virt_base < boot_info::KERNEL_VIRTUAL_ADDRESS
+        || virt_base.checked_add(layout.page_count * 0x1000).is_none()
+    {
+        return Err("the kernel is not linked in the higher half");
+    }
+
+    let phys_addr: Option<u64> = find_physical_region(regions, layout.page_count);
+    if phys_addr.is_none() {
+        return Err("no free memory region is large enough for the kernel");
+    }
+
+    return Ok(KernelPlan {
+        layout,
+        phys_addr: phys_addr.unwrap(),
+        virt_base,
+    });
+}
+
+/// Returns the number of places where a relocatable kernel can be put by KASLR, or None if it's too
+/// large for the kernel range.
+pub fn kaslr_slot_count(layout: &KernelLayout) -> Option<u64> {
+    let window: u64 = boot_info::KERNEL_VIRTUAL_END - boot_info::KERNEL_VIRTUAL_ADDRESS;
+    let size: Option<u64> = (layout.page_count * 0x1000).checked_next_multiple_of(KASLR_ALIGNMENT);
+    if size.is_none_or(|x| x > window) {
+        return None;
+    }
+
+    let size: u64 = size.unwrap();
+
+    return Some((window - size) / KASLR_ALIGNMENT + 1);
+}
+
+/// Returns the virtual address where the kernel will be mapped: a random one for a relocatable
+/// kernel when there is a KASLR seed, the link address otherwise.
+pub fn choose_virt_base(layout: &KernelLayout, kaslr_seed: Option<u64>) -> u64 {
+    if !layout.is_relocatable() {
+        return layout.virt_addr;
+    }
+
+    //a relocatable kernel could be linked anywhere (even at 0), keep it in the kernel range
+    let link_base: u64 = if layout.virt_addr < boot_info::KERNEL_VIRTUAL_ADDRESS {
+        boot_info::KERNEL_VIRTUAL_ADDRESS
+    } else {
+        layout.virt_addr
+    };
+
+    let num_slots: Option<u64> = kaslr_slot_count(layout);
+    if kaslr_seed.is_none() || num_slots.is_none() {
+        return link_base;
+    }
+
+    let slot: u64 = kaslr_seed.unwrap() % num_slots.unwrap();
+    return boot_info::KERNEL_VIRTUAL_ADDRESS + slot * KASLR_ALIGNMENT;
+}
+
+/// Returns the physical address where `page_count` pages can be loaded: 0x8000_0000 if the free
+/// memory there is large enough, otherwise the start of the first free region that is. The first
+/// page is never used, so the result is never 0. Returns None if there is no such region.
+pub fn find_physical_region(
+    regions: impl IntoIterator<Item = MemRegion>,
+    page_count: u64,
+) -> Option<u64> {
+    let size: u64 = page_count.checked_mul(0x1000)?;
+    let mut first_fit: Option<u64> = None;
+
+    for region in regions {
+        //only free memory can be allocated at a given address
+        if region.mem_type != MemoryType::Conventional {
+            continue;
+        }
+
+        let start: u64 = region.start.max(0x1000);
+        let end: u64 = region.end();
+        if start >= end {
+            continue;
+        }
+
+        //the kernel should generally be loaded at 0x8000_0000 (IDEAL_PHYSICAL_ADDRESS)
+        if start <= IDEAL_PHYSICAL_ADDRESS
+            && IDEAL_PHYSICAL_ADDRESS
+                .checked_add(size)
+                .is_some_and(|x| x <= end)
+        {
+            return Some(IDEAL_PHYSICAL_ADDRESS);
+        }
+
+        if first_fit.is_none() && end - start >= size {
+            first_fit = Some(start);
+        }
+    }
+
+    return first_fit;
+}
+
+/// The fields of a program header the bootloader needs.
+struct ProgramHeader {
+    p_type: u32,
+    p_flags: u32,
+    p_offset: u64,
+    p_vaddr: u64,
+    p_filesz: u64,
+    p_memsz: u64,
+}
+
+/// A little-endian x86_64 ELF64 file whose program headers are inside the file.
+struct ElfFile<'a> {
+    data: &'a [u8],
+    entry_point: u64,
+    ph_offset: usize,
+    ph_count: usize,
+}
+
+impl<'a> ElfFile<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, &'static str> {
+        if data.len() < ELF_HEADER_SIZE || data[..4] != *b"\x7fELF" {
+            return Err("not an ELF file");
+        }
+
+        if data[4] != ELFCLASS64 || data[5] != ELFDATA2LSB || read_u16(data, 18) != EM_X86_64 {
+            return Err("not an x86_64 ELF64 file");
+        }
+
+        let ph_offset: u64 = read_u64(data, 32);
+        let ph_entry_size: usize = read_u16(data, 54) as usize;
+        let ph_count: usize = read_u16(data, 56) as usize;
+        if ph_count == 0 {
+            return Err("no segments found");
+        }
+
+        if ph_entry_size != PROGRAM_HEADER_SIZE
+            || ph_offset.saturating_add((ph_count * PROGRAM_HEADER_SIZE) as u64) > data.len() as u64
+        {
+            return Err("invalid program headers");
+        }
+
+        return Ok(ElfFile {
+            data,
+            entry_point: read_u64(data, 24),
+            ph_offset: ph_offset as usize,
+            ph_count,
+        });
+    }
+
+    fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + '_ {
+        return (0..self.ph_count).map(|i: usize| {
+            let offset: usize = self.ph_offset + i * PROGRAM_HEADER_SIZE;
+            return ProgramHeader {
+                p_type: read_u32(self.data, offset),
+                p_flags: read_u32(self.data, offset + 4),
+                p_offset: read_u64(self.data, offset + 8),
+                p_vaddr: read_u64(self.data, offset + 16),
+                p_filesz: read_u64(self.data, offset + 32),
+                p_memsz: read_u64(self.data, offset + 40),
+            };
+        });
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    return u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap());
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    return u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    return u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+}
@@ -0,0 +1,14 @@
+//! The decisions the bootloader makes about memory, without calling the firmware: where the
+//! kernel is loaded and mapped, what the kernel memory map looks like, how many bitmaps the
+//! physical memory manager needs and what goes in the direct map. The bootloader reads the
+//! firmware memory map into [`mem_map::MemRegion`]s, asks for a plan and only executes it
+//! (allocates, copies, maps), so all of this can be tested on the host with synthetic memory maps.
+
+#![no_std]
+//the explicit returns are the style of the bootloader this code comes from
+#![allow(clippy::needless_return)]
+
+pub mod kernel;
+pub mod mem_map;
+pub mod paging;
+pub mod pmm;
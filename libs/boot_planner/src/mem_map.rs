@@ -0,0 +1,245 @@
+use boot_info::memory_map::{MemoryMapEntry, MemoryType};
+
+/// How many entries the firmware memory map may gain between the moment the memory for the kernel
+/// map is allocated and ExitBootServices.
+const SPARE_ENTRIES: usize = 10;
+
+/// A range of the firmware memory map, with the type the kernel will see for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemRegion {
+    pub mem_type: MemoryType,
+    /// One of the `memory_attributes::*` constants.
+    pub attributes: u64,
+    /// The physical address of the first page.
+    pub start: u64,
+    pub page_count: u64,
+    /// Where the firmware expects the range to be mapped, 0 if it isn't.
+    pub virt_start: u64,
+}
+
+impl MemRegion {
+    /// The physical address right after the last page (saturated at `u64::MAX` for an invalid
+    /// region).
+    pub fn end(&self) -> u64 {
+        return self
+            .start
+            .saturating_add(self.page_count.saturating_mul(0x1000));
+    }
+}
+
+/// Returns the number of pages needed for the kernel memory map, given the number of entries in the
+/// firmware memory map.
+pub fn map_pages_needed(entry_count: usize) -> usize {
+    return (entry_count + SPARE_ENTRIES) * size_of::<MemoryMapEntry>() / 0x1000 + 1;
+}
+
+/// Writes the memory map given to the kernel in `dest`: sorted, without overlapping ranges, with
+/// the adjacent ranges of the same type merged and with the framebuffer (`(address, size)`, if
+/// any) given its own type. The regions must be sorted by their start address. Returns the number
+/// of entries written, or None if they don't fit in `dest`.
+pub fn normalize_map(
+    regions: impl IntoIterator<Item = MemRegion>,
+    framebuffer: Option<(u64, u64)>,
+    dest: &mut [MemoryMapEntry],
+) -> Option<usize> {
+    let mut writer: MapWriter = MapWriter {
+        dest,
+        len: 0,
+        pending: None,
+        written_end: 0,
+    };
+
+    let mut fb_range: Option<Range> = None;
+    if let Some((fb_start, fb_size)) = framebuffer.filter(|(_, size)| *size != 0) {
+        fb_range = Some(Range {
+            mem_type: MemoryType::Framebuffer,
+            attributes: 0,
+            start: fb_start & !0xfff,
+            end: fb_start.saturating_add(fb_size).saturating_add(0xfff) & !0xfff,
+            virt_start: 0,
+        });
+    }
+
+    for region in regions {
+        //the framebuffer is usually outside the map, or inside an MMIO or reserved entry, so it's
+        //added as if it were in the map, in the right place
+        if fb_range.is_some_and(|range| range.start <= region.start)
+            && !writer.push(fb_range.take().unwrap())
+        {
+            return None;
+        }
+
+        let range: Range = Range {
+            mem_type: region.mem_type,
+            attributes: region.attributes,
+            start: region.start,
+            end: region.end(),
+            virt_start: region.virt_start,
+        };
+        if !writer.push(range) {
+            return None;
+        }
+    }
+
+    if fb_range.is_some() && !writer.push(fb_range.unwrap()) {
+        return None;
+    }
+
+    return writer.finish();
+}
+
+/// When two ranges overlap, the overlapping part gets the type with the higher priority, so that
+/// memory is never given out by mistake.
+fn priority(mem_type: MemoryType) -> u32 {
+    use MemoryType as Ty;
+
+    return match mem_type {
+        Ty::Conventional | Ty::Unaccepted => 0,
+        Ty::BootServicesCode | Ty::BootServicesData => 1,
+        Ty::BootloaderReclaimable | Ty::LoaderCode | Ty::LoaderData => 2,
+        Ty::PersistentMemory => 3,
+        Ty::AcpiReclaim => 4,
+        Ty::KernelImage | Ty::KernelStack | Ty::PageTables | Ty::PmmBitmap | Ty::BootData => 5,
+        Ty::RuntimeServicesCode | Ty::RuntimeServicesData | Ty::AcpiNonVolatile | Ty::PalCode => 6,
+        Ty::Reserved | Ty::Mmio | Ty::MmioPortSpace => 7,
+        Ty::Framebuffer => 8,
+        Ty::Unusable => 9,
+    };
+}
+
+#[derive(Copy, Clone)]
+struct Range {
+    mem_type: MemoryType,
+    attributes: u64,
+    start: u64,
+    end: u64,
+    /// 0 if the range isn't mapped by the firmware.
+    virt_start: u64,
+}
+
+impl Range {
+    /// The part of this range between `start` and `end`.
+    fn slice(&self, start: u64, end: u64) -> Range {
+        let mut virt_start: u64 = 0;
+        if self.virt_start != 0 {
+            virt_start = self.virt_start + (start - self.start);
+        }
+
+        return Range {
+            mem_type: self.mem_type,
+            attributes: self.attributes,
+            start,
+            end,
+            virt_start,
+        };
+    }
+
+    fn can_merge(&self, next: &Range) -> bool {
+        let is_virt_contiguous: bool = if self.virt_start == 0 {
+            next.virt_start == 0
+        } else {
+            next.virt_start == self.virt_start + (self.end - self.start)
+        };
+
+        return self.mem_type == next.mem_type
+            && self.attributes == next.attributes
+            && self.end == next.start
+            && is_virt_contiguous;
+    }
+}
+
+/// Writes ranges sorted by their start address, keeping the last one around so that the next ones
+/// can be merged with it or take a part of it.
+struct MapWriter<'a> {
+    dest: &'a mut [MemoryMapEntry],
+    len: usize,
+    pending: Option<Range>,
+    /// Where the last written range ends, the ones after it can't start earlier.
+    written_end: u64,
+}
+
+impl MapWriter<'_> {
+    fn push(&mut self, range: Range) -> bool {
+        let mut range: Range = range;
+        if range.start < self.written_end {
+            if range.end <= self.written_end {
+                return true;
+            }
+
+            range = range.slice(self.written_end, range.end);
+        }
+
+        if self.pending.is_none() {
+            self.pending = Some(range);
+            return true;
+        }
+
+        let pending: Range = self.pending.unwrap();
+        if range.start < pending.end {
+            if priority(range.mem_type) > priority(pending.mem_type) {
+                //the new range takes its part from the pending one, what's left after it goes back
+                //in the map afterward
+                let mut tail: Option<Range> = None;
+                if pending.end > range.end {
+                    tail = Some(pending.slice(range.end, pending.end));
+                }
+
+                self.pending = None;
+                if pending.start < range.start
+                    && !self.write(pending.slice(pending.start, range.start))
+                {
+                    return false;
+                }
+
+                self.pending = Some(range);
+                if let Some(tail) = tail {
+                    return self.push(tail);
+                }
+
+                return true;
+            }
+
+            if range.end <= pending.end {
+                return true;
+            }
+
+            range = range.slice(pending.end, range.end);
+        }
+
+        if pending.can_merge(&range) {
+            self.pending = Some(pending.slice(pending.start, range.end));
+            return true;
+        }
+
+        self.pending = Some(range);
+        return self.write(pending);
+    }
+
+    fn write(&mut self, range: Range) -> bool {
+        if self.len >= self.dest.len() {
+            return false;
+        }
+
+        self.dest[self.len] = MemoryMapEntry::new(
+            range.mem_type,
+            range.attributes,
+            range.start,
+            range.virt_start,
+            (range.end - range.start) / 0x1000,
+        );
+
+        self.len += 1;
+        self.written_end = range.end;
+        return true;
+    }
+
+    /// Writes the last range and returns the number of entries.
+    fn finish(&mut self) -> Option<usize> {
+        let pending: Option<Range> = self.pending.take();
+        if pending.is_some() && !self.write(pending.unwrap()) {
+            return None;
+        }
+
+        return Some(self.len);
+    }
+}
@@ -0,0 +1,55 @@
+use crate::mem_map::MemRegion;
+use boot_info::memory_map::MemoryType;
+
+pub const SIZE_2_MIB: u64 = 0x20_0000;
+pub const SIZE_1_GIB: u64 = 0x4000_0000;
+
+/// Returns true if a page of `page_size` bytes can map `virt_addr` to `phys_addr`: both are aligned
+/// to it and at least `remaining` bytes are left to map.
+pub fn can_use_page_size(virt_addr: u64, phys_addr: u64, remaining: u64, page_size: u64) -> bool {
+    return virt_addr.is_multiple_of(page_size)
+        && phys_addr.is_multiple_of(page_size)
+        && remaining >= page_size;
+}
+
+/// Returns true if the memory type is RAM, so it belongs in the direct map. MMIO and reserved
+/// regions must not be mapped as normal memory, and the memory that failed the memory test must not
+/// be used at all.
+pub fn is_ram(mem_type: MemoryType) -> bool {
+    return matches!(
+        mem_type,
+        MemoryType::Conventional
+            | MemoryType::LoaderCode
+            | MemoryType::LoaderData
+            | MemoryType::BootloaderReclaimable
+            | MemoryType::BootServicesCode
+            | MemoryType::BootServicesData
+            | MemoryType::RuntimeServicesCode
+            | MemoryType::RuntimeServicesData
+            | MemoryType::AcpiReclaim
+            | MemoryType::AcpiNonVolatile
+            | MemoryType::PersistentMemory
+            | MemoryType::KernelImage
+            | MemoryType::KernelStack
+            | MemoryType::PageTables
+            | MemoryType::PmmBitmap
+            | MemoryType::BootData
+    );
+}
+
+/// Returns true if the region is mapped at [`boot_info::PHYS_DIRECT_MAP_ADDRESS`]: it is RAM and
+/// ends below [`boot_info::PHYS_DIRECT_MAP_MAX_SIZE`].
+pub fn is_in_direct_map(region: &MemRegion) -> bool {
+    return is_ram(region.mem_type) && region.end() <= boot_info::PHYS_DIRECT_MAP_MAX_SIZE;
+}
+
+/// Returns the physical address right above the highest page of the direct map, 0 if nothing is
+/// mapped.
+pub fn direct_map_size(regions: impl IntoIterator<Item = MemRegion>) -> u64 {
+    return regions
+        .into_iter()
+        .filter(is_in_direct_map)
+        .map(|region: MemRegion| region.end())
+        .max()
+        .unwrap_or(0);
+}
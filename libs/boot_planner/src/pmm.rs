@@ -0,0 +1,31 @@
+use crate::mem_map::MemRegion;
+
+/// The number of pages of a section of the bitmap tree of the physical memory manager (36 KiB). A
+/// section manages 1 GiB of physical memory.
+pub const SECTION_PAGES: u64 = 9;
+/// The number of sections that can be given to the kernel, enough for 4 TiB of physical memory.
+/// The addresses of the sections are in an array of 8 pages.
+pub const MAX_SECTIONS: u64 = 4096;
+/// The physical memory managed by a section.
+const SECTION_SIZE: u64 = 0x4000_0000;
+
+/// Returns the number of bitmap sections needed for the memory map: enough to cover everything
+/// up to the end of the highest region or of the framebuffer (`(address, size)`, if any), because
+/// the kernel marks what isn't RAM as used (including reserved areas, MMIO and the framebuffer,
+/// which is often missing from the firmware map), but not more than [`MAX_SECTIONS`].
+pub fn section_count(
+    regions: impl IntoIterator<Item = MemRegion>,
+    framebuffer: Option<(u64, u64)>,
+) -> u64 {
+    let mut highest_end: u64 = regions
+        .into_iter()
+        .map(|region: MemRegion| region.end())
+        .max()
+        .unwrap_or(0);
+    if let Some((fb_start, fb_size)) = framebuffer.filter(|(_, size)| *size != 0) {
+        highest_end = highest_end.max(fb_start.saturating_add(fb_size));
+    }
+
+    //a partly used gigabyte still needs a whole section
+    return highest_end.div_ceil(SECTION_SIZE).min(MAX_SECTIONS);
+}
@@ -0,0 +1,73 @@
+//! Fixtures shared by the integration tests. Every test file uses only some of them.
+#![allow(dead_code)]
+
+use boot_info::memory_map::MemoryType;
+use boot_planner::mem_map::MemRegion;
+
+pub const PT_LOAD: u32 = 1;
+pub const PT_DYNAMIC: u32 = 2;
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+pub struct TestSegment<'a> {
+    pub p_type: u32,
+    pub flags: u32,
+    pub vaddr: u64,
+    pub data: &'a [u8],
+    pub memsz: u64,
+}
+
+/// Builds an x86_64 ELF64 file with the given segments, their data placed after the program
+/// headers.
+pub fn build_elf(entry: u64, segments: &[TestSegment]) -> Vec<u8> {
+    let ph_size: usize = 56;
+    let mut data_offset: usize = 64 + segments.len() * ph_size;
+
+    let mut file: Vec<u8> = vec![0; data_offset];
+    file[..4].copy_from_slice(b"\x7fELF");
+    file[4] = 2; //ELFCLASS64
+    file[5] = 1; //little-endian
+    file[6] = 1; //EV_CURRENT
+    file[16..18].copy_from_slice(&2u16.to_le_bytes()); //ET_EXEC
+    file[18..20].copy_from_slice(&62u16.to_le_bytes()); //EM_X86_64
+    file[24..32].copy_from_slice(&entry.to_le_bytes());
+    file[32..40].copy_from_slice(&64u64.to_le_bytes());
+    file[52..54].copy_from_slice(&64u16.to_le_bytes());
+    file[54..56].copy_from_slice(&(ph_size as u16).to_le_bytes());
+    file[56..58].copy_from_slice(&(segments.len() as u16).to_le_bytes());
+
+    for (i, segment) in segments.iter().enumerate() {
+        let ph: usize = 64 + i * ph_size;
+        file[ph..ph + 4].copy_from_slice(&segment.p_type.to_le_bytes());
+        file[ph + 4..ph + 8].copy_from_slice(&segment.flags.to_le_bytes());
+        file[ph + 8..ph + 16].copy_from_slice(&(data_offset as u64).to_le_bytes());
+        file[ph + 16..ph + 24].copy_from_slice(&segment.vaddr.to_le_bytes());
+        file[ph + 24..ph + 32].copy_from_slice(&segment.vaddr.to_le_bytes());
+        file[ph + 32..ph + 40].copy_from_slice(&(segment.data.len() as u64).to_le_bytes());
+        file[ph + 40..ph + 48].copy_from_slice(&segment.memsz.to_le_bytes());
+        file[ph + 48..ph + 56].copy_from_slice(&0x1000u64.to_le_bytes());
+        data_offset += segment.data.len();
+    }
+
+    for segment in segments {
+        file.extend_from_slice(segment.data);
+    }
+
+    file
+}
+
+/// A free (conventional) memory region.
+pub fn free(start: u64, page_count: u64) -> MemRegion {
+    region(MemoryType::Conventional, start, page_count)
+}
+
+pub fn region(mem_type: MemoryType, start: u64, page_count: u64) -> MemRegion {
+    MemRegion {
+        mem_type,
+        attributes: 0,
+        start,
+        page_count,
+        virt_start: 0,
+    }
+}
@@ -0,0 +1,291 @@
+mod common;
+
+use boot_info::memory_map::MemoryType;
+use boot_planner::kernel::{self, KernelLayout, KernelPlan, PagePermissions};
+use boot_planner::mem_map::MemRegion;
+use common::{PF_R, PF_W, PF_X, PT_DYNAMIC, PT_LOAD, TestSegment, build_elf, free, region};
+
+/// A kernel linked at the start of the kernel range: code, then data with a BSS on the next pages.
+fn static_kernel() -> Vec<u8> {
+    let base: u64 = boot_info::KERNEL_VIRTUAL_ADDRESS;
+    build_elf(
+        base + 0x10,
+        &[
+            TestSegment {
+                p_type: PT_LOAD,
+                flags: PF_R | PF_X,
+                vaddr: base,
+                data: &[0xcc; 0x1800],
+                memsz: 0x1800,
+            },
+            TestSegment {
+                p_type: PT_LOAD,
+                flags: PF_R | PF_W,
+                vaddr: base + 0x2000,
+                data: &[0xab; 0x10],
+                memsz: 0x2000,
+            },
+        ],
+    )
+}
+
+/// A static PIE linked at 0, with a single relocation at address 0 (addend 0x40) stored in its
+/// data segment at 0x100.
+fn relocatable_kernel() -> Vec<u8> {
+    let mut data: Vec<u8> = vec![0; 0x200];
+    data[0x100..0x108].copy_from_slice(&0u64.to_le_bytes());
+    data[0x108..0x110].copy_from_slice(&8u64.to_le_bytes()); //R_X86_64_RELATIVE
+    data[0x110..0x118].copy_from_slice(&0x40u64.to_le_bytes());
+
+    let mut dynamic: Vec<u8> = Vec::new();
+    for (tag, value) in [(7i64, 0x100u64), (8, 24), (9, 24), (0, 0)] {
+        dynamic.extend_from_slice(&tag.to_le_bytes());
+        dynamic.extend_from_slice(&value.to_le_bytes());
+    }
+
+    build_elf(
+        0x20,
+        &[
+            TestSegment {
+                p_type: PT_LOAD,
+                flags: PF_R | PF_W | PF_X,
+                vaddr: 0,
+                data: &data,
+                memsz: 0x1000,
+            },
+            TestSegment {
+                p_type: PT_DYNAMIC,
+                flags: PF_R,
+                vaddr: 0x1000,
+                data: &dynamic,
+                memsz: dynamic.len() as u64,
+            },
+        ],
+    )
+}
+
+#[test]
+fn layout_covers_all_segments() {
+    let layout: KernelLayout = KernelLayout::parse(&static_kernel()).unwrap();
+
+    assert_eq!(layout.virt_addr(), boot_info::KERNEL_VIRTUAL_ADDRESS);
+    assert_eq!(layout.page_count(), 4);
+    assert_eq!(layout.segments().len(), 2);
+    assert!(!layout.is_relocatable());
+}
+
+#[test]
+fn pages_get_the_permissions_of_their_segment() {
+    let layout: KernelLayout = KernelLayout::parse(&static_kernel()).unwrap();
+    let base: u64 = boot_info::KERNEL_VIRTUAL_ADDRESS;
+
+    let code: PagePermissions = layout.page_permissions(base + 0x1000).unwrap();
+    assert!(code.executable && !code.writable);
+
+    let data: PagePermissions = layout.page_permissions(base + 0x2000).unwrap();
+    assert!(!data.executable && data.writable);
+
+    assert!(layout.page_permissions(base + 0x4000).is_none());
+}
+
+#[test]
+fn rejects_pages_shared_by_writable_and_executable_segments() {
+    let base: u64 = boot_info::KERNEL_VIRTUAL_ADDRESS;
+    let file: Vec<u8> = build_elf(
+        base,
+        &[
+            TestSegment {
+                p_type: PT_LOAD,
+                flags: PF_R | PF_X,
+                vaddr: base,
+                data: &[0xcc; 0x1800],
+                memsz: 0x1800,
+            },
+            TestSegment {
+                p_type: PT_LOAD,
+                flags: PF_R | PF_W,
+                vaddr: base + 0x1800,
+                data: &[0xab; 0x10],
+                memsz: 0x10,
+            },
+        ],
+    );
+
+    assert!(KernelLayout::parse(&file).is_err());
+}
+
+#[test]
+fn rejects_files_that_are_not_x86_64_elf64() {
+    assert!(KernelLayout::parse(b"not an elf file at all, just some text").is_err());
+
+    let mut file: Vec<u8> = static_kernel();
+    file[18] = 183; //EM_AARCH64
+    assert!(KernelLayout::parse(&file).is_err());
+}
+
+#[test]
+fn rejects_segments_outside_of_the_file() {
+    let mut file: Vec<u8> = static_kernel();
+    let len: usize = file.len();
+    file.truncate(len - 8);
+
+    assert!(KernelLayout::parse(&file).is_err());
+}
+
+#[test]
+fn rejects_segments_past_the_end_of_the_address_space() {
+    let file: Vec<u8> = build_elf(
+        boot_info::KERNEL_VIRTUAL_ADDRESS,
+        &[TestSegment {
+            p_type: PT_LOAD,
+            flags: PF_R | PF_X,
+            vaddr: boot_info::KERNEL_VIRTUAL_ADDRESS,
+            data: &[0; 0x10],
+            memsz: u64::MAX,
+        }],
+    );
+
+    assert!(KernelLayout::parse(&file).is_err());
+}
+
+#[test]
+fn rejects_kernel_below_the_higher_half() {
+    let file: Vec<u8> = build_elf(
+        0x10_0000,
+        &[TestSegment {
+            p_type: PT_LOAD,
+            flags: PF_R | PF_X,
+            vaddr: 0x10_0000,
+            data: &[0; 0x10],
+            memsz: 0x10,
+        }],
+    );
+
+    assert!(kernel::plan_kernel(&file, [free(0x10_0000, 0x1000)], None).is_err());
+}
+
+#[test]
+fn prefers_the_ideal_physical_address() {
+    let regions: [MemRegion; 2] = [free(0x10_0000, 0x100), free(0x4000_0000, 0x80000)];
+    assert_eq!(kernel::find_physical_region(regions, 16), Some(0x8000_0000));
+}
+
+#[test]
+fn falls_back_to_the_first_free_region_that_fits() {
+    let regions: [MemRegion; 3] = [
+        free(0x10_0000, 4),
+        region(MemoryType::BootServicesData, 0x20_0000, 0x1000),
+        free(0x4000_0000, 0x100),
+    ];
+    assert_eq!(kernel::find_physical_region(regions, 16), Some(0x4000_0000));
+}
+
+#[test]
+fn only_free_memory_is_used_for_the_kernel() {
+    //the ideal address is in used memory, so it can't be allocated there
+    let regions: [MemRegion; 3] = [
+        region(MemoryType::LoaderData, 0x7000_0000, 0x20000),
+        region(MemoryType::BootServicesCode, 0x9000_0000, 0x100),
+        free(0xa000_0000, 0x100),
+    ];
+    assert_eq!(kernel::find_physical_region(regions, 16), Some(0xa000_0000));
+    assert_eq!(kernel::find_physical_region(regions, 0x200), None);
+}
+
+#[test]
+fn never_loads_the_kernel_at_address_0() {
+    assert_eq!(
+        kernel::find_physical_region([free(0, 17)], 16),
+        Some(0x1000)
+    );
+    assert_eq!(kernel::find_physical_region([free(0, 16)], 16), None);
+}
+
+#[test]
+fn huge_kernels_fit_nowhere() {
+    let regions: [MemRegion; 2] = [free(0x10_0000, 0x100), free(0x4000_0000, u64::MAX)];
+    assert_eq!(kernel::find_physical_region(regions, u64::MAX), None);
+    assert_eq!(
+        kernel::find_physical_region(regions, u64::MAX / 0x1000),
+        None
+    );
+}
+
+#[test]
+fn static_kernel_is_loaded_at_its_link_address() {
+    let file: Vec<u8> = static_kernel();
+    let plan: KernelPlan =
+        kernel::plan_kernel(&file, [free(0x7000_0000, 0x20000)], Some(12345)).unwrap();
+    assert_eq!(plan.phys_addr(), 0x8000_0000);
+    assert_eq!(plan.virt_base(), boot_info::KERNEL_VIRTUAL_ADDRESS);
+
+    let mut image: Vec<u8> = vec![0xff; (plan.page_count() * 0x1000) as usize];
+    let layout: KernelLayout = plan.load(&file, &mut image).unwrap();
+
+    assert_eq!(layout.slide(), 0);
+    assert_eq!(
+        layout.entry_point(),
+        boot_info::KERNEL_VIRTUAL_ADDRESS + 0x10
+    );
+    assert!(image[..0x1800].iter().all(|&x| x == 0xcc));
+    assert!(image[0x1800..0x2000].iter().all(|&x| x == 0));
+    assert!(image[0x2000..0x2010].iter().all(|&x| x == 0xab));
+    //the BSS and the end of the last page are zeroed
+    assert!(image[0x2010..].iter().all(|&x| x == 0));
+}
+
+#[test]
+fn relocatable_kernel_without_kaslr_is_moved_into_the_kernel_range() {
+    let file: Vec<u8> = relocatable_kernel();
+    let plan: KernelPlan = kernel::plan_kernel(&file, [free(0x7000_0000, 0x20000)], None).unwrap();
+    assert_eq!(plan.virt_base(), boot_info::KERNEL_VIRTUAL_ADDRESS);
+
+    let mut image: Vec<u8> = vec![0; 0x1000];
+    let layout: KernelLayout = plan.load(&file, &mut image).unwrap();
+
+    assert_eq!(layout.virt_addr(), boot_info::KERNEL_VIRTUAL_ADDRESS);
+    assert_eq!(
+        layout.entry_point(),
+        boot_info::KERNEL_VIRTUAL_ADDRESS + 0x20
+    );
+    let relocated: u64 = u64::from_le_bytes(image[..8].try_into().unwrap());
+    assert_eq!(relocated, boot_info::KERNEL_VIRTUAL_ADDRESS + 0x40);
+}
+
+#[test]
+fn kaslr_moves_a_relocatable_kernel_to_an_aligned_slot() {
+    let file: Vec<u8> = relocatable_kernel();
+    let plan: KernelPlan =
+        kernel::plan_kernel(&file, [free(0x7000_0000, 0x20000)], Some(3)).unwrap();
+    let expected_base: u64 = boot_info::KERNEL_VIRTUAL_ADDRESS + 3 * kernel::KASLR_ALIGNMENT;
+    assert_eq!(plan.virt_base(), expected_base);
+
+    let mut image: Vec<u8> = vec![0; 0x1000];
+    let layout: KernelLayout = plan.load(&file, &mut image).unwrap();
+
+    assert_eq!(layout.slide(), expected_base);
+    assert_eq!(layout.segments()[0].virt_addr(), expected_base);
+    let relocated: u64 = u64::from_le_bytes(image[..8].try_into().unwrap());
+    assert_eq!(relocated, expected_base + 0x40);
+}
+
+#[test]
+fn kaslr_seed_wraps_around_the_slots() {
+    let layout: KernelLayout = KernelLayout::parse(&relocatable_kernel()).unwrap();
+    let num_slots: u64 = kernel::kaslr_slot_count(&layout).unwrap();
+
+    assert_eq!(num_slots, 512);
+    assert_eq!(
+        kernel::choose_virt_base(&layout, Some(num_slots + 1)),
+        boot_info::KERNEL_VIRTUAL_ADDRESS + kernel::KASLR_ALIGNMENT
+    );
+}
+
+#[test]
+fn static_kernel_ignores_the_kaslr_seed() {
+    let layout: KernelLayout = KernelLayout::parse(&static_kernel()).unwrap();
+    assert_eq!(
+        kernel::choose_virt_base(&layout, Some(7)),
+        boot_info::KERNEL_VIRTUAL_ADDRESS
+    );
+}
@@ -0,0 +1,152 @@
+mod common;
+
+use boot_info::memory_map::{MemoryMapEntry, MemoryType};
+use boot_planner::mem_map::{self, MemRegion};
+use common::region;
+
+fn empty_map<const N: usize>() -> [MemoryMapEntry; N] {
+    core::array::from_fn(|_| MemoryMapEntry::new(MemoryType::Reserved, 0, 0, 0, 0))
+}
+
+/// Returns (type, start, page count) for the written entries.
+fn entries(map: &[MemoryMapEntry], len: usize) -> Vec<(MemoryType, u64, u64)> {
+    map[..len]
+        .iter()
+        .map(|x| (x.mem_type(), x.physical_addr(), x.num_pages()))
+        .collect()
+}
+
+#[test]
+fn merges_adjacent_regions_of_the_same_type() {
+    let regions: [MemRegion; 3] = [
+        region(MemoryType::Conventional, 0, 16),
+        region(MemoryType::Conventional, 0x10000, 16),
+        region(MemoryType::AcpiReclaim, 0x20000, 1),
+    ];
+    let mut map: [MemoryMapEntry; 8] = empty_map();
+    let len: usize = mem_map::normalize_map(regions, None, &mut map).unwrap();
+
+    assert_eq!(
+        entries(&map, len),
+        [
+            (MemoryType::Conventional, 0, 32),
+            (MemoryType::AcpiReclaim, 0x20000, 1)
+        ]
+    );
+}
+
+#[test]
+fn overlap_goes_to_the_type_with_the_higher_priority() {
+    //a reserved range in the middle of free memory splits it
+    let regions: [MemRegion; 2] = [
+        region(MemoryType::Conventional, 0, 16),
+        region(MemoryType::Reserved, 0x4000, 2),
+    ];
+    let mut map: [MemoryMapEntry; 8] = empty_map();
+    let len: usize = mem_map::normalize_map(regions, None, &mut map).unwrap();
+
+    assert_eq!(
+        entries(&map, len),
+        [
+            (MemoryType::Conventional, 0, 4),
+            (MemoryType::Reserved, 0x4000, 2),
+            (MemoryType::Conventional, 0x6000, 10)
+        ]
+    );
+}
+
+#[test]
+fn lower_priority_overlap_is_dropped() {
+    let regions: [MemRegion; 2] = [
+        region(MemoryType::KernelImage, 0, 16),
+        region(MemoryType::Conventional, 0x8000, 16),
+    ];
+    let mut map: [MemoryMapEntry; 8] = empty_map();
+    let len: usize = mem_map::normalize_map(regions, None, &mut map).unwrap();
+
+    assert_eq!(
+        entries(&map, len),
+        [
+            (MemoryType::KernelImage, 0, 16),
+            (MemoryType::Conventional, 0x10000, 8)
+        ]
+    );
+}
+
+#[test]
+fn framebuffer_is_inserted_in_order() {
+    let regions: [MemRegion; 2] = [
+        region(MemoryType::Conventional, 0, 16),
+        region(MemoryType::Mmio, 0xfee0_0000, 1),
+    ];
+    let mut map: [MemoryMapEntry; 8] = empty_map();
+    //not page-aligned on purpose, the whole pages are reserved
+    let len: usize =
+        mem_map::normalize_map(regions, Some((0x8000_0800, 0x1000)), &mut map).unwrap();
+
+    assert_eq!(
+        entries(&map, len),
+        [
+            (MemoryType::Conventional, 0, 16),
+            (MemoryType::Framebuffer, 0x8000_0000, 2),
+            (MemoryType::Mmio, 0xfee0_0000, 1)
+        ]
+    );
+}
+
+#[test]
+fn framebuffer_inside_a_reserved_region_takes_its_part() {
+    let regions: [MemRegion; 1] = [region(MemoryType::Reserved, 0x8000_0000, 16)];
+    let mut map: [MemoryMapEntry; 8] = empty_map();
+    let len: usize =
+        mem_map::normalize_map(regions, Some((0x8000_4000, 0x2000)), &mut map).unwrap();
+
+    assert_eq!(
+        entries(&map, len),
+        [
+            (MemoryType::Reserved, 0x8000_0000, 4),
+            (MemoryType::Framebuffer, 0x8000_4000, 2),
+            (MemoryType::Reserved, 0x8000_6000, 10)
+        ]
+    );
+}
+
+#[test]
+fn fails_when_the_map_is_too_small() {
+    let regions: [MemRegion; 3] = [
+        region(MemoryType::Conventional, 0, 1),
+        region(MemoryType::Reserved, 0x1000, 1),
+        region(MemoryType::Conventional, 0x2000, 1),
+    ];
+    let mut map: [MemoryMapEntry; 2] = empty_map();
+
+    assert_eq!(mem_map::normalize_map(regions, None, &mut map), None);
+}
+
+#[test]
+fn runtime_virtual_addresses_are_kept_when_splitting() {
+    let regions: [MemRegion; 2] = [
+        MemRegion {
+            mem_type: MemoryType::RuntimeServicesData,
+            attributes: 0,
+            start: 0x10_0000,
+            page_count: 4,
+            virt_start: 0xffff_eeeb_0000_0000,
+        },
+        region(MemoryType::Unusable, 0x10_1000, 1),
+    ];
+    let mut map: [MemoryMapEntry; 8] = empty_map();
+    let len: usize = mem_map::normalize_map(regions, None, &mut map).unwrap();
+
+    assert_eq!(len, 3);
+    assert_eq!(map[2].physical_addr(), 0x10_2000);
+    assert_eq!(map[2].virtual_addr(), 0xffff_eeeb_0000_2000);
+}
+
+#[test]
+fn map_pages_include_spare_entries() {
+    assert_eq!(mem_map::map_pages_needed(0), 1);
+    //(92 + 10) entries of 40 bytes are just under 4 KiB
+    assert_eq!(mem_map::map_pages_needed(92), 1);
+    assert_eq!(mem_map::map_pages_needed(93), 2);
+}
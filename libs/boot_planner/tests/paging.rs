@@ -0,0 +1,64 @@
+mod common;
+
+use boot_info::memory_map::MemoryType;
+use boot_planner::mem_map::MemRegion;
+use boot_planner::paging::{self, SIZE_1_GIB, SIZE_2_MIB};
+use common::region;
+
+#[test]
+fn huge_pages_need_aligned_addresses_and_enough_memory() {
+    assert!(paging::can_use_page_size(
+        0xffff_8000_0020_0000,
+        0x20_0000,
+        SIZE_2_MIB,
+        SIZE_2_MIB
+    ));
+    //the physical address is only 4 KiB-aligned
+    assert!(!paging::can_use_page_size(
+        0xffff_8000_0020_0000,
+        0x20_1000,
+        SIZE_2_MIB,
+        SIZE_2_MIB
+    ));
+    //not enough left to map
+    assert!(!paging::can_use_page_size(
+        0xffff_8000_4000_0000,
+        0x4000_0000,
+        SIZE_1_GIB - 0x1000,
+        SIZE_1_GIB
+    ));
+}
+
+#[test]
+fn only_ram_is_in_the_direct_map() {
+    assert!(paging::is_ram(MemoryType::Conventional));
+    assert!(paging::is_ram(MemoryType::BootloaderReclaimable));
+    assert!(paging::is_ram(MemoryType::KernelImage));
+    assert!(!paging::is_ram(MemoryType::Mmio));
+    assert!(!paging::is_ram(MemoryType::Reserved));
+    assert!(!paging::is_ram(MemoryType::Unusable));
+}
+
+#[test]
+fn direct_map_ends_at_the_highest_ram_page() {
+    let regions: [MemRegion; 3] = [
+        region(MemoryType::Conventional, 0, 0x8000),
+        region(MemoryType::AcpiReclaim, 0x800_0000, 0x10),
+        region(MemoryType::Mmio, 0xfee0_0000, 1),
+    ];
+    assert_eq!(paging::direct_map_size(regions), 0x801_0000);
+}
+
+#[test]
+fn memory_above_the_direct_map_limit_is_left_out() {
+    let high: MemRegion = region(
+        MemoryType::Conventional,
+        boot_info::PHYS_DIRECT_MAP_MAX_SIZE - 0x1000,
+        2,
+    );
+    assert!(!paging::is_in_direct_map(&high));
+    assert_eq!(
+        paging::direct_map_size([region(MemoryType::Conventional, 0, 16), high]),
+        0x10000
+    );
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use boot_info::memory_map::MemoryType;
+use boot_planner::mem_map::MemRegion;
+use boot_planner::pmm;
+use common::region;
+
+#[test]
+fn less_than_a_gigabyte_still_needs_a_section() {
+    //128 MiB
+    let regions: [MemRegion; 1] = [region(MemoryType::Conventional, 0, 0x8000)];
+    assert_eq!(pmm::section_count(regions, None), 1);
+}
+
+#[test]
+fn partly_used_gigabyte_gets_its_own_section() {
+    //1.5 GiB
+    let regions: [MemRegion; 2] = [
+        region(MemoryType::Conventional, 0, 0x40000),
+        region(MemoryType::Conventional, 0x4000_0000, 0x20000),
+    ];
+    assert_eq!(pmm::section_count(regions, None), 2);
+}
+
+#[test]
+fn sections_cover_up_to_the_highest_region() {
+    let regions: [MemRegion; 2] = [
+        region(MemoryType::Mmio, 0xfec0_0000, 1),
+        region(MemoryType::Conventional, 0, 0x8000),
+    ];
+    assert_eq!(pmm::section_count(regions, None), 4);
+}
+
+#[test]
+fn sections_are_capped() {
+    let regions: [MemRegion; 1] = [region(MemoryType::Conventional, 0x800_0000_0000, 1)];
+    assert_eq!(pmm::section_count(regions, None), pmm::MAX_SECTIONS);
+    assert_eq!(pmm::section_count([], None), 0);
+}
+
+#[test]
+fn framebuffer_above_the_map_is_covered() {
+    //the framebuffer of a resizable BAR is usually far above RAM and missing from the map
+    let regions: [MemRegion; 2] = [
+        region(MemoryType::Conventional, 0, 0x8000),
+        region(MemoryType::Mmio, 0xfec0_0000, 1),
+    ];
+    assert_eq!(
+        pmm::section_count(regions, Some((0x40_0000_0000, 0x80_0000))),
+        257
+    );
+    assert_eq!(pmm::section_count(regions, Some((0x40_0000_0000, 0))), 4);
+}